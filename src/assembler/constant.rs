@@ -0,0 +1,138 @@
+// Operand constants shared by pass 1 and pass 2: decimal numbers, X'..' hex and C'..' characters
+
+// Bytes of a BYTE operand or literal body: C'EOF', X'F1' or a decimal byte such as -1
+pub fn parse_bytes(operand: &str) -> Result<Vec<u8>, String> {
+    if let Some(content) = quoted(operand, 'C') {
+        return unescape(content);
+    }
+    if let Some(content) = quoted(operand, 'X') {
+        return parse_hex(content);
+    }
+    match operand.parse::<i32>() {
+        Ok(value) if (-128..=255).contains(&value) => Ok(vec![value as u8]),
+        Ok(value) => Err(format!("Value {} does not fit in a byte", value)),
+        Err(_) => Err(format!("Invalid constant: {}", operand)),
+    }
+}
+
+// Plain number such as a START address. Digits without a prefix are read in `radix`,
+// X'..', 0x and $ are always hex.
+pub fn parse_number(operand: &str, radix: u32) -> Result<u32, String> {
+    let (digits, radix) = if let Some(content) = quoted(operand, 'X') {
+        (content, 16)
+    } else if let Some(hex) = operand
+        .strip_prefix("0x")
+        .or_else(|| operand.strip_prefix("0X"))
+        .or_else(|| operand.strip_prefix('$'))
+    {
+        (hex, 16)
+    } else {
+        (operand, radix)
+    };
+    u32::from_str_radix(digits, radix)
+        .map_err(|_| format!("Invalid number '{}' (radix {})", operand, radix))
+}
+
+// content between the quotes of `K'...'`, K being the constant kind letter
+fn quoted(operand: &str, kind: char) -> Option<&str> {
+    let mut chars = operand.chars();
+    if !chars.next()?.eq_ignore_ascii_case(&kind) {
+        return None;
+    }
+    chars.as_str().strip_prefix('\'')?.strip_suffix('\'')
+}
+
+// odd-length hex is padded on the left, so X'F' is the single byte 0F
+fn parse_hex(content: &str) -> Result<Vec<u8>, String> {
+    let padded = if content.len() % 2 == 1 {
+        format!("0{}", content)
+    } else {
+        content.to_string()
+    };
+    hex::decode(&padded).map_err(|_| format!("Invalid hex constant: X'{}'", content))
+}
+
+// Follows the quotes of C'..' and X'..' through an operand a character at a time,
+// with the escapes unescape reads: \' keeps the quote open, and '' closes and opens
+// it again, which reads the same.
+#[derive(Debug, Default)]
+pub struct Quotes {
+    inside: bool,
+    escaped: bool,
+}
+
+impl Quotes {
+    // whether `ch` belongs to a quote, the quote marks included
+    pub fn step(&mut self, ch: char) -> bool {
+        if self.escaped {
+            self.escaped = false;
+        } else if self.inside {
+            match ch {
+                '\\' => self.escaped = true,
+                '\'' => self.inside = false,
+                _ => {}
+            }
+        } else if ch == '\'' {
+            self.inside = true;
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+// where the quote `text` starts inside of closes, None when it does not
+pub fn closing_quote(text: &str) -> Option<usize> {
+    let mut quotes = Quotes {
+        inside: true,
+        escaped: false,
+    };
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        quotes.step(ch);
+        if !quotes.inside && chars.peek().is_none_or(|(_, next)| *next != '\'') {
+            return Some(i);
+        }
+    }
+    None
+}
+
+// C'..' escapes: \n \t \r \0 \\ \' \xHH, and '' for a single quote
+fn unescape(content: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = content.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('n') => bytes.push(b'\n'),
+                Some('t') => bytes.push(b'\t'),
+                Some('r') => bytes.push(b'\r'),
+                Some('0') => bytes.push(0),
+                Some('\\') => bytes.push(b'\\'),
+                Some('\'') => bytes.push(b'\''),
+                Some('x') | Some('X') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    let value = u8::from_str_radix(&hex, 16)
+                        .map_err(|_| format!("Invalid escape \\x{} in C'{}'", hex, content))?;
+                    bytes.push(value);
+                }
+                other => {
+                    return Err(format!(
+                        "Unknown escape \\{} in C'{}'",
+                        other.map(String::from).unwrap_or_default(),
+                        content
+                    ));
+                }
+            },
+            '\'' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                bytes.push(b'\'');
+            }
+            _ => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    Ok(bytes)
+}
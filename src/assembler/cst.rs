@@ -0,0 +1,205 @@
+use super::lexer::{SpannedToken, Token, expand_tabs, lex};
+use crate::predefined::common::{AssemblerOptions, Span};
+
+// Lossless syntax tree: the lexer's tokens plus the whitespace, commas and comments
+// between them, so writing every element back out gives the source text unchanged
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CstKind {
+    Whitespace,
+    Comment,
+    Label,
+    Mnemonic,
+    Operand,
+    Comma,
+    Error, // text the lexer could not read as a statement
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstElement {
+    pub kind: CstKind,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstLine {
+    pub line: usize,
+    pub elements: Vec<CstElement>,
+    pub newline: String, // "\n", "\r\n", or empty on a last line without one
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub lines: Vec<CstLine>,
+}
+
+impl CstLine {
+    pub fn find(&self, kind: CstKind) -> Option<&CstElement> {
+        self.elements.iter().find(|e| e.kind == kind)
+    }
+
+    pub fn operands(&self) -> impl Iterator<Item = &CstElement> {
+        self.elements.iter().filter(|e| e.kind == CstKind::Operand)
+    }
+
+    // nothing but blanks and possibly a comment
+    pub fn is_trivia(&self) -> bool {
+        self.elements
+            .iter()
+            .all(|e| matches!(e.kind, CstKind::Whitespace | CstKind::Comment))
+    }
+}
+
+impl std::fmt::Display for SourceFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.lines.iter() {
+            for element in line.elements.iter() {
+                write!(f, "{}", element.text)?;
+            }
+            write!(f, "{}", line.newline)?;
+        }
+        Ok(())
+    }
+}
+
+// The tree is read in the layout `options` gives, elements keep the text as written
+pub fn parse(source: &str, options: &AssemblerOptions) -> SourceFile {
+    let (tokens, _) = lex(source, options);
+    let mut tokens = tokens.iter().peekable();
+    let mut lines: Vec<CstLine> = Vec::new();
+    let mut rest = source;
+    let mut line = 0;
+
+    while !rest.is_empty() {
+        line += 1;
+        let (text, newline, next) = match rest.find('\n') {
+            Some(i) if rest[..i].ends_with('\r') => (&rest[..i - 1], "\r\n", &rest[i + 1..]),
+            Some(i) => (&rest[..i], "\n", &rest[i + 1..]),
+            None => (rest, "", ""),
+        };
+        // the lexer skips lines without a statement, so only take the next one if it is ours
+        let line_tokens: &[SpannedToken] = tokens
+            .next_if(|t| t.first().is_some_and(|t| t.span.line == line))
+            .map_or(&[], |t| t.as_slice());
+        let line_tokens: Vec<SpannedToken> = if options.fixed_columns {
+            line_tokens.iter().map(|t| in_characters(text, t)).collect()
+        } else {
+            line_tokens.to_vec()
+        };
+        lines.push(CstLine {
+            line,
+            elements: line_elements(text, line, &line_tokens),
+            newline: newline.to_string(),
+        });
+        rest = next;
+    }
+    SourceFile { lines }
+}
+
+// in fixed columns the lexer counts columns with tabs expanded, the tree counts characters
+fn in_characters(text: &str, token: &SpannedToken) -> SpannedToken {
+    let indices = text
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| *c != '\t')
+        .map(|(i, _)| i);
+    let columns: Vec<(usize, usize)> = expand_tabs(text)
+        .into_iter()
+        .map(|(column, _)| column)
+        .zip(indices)
+        .collect();
+    let index = |column: usize| {
+        columns
+            .iter()
+            .find(|(c, _)| *c == column)
+            .map_or(column, |(_, i)| *i)
+    };
+    SpannedToken {
+        token: token.token.clone(),
+        span: Span {
+            start: index(token.span.start),
+            end: index(token.span.end - 1) + 1,
+            ..token.span
+        },
+    }
+}
+
+fn line_elements(text: &str, line: usize, tokens: &[SpannedToken]) -> Vec<CstElement> {
+    let chars: Vec<char> = text.chars().collect();
+    let slice = |start: usize, end: usize| chars[start..end].iter().collect::<String>();
+    let mut elements: Vec<CstElement> = Vec::new();
+    let mut column = 0;
+    // text after the last token is a comment only if the statement was understood
+    let statement = tokens
+        .iter()
+        .any(|t| matches!(t.token, Token::Instruction(_) | Token::Directive(_)));
+
+    for token in tokens.iter() {
+        let kind = match token.token {
+            Token::Label(_) => CstKind::Label,
+            Token::Instruction(_) | Token::Directive(_) => CstKind::Mnemonic,
+            Token::Operand1(_) | Token::Operand2(_) => CstKind::Operand,
+        };
+        push_gap(
+            &mut elements,
+            &chars[column..token.span.start],
+            line,
+            column,
+            true,
+        );
+        elements.push(CstElement {
+            kind,
+            text: slice(token.span.start, token.span.end),
+            span: token.span,
+        });
+        column = token.span.end;
+    }
+    push_gap(&mut elements, &chars[column..], line, column, statement);
+    elements
+}
+
+// Text between tokens: runs of blanks and commas, and whatever follows the last
+// comma or blank that is not a token, which is the comment. Without a statement only
+// text starting with `.` is a comment, the rest is an error.
+fn push_gap(
+    elements: &mut Vec<CstElement>,
+    gap: &[char],
+    line: usize,
+    start: usize,
+    statement: bool,
+) {
+    let mut i = 0;
+    while i < gap.len() {
+        let (kind, len) = if gap[i].is_whitespace() {
+            let len = gap[i..].iter().take_while(|c| c.is_whitespace()).count();
+            (CstKind::Whitespace, len)
+        } else if gap[i] == ',' {
+            (CstKind::Comma, 1)
+        } else {
+            // a comment runs to the end of the line, without its trailing blanks
+            let len = gap[i..].len()
+                - gap[i..]
+                    .iter()
+                    .rev()
+                    .take_while(|c| c.is_whitespace())
+                    .count();
+            let kind = if statement || gap[i] == '.' {
+                CstKind::Comment
+            } else {
+                CstKind::Error
+            };
+            (kind, len)
+        };
+        elements.push(CstElement {
+            kind,
+            text: gap[i..i + len].iter().collect(),
+            span: Span {
+                line,
+                start: start + i,
+                end: start + i + len,
+            },
+        });
+        i += len;
+    }
+}
@@ -0,0 +1,52 @@
+use crate::error::{log_error, log_info, log_warning};
+use crate::predefined::common::{DIAGNOSTICS, Diagnostic, Origin, SOURCEMAP, Severity};
+
+// Diagnostics are logged like every other message and also kept in DIAGNOSTICS,
+// so callers can show them next to the source line. Lines are those of the
+// preprocessed source, they are reported at the file and line they came from.
+
+pub fn report_error(line: usize, message: &str) {
+    report_at(source_line(line), Severity::Error, message);
+}
+
+pub fn report_warning(line: usize, message: &str) {
+    report_at(source_line(line), Severity::Warning, message);
+}
+
+// informational, e.g. what relaxation changed
+pub fn report_note(line: usize, message: &str) {
+    report_at(source_line(line), Severity::Note, message);
+}
+
+// for the preprocessor, which knows the origin before there is a source map
+pub fn report_at(origin: Origin, severity: Severity, message: &str) {
+    let entry = match &origin.file {
+        Some(file) => format!("line {} of {}: {}", origin.line, file, message),
+        None => format!("line {}: {}", origin.line, message),
+    };
+    match severity {
+        Severity::Error => log_error(&entry),
+        Severity::Warning => log_warning(&entry),
+        Severity::Note => log_info(&entry),
+    }
+    DIAGNOSTICS.lock().unwrap().push(Diagnostic {
+        line: origin.line,
+        file: origin.file,
+        severity,
+        message: message.to_string(),
+    });
+}
+
+// where a preprocessed line came from, itself if it is not in the source map
+pub fn source_line(line: usize) -> Origin {
+    SOURCEMAP
+        .lock()
+        .unwrap()
+        .get(line.wrapping_sub(1))
+        .cloned()
+        .unwrap_or(Origin { file: None, line })
+}
+
+pub fn clear() {
+    DIAGNOSTICS.lock().unwrap().clear();
+}
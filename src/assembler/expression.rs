@@ -0,0 +1,398 @@
+use super::constant::{Quotes, closing_quote, parse_bytes};
+use crate::error::{log_error, log_info};
+use crate::predefined::common::{SymbolTable, SymbolType};
+
+// Result of evaluating an operand expression.
+// The type says if the value depends on the program's load address (relative)
+// or on another program (external), which is what decides if a modification record is needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub value: i32,
+    pub symbol_type: SymbolType,
+    pub external: Option<String>, // name of the external symbol the value is based on
+}
+
+// Value on the evaluation stack: how many relative terms it holds (+1 for an added label,
+// -1 for a subtracted one) and which external symbol, if any, it is based on
+#[derive(Debug, Clone, Copy)]
+struct Term {
+    value: i32,
+    relative: i32,
+    external: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(i32),
+    Symbol(usize),
+    Location,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg, // unary minus
+    LParen,
+    RParen,
+}
+
+// `*` is the current location counter when it shows up where an operand is expected,
+// otherwise it is multiplication
+fn expects_operand(tokens: &[Token]) -> bool {
+    matches!(
+        tokens.last(),
+        None | Some(Token::Add | Token::Sub | Token::Mul | Token::Div | Token::Neg | Token::LParen)
+    )
+}
+
+fn tokenize_expression(expr: &str, symbol_table: &[SymbolTable]) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\'' if current.eq_ignore_ascii_case("C") || current.eq_ignore_ascii_case("X") => {
+                // Quoted constant such as C'EOF' or X'F1'
+                let rest: String = chars.clone().collect();
+                let end = closing_quote(&rest)
+                    .ok_or_else(|| format!("Unterminated constant in expression: {}", expr))?;
+                let content = &rest[..end];
+                // past the closing quote
+                chars.nth(content.chars().count());
+                tokens.push(Token::Number(constant_value(&current, content)?));
+                current.clear();
+            }
+            '*' if current.is_empty() && expects_operand(&tokens) => {
+                tokens.push(Token::Location);
+            }
+            '-' if current.is_empty() && expects_operand(&tokens) => {
+                tokens.push(Token::Neg);
+            }
+            '+' if current.is_empty() && expects_operand(&tokens) => {
+                // unary plus changes nothing
+            }
+            '+' | '-' | '*' | '/' | '(' | ')' => {
+                if !current.is_empty() {
+                    tokens.push(parse_operand(&current, symbol_table)?);
+                    current.clear();
+                }
+                tokens.push(match ch {
+                    '+' => Token::Add,
+                    '-' => Token::Sub,
+                    '*' => Token::Mul,
+                    '/' => Token::Div,
+                    '(' => Token::LParen,
+                    _ => Token::RParen,
+                });
+            }
+            ' ' | '\t' => {}
+            _ => {
+                current.push(ch);
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(parse_operand(&current, symbol_table)?);
+    }
+
+    Ok(tokens)
+}
+
+fn constant_value(kind: &str, content: &str) -> Result<i32, String> {
+    let bytes = parse_bytes(&format!("{}'{}'", kind, content))?;
+    if bytes.len() > 3 {
+        return Err(format!(
+            "Constant {}'{}' does not fit in a word",
+            kind, content
+        ));
+    }
+    Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as i32))
+}
+
+fn parse_operand(operand: &str, symbol_table: &[SymbolTable]) -> Result<Token, String> {
+    // Try to parse as decimal number
+    if let Ok(num) = operand.parse::<i32>() {
+        return Ok(Token::Number(num));
+    }
+
+    // Try to parse as hexadecimal (0x prefix or $ prefix)
+    if let Some(hex_str) = operand
+        .strip_prefix("0x")
+        .or_else(|| operand.strip_prefix("0X"))
+        && let Ok(num) = i32::from_str_radix(hex_str, 16)
+    {
+        return Ok(Token::Number(num));
+    }
+
+    if let Some(hex_str) = operand.strip_prefix('$')
+        && let Ok(num) = i32::from_str_radix(hex_str, 16)
+    {
+        return Ok(Token::Number(num));
+    }
+
+    if let Some(index) = symbol_table.iter().position(|sym| sym.label == operand) {
+        return Ok(Token::Symbol(index));
+    }
+
+    Err(format!("Unknown symbol or invalid number: {}", operand))
+}
+
+// Evaluate expression using operator precedence (Shunting Yard algorithm)
+fn evaluate_expression(
+    tokens: Vec<Token>,
+    symbol_table: &[SymbolTable],
+    locctr: u32,
+) -> Result<Expression, String> {
+    let mut output: Vec<Term> = Vec::new();
+    let mut operators: Vec<Token> = Vec::new();
+
+    fn precedence(op: &Token) -> i32 {
+        match op {
+            Token::Add | Token::Sub => 1,
+            Token::Mul | Token::Div => 2,
+            Token::Neg => 3,
+            _ => 0,
+        }
+    }
+
+    fn apply_operator(op: Token, output: &mut Vec<Term>) -> Result<(), String> {
+        if op == Token::Neg {
+            let a = output
+                .pop()
+                .ok_or_else(|| "Invalid expression: insufficient operands".to_string())?;
+            if a.external.is_some() {
+                return Err("External reference cannot be negated".to_string());
+            }
+            output.push(Term {
+                value: a.value.wrapping_neg(),
+                relative: -a.relative,
+                external: None,
+            });
+            return Ok(());
+        }
+
+        if output.len() < 2 {
+            return Err("Invalid expression: insufficient operands".to_string());
+        }
+
+        let b = output.pop().unwrap();
+        let a = output.pop().unwrap();
+
+        let result = match op {
+            Token::Add => {
+                if a.external.is_some() && b.external.is_some() {
+                    return Err("Only one external reference is allowed".to_string());
+                }
+                Term {
+                    value: a.value.wrapping_add(b.value),
+                    relative: a.relative + b.relative,
+                    external: a.external.or(b.external),
+                }
+            }
+            Token::Sub => {
+                if b.external.is_some() {
+                    return Err("External reference cannot be subtracted".to_string());
+                }
+                Term {
+                    value: a.value.wrapping_sub(b.value),
+                    relative: a.relative - b.relative,
+                    external: a.external,
+                }
+            }
+            Token::Mul | Token::Div => {
+                if a.relative != 0
+                    || b.relative != 0
+                    || a.external.is_some()
+                    || b.external.is_some()
+                {
+                    return Err(
+                        "Relative or external terms cannot be multiplied or divided".to_string()
+                    );
+                }
+                let value = if op == Token::Mul {
+                    a.value.wrapping_mul(b.value)
+                } else {
+                    if b.value == 0 {
+                        return Err("Division by zero".to_string());
+                    }
+                    // i32::MIN / -1 is the one quotient that does not fit
+                    a.value
+                        .checked_div(b.value)
+                        .ok_or_else(|| "Division overflows".to_string())?
+                };
+                Term {
+                    value,
+                    relative: 0,
+                    external: None,
+                }
+            }
+            _ => return Err("Invalid operator".to_string()),
+        };
+
+        output.push(result);
+        Ok(())
+    }
+
+    for token in tokens {
+        match token {
+            Token::Number(n) => {
+                output.push(Term {
+                    value: n,
+                    relative: 0,
+                    external: None,
+                });
+            }
+            Token::Symbol(idx) => {
+                let sym = &symbol_table[idx];
+                output.push(match sym.symbol_type {
+                    SymbolType::Absolute => Term {
+                        value: sym.address as i32,
+                        relative: 0,
+                        external: None,
+                    },
+                    SymbolType::Relative => Term {
+                        value: sym.address as i32,
+                        relative: 1,
+                        external: None,
+                    },
+                    SymbolType::External => Term {
+                        value: 0,
+                        relative: 0,
+                        external: Some(idx),
+                    },
+                });
+            }
+            Token::Location => {
+                output.push(Term {
+                    value: locctr as i32,
+                    relative: 1,
+                    external: None,
+                });
+            }
+            Token::LParen => {
+                operators.push(Token::LParen);
+            }
+            Token::RParen => {
+                while let Some(op) = operators.last() {
+                    if *op == Token::LParen {
+                        break;
+                    }
+                    let op = operators.pop().unwrap();
+                    apply_operator(op, &mut output)?;
+                }
+
+                if operators.is_empty() || operators.pop() != Some(Token::LParen) {
+                    return Err("Mismatched parentheses".to_string());
+                }
+            }
+            Token::Neg => {
+                // prefix operator, applied once its operand is on the stack
+                operators.push(token);
+            }
+            Token::Add | Token::Sub | Token::Mul | Token::Div => {
+                while let Some(top) = operators.last() {
+                    if *top == Token::LParen || precedence(top) < precedence(&token) {
+                        break;
+                    }
+                    let op = operators.pop().unwrap();
+                    apply_operator(op, &mut output)?;
+                }
+                operators.push(token);
+            }
+        }
+    }
+
+    while let Some(op) = operators.pop() {
+        if op == Token::LParen || op == Token::RParen {
+            return Err("Mismatched parentheses".to_string());
+        }
+        apply_operator(op, &mut output)?;
+    }
+
+    if output.len() != 1 {
+        return Err("Invalid expression".to_string());
+    }
+
+    // relative terms have to pair up (LABEL1-LABEL2) leaving at most one positive one
+    let term = output[0];
+    let symbol_type = match (term.relative, term.external) {
+        (0, Some(_)) => SymbolType::External,
+        (_, Some(_)) => {
+            return Err("External reference cannot be combined with relative terms".to_string());
+        }
+        (0, None) => SymbolType::Absolute,
+        (1, None) => SymbolType::Relative,
+        (n, None) if n > 1 => {
+            return Err("Invalid expression: relative terms cannot be added together".to_string());
+        }
+        _ => {
+            return Err("Invalid expression: negative relative term".to_string());
+        }
+    };
+
+    Ok(Expression {
+        value: term.value,
+        symbol_type,
+        external: term.external.map(|idx| symbol_table[idx].label.clone()),
+    })
+}
+
+// Evaluates an operand expression at `locctr`, which is what `*` stands for
+pub fn evaluate(
+    expr: &str,
+    symbol_table: &[SymbolTable],
+    locctr: u32,
+) -> Result<Expression, String> {
+    let tokens = tokenize_expression(expr, symbol_table)?;
+    evaluate_expression(tokens, symbol_table, locctr)
+}
+
+pub fn expression_evaluate(
+    expr: &str,
+    symbol_table: &[SymbolTable],
+    locctr: u32,
+) -> Result<u32, String> {
+    let result = evaluate(expr, symbol_table, locctr)?.value;
+
+    if result < 0 {
+        log_error(&format!(
+            "Expression '{}' evaluated to negative value: {}",
+            expr, result
+        ));
+        return Err(format!("Negative result: {}", result));
+    }
+
+    log_info(&format!("Expression '{}' = {}", expr, result));
+    Ok(result as u32)
+}
+
+// Names used in `expr` that are not in the symbol table (yet), so callers can tell
+// a forward reference apart from a malformed expression
+pub fn undefined_symbols(expr: &str, symbol_table: &[SymbolTable]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quotes = Quotes::default();
+
+    for ch in expr.chars().chain(std::iter::once(' ')) {
+        // C'..' and X'..' are constants, not names
+        if quotes.step(ch) {
+            current.clear();
+            continue;
+        }
+        match ch {
+            '+' | '-' | '*' | '/' | '(' | ')' | ' ' | '\t' => {
+                if !current.is_empty()
+                    && parse_operand(&current, symbol_table).is_err()
+                    && !names.contains(&current)
+                {
+                    names.push(current.clone());
+                }
+                current.clear();
+            }
+            _ => current.push(ch),
+        }
+    }
+
+    names
+}
@@ -0,0 +1,74 @@
+use super::cst::{CstKind, CstLine, parse};
+use crate::predefined::common::AssemblerOptions;
+
+// `hexe fmt`: lines up labels, mnemonics, operands and comments in fixed columns
+
+// canonical columns, 0-based
+const MNEMONIC_COLUMN: usize = 8;
+const OPERAND_COLUMN: usize = 16;
+const COMMENT_COLUMN: usize = 40;
+
+const REGISTERS: [&str; 9] = ["A", "X", "L", "B", "S", "T", "F", "PC", "SW"];
+
+pub fn format_source(source: &str) -> String {
+    let mut out = String::new();
+    // the formatter reads and writes free-form source
+    for line in parse(source, &AssemblerOptions::default()).lines.iter() {
+        out.push_str(&format_line(line));
+        out.push('\n');
+    }
+    out
+}
+
+fn format_line(line: &CstLine) -> String {
+    // blank lines stay blank, full-line comments start in the first column
+    if line.is_trivia() {
+        return line
+            .find(CstKind::Comment)
+            .map(|c| c.text.clone())
+            .unwrap_or_default();
+    }
+
+    let mnemonic = line.find(CstKind::Mnemonic);
+    let Some(mnemonic) = mnemonic.filter(|_| line.find(CstKind::Error).is_none()) else {
+        // the lexer could not read this statement, leave it for the author to fix
+        let text: String = line.elements.iter().map(|e| e.text.as_str()).collect();
+        return text.trim_end().to_string();
+    };
+
+    let mut out = String::new();
+    if let Some(label) = line.find(CstKind::Label) {
+        out.push_str(&label.text);
+    }
+    pad_to(&mut out, MNEMONIC_COLUMN);
+    out.push_str(&mnemonic.text.to_uppercase());
+
+    // operands are joined without blanks: `BUFFER,X`, `A,S`
+    let operands: Vec<String> = line
+        .operands()
+        .map(|o| {
+            if REGISTERS.contains(&o.text.to_uppercase().as_str()) {
+                o.text.to_uppercase()
+            } else {
+                o.text.clone()
+            }
+        })
+        .collect();
+    if !operands.is_empty() {
+        pad_to(&mut out, OPERAND_COLUMN);
+        out.push_str(&operands.join(","));
+    }
+
+    if let Some(comment) = line.find(CstKind::Comment) {
+        pad_to(&mut out, COMMENT_COLUMN);
+        out.push_str(&comment.text);
+    }
+    out
+}
+
+// pads with blanks up to `column`, or adds one blank when the text already reaches it
+fn pad_to(out: &mut String, column: usize) {
+    let width = out.chars().count();
+    let padding = if width < column { column - width } else { 1 };
+    out.extend(std::iter::repeat_n(' ', padding));
+}
@@ -1,6 +1,10 @@
-use crate::predefined::common::Instruction;
+use super::constant::Quotes;
+use super::diagnostics::report_error;
+use crate::predefined::common::{AssemblerOptions, Instruction, OpCode, Span};
 use crate::predefined::directive;
 use crate::predefined::opcode;
+use std::collections::HashMap;
+
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub enum Token {
@@ -11,84 +15,324 @@ pub enum Token {
     Operand2(String),
 }
 
-#[warn(unused_assignments)]
-pub fn tokenize(buffer: &str) -> Vec<Vec<Token>> {
-    let mut lexed_token: Vec<Vec<Token>> = Vec::new();
-    let token_line = segregate(buffer);
-    for el in token_line.iter() {
-        if el.is_empty() {
-            continue;
+// a token together with where it was written
+#[derive(Clone, Debug)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+// Raw text of a source field. `columns` holds the column of every character, so
+// fields that had blanks removed (`A, X`) still map back to the source.
+#[derive(Clone, Debug, Default)]
+struct Field {
+    text: String,
+    columns: Vec<usize>,
+}
+
+impl Field {
+    fn push(&mut self, ch: char, column: usize) {
+        self.text.push(ch);
+        self.columns.push(column);
+    }
+
+    fn span(&self, line: usize) -> Span {
+        Span {
+            line,
+            start: self.columns.first().copied().unwrap_or(0),
+            end: self.columns.last().map_or(0, |c| c + 1),
         }
-        let labeled_token: Vec<Token> = labeling(el);
-        lexed_token.push(labeled_token);
-        // println!("{:?}", labeled_token);
     }
-    lexed_token
+
+    fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+// Card layout used by fixed-column sources (0-based, end exclusive)
+const LABEL_COLUMNS: (usize, usize) = (0, 8);
+const MNEMONIC_COLUMNS: (usize, usize) = (9, 15);
+const OPERAND_COLUMNS: (usize, usize) = (16, 35);
+const TAB_WIDTH: usize = 8;
+// in fixed columns a tab moves to the next field, like the tab stops on a card punch
+const TAB_STOPS: [usize; 3] = [MNEMONIC_COLUMNS.0, OPERAND_COLUMNS.0, OPERAND_COLUMNS.1];
+
+// a line the lexer could not make sense of
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    pub line: usize,
+    pub message: String,
+}
+
+// every token line is tagged with its source span, lines without a statement are
+// skipped. Errors go to the assembler's diagnostics.
+pub fn tokenize(buffer: &str, options: &AssemblerOptions) -> Vec<Vec<SpannedToken>> {
+    let (tokens, errors) = lex(buffer, options);
+    for error in errors.iter() {
+        report_error(error.line, &error.message);
+    }
+    tokens
 }
 
-fn segregate(buffer: &str) -> Vec<Vec<String>> {
-    let mut token_vec: Vec<String> = Vec::new();
-    let mut token_line: Vec<Vec<String>> = Vec::new();
-    for l in buffer.lines() {
-        let mut new_token: String = String::new();
-        for c in l.chars() {
-            if c == '.' {
-                //breaking the loop of line before comment
-                break;
+// tokenize without touching the shared assembler state, for the formatter and the
+// editor, which read a source without assembling it
+pub fn lex(buffer: &str, options: &AssemblerOptions) -> (Vec<Vec<SpannedToken>>, Vec<LexError>) {
+    let optab = opcode::build_optab();
+    let directives = directive::directives();
+    let mut lexed_token: Vec<Vec<SpannedToken>> = Vec::new();
+    let mut errors: Vec<LexError> = Vec::new();
+    let mut error = |line: usize, message: String| errors.push(LexError { line, message });
+
+    for (index, text) in buffer.lines().enumerate() {
+        let line = index + 1;
+        let chars: Vec<(usize, char)> = if options.fixed_columns {
+            expand_tabs(text)
+        } else {
+            text.chars().enumerate().collect()
+        };
+        // `.` in the first non-blank column makes the whole line a comment
+        if chars
+            .iter()
+            .find(|(_, c)| !c.is_whitespace())
+            .is_none_or(|(_, c)| *c == '.')
+        {
+            continue;
+        }
+
+        let (label, mut fields) = if options.fixed_columns {
+            fixed_fields(&chars)
+        } else {
+            free_fields(&chars, &optab, &directives)
+        };
+        let mnemonic = (!fields.is_empty()).then(|| fields.remove(0));
+
+        let mut tokens: Vec<SpannedToken> = Vec::new();
+        if let Some(label) = label.filter(|l| !l.is_empty()) {
+            tokens.push(SpannedToken {
+                span: label.span(line),
+                token: Token::Label(fold(&label.text, options)),
+            });
+        }
+
+        let Some(mnemonic) = mnemonic.filter(|m| !m.is_empty()) else {
+            error(line, "Missing mnemonic".to_string());
+            if !tokens.is_empty() {
+                lexed_token.push(tokens);
             }
-            if c == ',' {
-                if !new_token.is_empty() {
-                    token_vec.push(new_token.clone());
-                    new_token.clear();
-                }
-                continue;
+            continue;
+        };
+        let name = mnemonic.text.to_uppercase();
+        let takes_operand = match optab.get(name.as_str()) {
+            Some(code) => {
+                tokens.push(SpannedToken {
+                    span: mnemonic.span(line),
+                    token: Token::Instruction(Instruction {
+                        instr: name.clone(),
+                        opcode: code.clone(),
+                    }),
+                });
+                takes_operand(&name, Some(code))
+            }
+            None if directives.contains(&name) => {
+                tokens.push(SpannedToken {
+                    span: mnemonic.span(line),
+                    token: Token::Directive(name.clone()),
+                });
+                takes_operand(&name, None)
+            }
+            None => {
+                error(line, format!("Unknown mnemonic '{}'", mnemonic.text));
+                false
             }
-            if c == ' ' && !new_token.is_empty() {
-                token_vec.push(new_token.clone());
-                new_token.clear();
+        };
+
+        // anything after the operand field is a comment
+        if takes_operand && let Some(operands) = fields.first() {
+            for (i, operand) in split_operands(operands).into_iter().enumerate() {
+                let text = fold(&operand.text, options);
+                tokens.push(SpannedToken {
+                    span: operand.span(line),
+                    token: if i == 0 {
+                        Token::Operand1(text)
+                    } else {
+                        Token::Operand2(text)
+                    },
+                });
             }
-            if c != ' ' {
-                new_token.push(c);
+        }
+        if !tokens.is_empty() {
+            lexed_token.push(tokens);
+        }
+    }
+    (lexed_token, errors)
+}
+
+// Format 1 instructions, RSUB, LTORG, NOBASE, ELSE and ENDIF have no operand
+// field, so whatever follows them is a comment
+fn takes_operand(name: &str, opcode: Option<&OpCode>) -> bool {
+    match opcode {
+        Some(code) => code.format != 1 && name.trim_start_matches('+') != "RSUB",
+        None => !matches!(name, "LTORG" | "NOBASE" | "ELSE" | "ENDIF"),
+    }
+}
+
+// Label, upper-cased mnemonic and raw operand field of one line, without reporting
+// anything, for the preprocessor. None for comment and blank lines.
+pub fn statement(
+    text: &str,
+    options: &AssemblerOptions,
+) -> Option<(Option<String>, String, Option<String>)> {
+    let chars: Vec<(usize, char)> = if options.fixed_columns {
+        expand_tabs(text)
+    } else {
+        text.chars().enumerate().collect()
+    };
+    if chars
+        .iter()
+        .find(|(_, c)| !c.is_whitespace())
+        .is_none_or(|(_, c)| *c == '.')
+    {
+        return None;
+    }
+    let (label, fields) = if options.fixed_columns {
+        fixed_fields(&chars)
+    } else {
+        free_fields(&chars, &opcode::build_optab(), &directive::directives())
+    };
+    let mut fields = fields.into_iter().map(|f| f.text);
+    let mnemonic = fields.next()?.to_uppercase();
+    Some((label.map(|l| l.text), mnemonic, fields.next()))
+}
+
+// Free-form: blanks and tabs separate fields. A field at the start of the line is a
+// label, unless it is the only mnemonic on the line (`RSUB` written in column 1).
+fn free_fields(
+    chars: &[(usize, char)],
+    optab: &HashMap<&'static str, OpCode>,
+    directives: &[String],
+) -> (Option<Field>, Vec<Field>) {
+    let mut fields = split_fields(chars);
+    let starts_in_first_column = chars.first().is_some_and(|(_, c)| !c.is_whitespace());
+    if !starts_in_first_column || fields.is_empty() {
+        return (None, fields);
+    }
+
+    let is_mnemonic = |field: &Field| {
+        let name = field.text.to_uppercase();
+        optab.contains_key(name.as_str()) || directives.contains(&name)
+    };
+    if is_mnemonic(&fields[0]) && !fields.get(1).is_some_and(is_mnemonic) {
+        return (None, fields);
+    }
+    let label = fields.remove(0);
+    (Some(label), fields)
+}
+
+// Splits a line into blank-separated fields. Quotes keep their blanks, blanks next to
+// a comma do not end a field and a field starting with `.` begins the comment.
+fn split_fields(chars: &[(usize, char)]) -> Vec<Field> {
+    let mut fields: Vec<Field> = Vec::new();
+    let mut current = Field::default();
+    let mut quotes = Quotes::default();
+
+    for (i, &(column, ch)) in chars.iter().enumerate() {
+        if quotes.step(ch) {
+            current.push(ch, column);
+            continue;
+        }
+        if ch.is_whitespace() {
+            let next = chars[i..].iter().find(|(_, c)| !c.is_whitespace());
+            let continues = current.text.ends_with(',') || next.is_some_and(|(_, c)| *c == ',');
+            if !current.is_empty() && !continues {
+                fields.push(std::mem::take(&mut current));
             }
+            continue;
         }
-        if new_token.is_empty() {
+        if ch == '.' && current.is_empty() {
+            break;
+        }
+        current.push(ch, column);
+    }
+    if !current.is_empty() {
+        fields.push(current);
+    }
+    fields
+}
+
+// Fixed columns: label, mnemonic and operand each have their own columns,
+// everything past the operand columns is a comment
+fn fixed_fields(chars: &[(usize, char)]) -> (Option<Field>, Vec<Field>) {
+    let columns = |(start, end): (usize, usize)| -> Vec<(usize, char)> {
+        chars
+            .iter()
+            .filter(|(c, _)| (start..end).contains(c))
+            .copied()
+            .collect()
+    };
+    let label = split_fields(&columns(LABEL_COLUMNS)).into_iter().next();
+    let mnemonic = split_fields(&columns(MNEMONIC_COLUMNS)).into_iter().next();
+    let operand = split_fields(&columns(OPERAND_COLUMNS)).into_iter().next();
+    (label, mnemonic.into_iter().chain(operand).collect())
+}
+
+// columns of every character once tabs are expanded to the next tab stop,
+// past the last field stop they are every TAB_WIDTH columns
+pub(super) fn expand_tabs(text: &str) -> Vec<(usize, char)> {
+    let mut column = 0;
+    let mut chars = Vec::new();
+    for ch in text.chars() {
+        if ch == '\t' {
+            column = TAB_STOPS
+                .into_iter()
+                .find(|stop| *stop > column)
+                .unwrap_or((column / TAB_WIDTH + 1) * TAB_WIDTH);
             continue;
         }
-        token_vec.push(new_token.clone());
-        new_token.clear();
-        token_line.push(token_vec.clone());
-        token_vec.clear();
+        chars.push((column, ch));
+        column += 1;
     }
-    token_line
+    chars
 }
 
-//giving label enum(token) to the fetched tokens
-fn labeling(token_line: &[String]) -> Vec<Token> {
-    let opcode = opcode::build_optab();
-    let mut arr: Vec<Token> = Vec::new();
-    let mut check: bool = false;
-    let mut check_for_op1: bool = false;
-    for tokens in token_line.iter() {
-        if !check {
-            if let Some(code) = opcode.get(tokens.as_str()) {
-                let instr = Instruction {
-                    instr: tokens.clone(),
-                    opcode: code.clone(),
-                };
-                arr.push(Token::Instruction(instr));
-                check = true;
-            } else if directive::directives().contains(tokens) {
-                arr.push(Token::Directive(tokens.clone()));
-                check = true;
-            } else {
-                arr.push(Token::Label(tokens.clone()));
+// operands are separated by commas outside quotes and parentheses
+fn split_operands(field: &Field) -> Vec<Field> {
+    let mut operands: Vec<Field> = Vec::new();
+    let mut current = Field::default();
+    let mut quotes = Quotes::default();
+    let mut depth = 0;
+    for (ch, &column) in field.text.chars().zip(field.columns.iter()) {
+        if quotes.step(ch) {
+            current.push(ch, column);
+            continue;
+        }
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(std::mem::take(&mut current));
+                continue;
             }
-        } else if !check_for_op1 {
-            arr.push(Token::Operand1(tokens.clone()));
-            check_for_op1 = true;
-        } else {
-            arr.push(Token::Operand2(tokens.clone()));
+            _ => {}
         }
+        current.push(ch, column);
+    }
+    operands.push(current);
+    operands.into_iter().filter(|o| !o.is_empty()).collect()
+}
+
+// with case folding symbols are upper-cased, quoted text is left as written
+fn fold(text: &str, options: &AssemblerOptions) -> String {
+    if !options.fold_case {
+        return text.to_string();
     }
-    arr
+    let mut quotes = Quotes::default();
+    text.chars()
+        .map(|ch| {
+            if quotes.step(ch) {
+                ch
+            } else {
+                ch.to_ascii_uppercase()
+            }
+        })
+        .collect()
 }
@@ -0,0 +1,402 @@
+use super::cst::{self, CstKind};
+use super::diagnostics::source_line;
+use super::expression::{evaluate, undefined_symbols};
+use super::pass1asm::pass1asm;
+use crate::predefined::common::{
+    AssemblerOptions, Command, LabeledParsedLines, ParsedToken, SymbolTable, SymbolType,
+};
+
+// `hexe lint`: warnings for programs that assemble but probably do not do what was meant.
+// A warning is silenced by a comment on its line: `. nolint` for all of them, or
+// `. nolint(L001, unreachable-code)` for some, by code or by name.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintCode {
+    UnusedLabel,
+    UnreachableCode,
+    WriteToCode,
+    ReturnWithoutSavedL,
+    MissingBase,
+    DataBetweenInstructions,
+    ByteAccessToWord,
+}
+
+impl LintCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            LintCode::UnusedLabel => "L001",
+            LintCode::UnreachableCode => "L002",
+            LintCode::WriteToCode => "L003",
+            LintCode::ReturnWithoutSavedL => "L004",
+            LintCode::MissingBase => "L005",
+            LintCode::DataBetweenInstructions => "L006",
+            LintCode::ByteAccessToWord => "L007",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintCode::UnusedLabel => "unused-label",
+            LintCode::UnreachableCode => "unreachable-code",
+            LintCode::WriteToCode => "write-to-code",
+            LintCode::ReturnWithoutSavedL => "rsub-without-saved-l",
+            LintCode::MissingBase => "missing-base",
+            LintCode::DataBetweenInstructions => "data-between-instructions",
+            LintCode::ByteAccessToWord => "byte-access-to-word",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub line: usize,
+    pub code: LintCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: warning[{}/{}]: {}",
+            self.line,
+            self.code.code(),
+            self.code.name(),
+            self.message
+        )
+    }
+}
+
+const STORES: [&str; 10] = [
+    "STA", "STB", "STCH", "STF", "STI", "STL", "STS", "STSW", "STT", "STX",
+];
+const DATA: [&str; 4] = ["WORD", "BYTE", "RESW", "RESB"];
+
+pub fn lint(source: &str, options: &AssemblerOptions) -> Vec<LintWarning> {
+    // read before pass 1, which starts the diagnostics over, so lexer errors show up once
+    let suppressions = suppressions(source, options);
+    let (lines, _, _, symbol_table) = pass1asm(source, options, &[]);
+    let mut warnings: Vec<LintWarning> = Vec::new();
+
+    unused_labels(&lines, &mut warnings);
+    unreachable_code(&lines, &mut warnings);
+    writes_to_code(&lines, &symbol_table, &mut warnings);
+    returns_without_saved_l(&lines, &mut warnings);
+    missing_base(&lines, &symbol_table, &mut warnings);
+    data_between_instructions(&lines, &mut warnings);
+    byte_access_to_words(&lines, &mut warnings);
+
+    // lines of included files are linted with those files
+    warnings.retain_mut(|w| {
+        let origin = source_line(w.line);
+        w.line = origin.line;
+        origin.file.is_none()
+    });
+    warnings.retain(|w| {
+        !suppressions.iter().any(|(line, codes)| {
+            *line == w.line
+                && (codes.is_empty()
+                    || codes
+                        .iter()
+                        .any(|c| c == w.code.code() || c == w.code.name()))
+        })
+    });
+    warnings.sort_by_key(|w| w.line);
+    warnings
+}
+
+// mnemonic without the format 4 `+`, or the directive name
+fn mnemonic(token: &ParsedToken) -> &str {
+    match &token.command {
+        Command::Instruction(instr) => instr.instr.trim_start_matches('+'),
+        Command::Directive(directive) => directive,
+    }
+}
+
+fn is_instruction(token: &ParsedToken) -> bool {
+    matches!(token.command, Command::Instruction(_))
+}
+
+fn is_data(token: &ParsedToken) -> bool {
+    matches!(&token.command, Command::Directive(d) if DATA.contains(&d.as_str()))
+}
+
+// expression part of a memory operand, without `#`/`@` and never for a literal
+fn operand_expression(operand: &Option<String>) -> Option<&str> {
+    let operand = operand.as_deref()?;
+    if operand.starts_with('=') {
+        return None;
+    }
+    Some(operand.trim_start_matches(['#', '@']))
+}
+
+// statement labeled with the operand, e.g. the BUFFER line for `STCH BUFFER,X`
+fn target_label<'a>(
+    lines: &'a [LabeledParsedLines],
+    operand: &Option<String>,
+) -> Option<&'a LabeledParsedLines> {
+    let name = operand_expression(operand)?;
+    lines
+        .iter()
+        .find(|l| l.parsedtoken.label.as_deref() == Some(name))
+}
+
+fn unused_labels(lines: &[LabeledParsedLines], warnings: &mut Vec<LintWarning>) {
+    let mut used: Vec<String> = Vec::new();
+    for line in lines.iter() {
+        for operand in [&line.parsedtoken.operand1, &line.parsedtoken.operand2] {
+            if let Some(expr) = operand_expression(operand) {
+                // with an empty table every name in the expression counts as undefined
+                used.extend(undefined_symbols(expr, &[]));
+            }
+        }
+    }
+
+    for line in lines.iter() {
+        let token = &line.parsedtoken;
+        if let Some(label) = &token.label
+            && mnemonic(token) != "START"
+            && !used.contains(label)
+        {
+            warnings.push(LintWarning {
+                line: token.line,
+                code: LintCode::UnusedLabel,
+                message: format!("label '{}' is never used", label),
+            });
+        }
+    }
+}
+
+// after J or RSUB only a labeled statement can be reached, one warning per unreachable block
+fn unreachable_code(lines: &[LabeledParsedLines], warnings: &mut Vec<LintWarning>) {
+    let mut after_jump: Option<&str> = None;
+    let mut reported = false;
+    for line in lines.iter() {
+        let token = &line.parsedtoken;
+        if token.label.is_some() {
+            after_jump = None;
+        }
+        if !is_instruction(token) {
+            continue;
+        }
+        if let Some(jump) = after_jump {
+            if !reported {
+                warnings.push(LintWarning {
+                    line: token.line,
+                    code: LintCode::UnreachableCode,
+                    message: format!(
+                        "{} can never run, it follows {} and has no label",
+                        mnemonic(token),
+                        jump
+                    ),
+                });
+                reported = true;
+            }
+            continue;
+        }
+        after_jump = match mnemonic(token) {
+            "J" => Some("J"),
+            "RSUB" => Some("RSUB"),
+            _ => None,
+        };
+        reported = false;
+    }
+}
+
+fn writes_to_code(
+    lines: &[LabeledParsedLines],
+    symbol_table: &[SymbolTable],
+    warnings: &mut Vec<LintWarning>,
+) {
+    let code_addresses: Vec<u32> = lines
+        .iter()
+        .filter(|l| is_instruction(&l.parsedtoken))
+        .map(|l| l.locctr)
+        .collect();
+
+    for line in lines.iter() {
+        let token = &line.parsedtoken;
+        if !is_instruction(token) || !STORES.contains(&mnemonic(token)) {
+            continue;
+        }
+        let Some(operand) = token.operand1.as_deref() else {
+            continue;
+        };
+        // immediate and indirect stores do not write to the named label
+        if operand.starts_with(['#', '@', '=']) {
+            continue;
+        }
+        if let Ok(target) = evaluate(operand, symbol_table, line.locctr)
+            && target.symbol_type == SymbolType::Relative
+            && code_addresses.contains(&(target.value as u32))
+        {
+            warnings.push(LintWarning {
+                line: token.line,
+                code: LintCode::WriteToCode,
+                message: format!(
+                    "{} writes into the instructions at {}",
+                    mnemonic(token),
+                    operand
+                ),
+            });
+        }
+    }
+}
+
+// A subroutine that calls another one loses its return address in L, so it has to
+// reload L (after saving it with STL) before its RSUB
+fn returns_without_saved_l(lines: &[LabeledParsedLines], warnings: &mut Vec<LintWarning>) {
+    let mut subroutines: Vec<&str> = Vec::new();
+    for line in lines.iter() {
+        if mnemonic(&line.parsedtoken) == "JSUB"
+            && let Some(name) = operand_expression(&line.parsedtoken.operand1)
+            && !subroutines.contains(&name)
+        {
+            subroutines.push(name);
+        }
+    }
+
+    for name in subroutines {
+        let Some(start) = lines
+            .iter()
+            .position(|l| l.parsedtoken.label.as_deref() == Some(name))
+        else {
+            continue;
+        };
+        let mut nested_call: Option<usize> = None;
+        // where the routine saved L while it still held the return address
+        let mut saved: Vec<&str> = Vec::new();
+        for line in lines[start..].iter() {
+            let token = &line.parsedtoken;
+            let operand = operand_expression(&token.operand1);
+            match mnemonic(token) {
+                "JSUB" => nested_call = Some(token.line),
+                "STL" if nested_call.is_none() => saved.extend(operand),
+                "LDL" if operand.is_some_and(|o| saved.contains(&o)) => nested_call = None,
+                "RSUB" => {
+                    if let Some(call) = nested_call {
+                        warnings.push(LintWarning {
+                            line: token.line,
+                            code: LintCode::ReturnWithoutSavedL,
+                            message: format!(
+                                "RSUB in {} returns through L, which the JSUB on line {} overwrote; save L with STL and reload it with LDL",
+                                name, call
+                            ),
+                        });
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// format 3 operands out of PC-relative range while no BASE is in effect
+fn missing_base(
+    lines: &[LabeledParsedLines],
+    symbol_table: &[SymbolTable],
+    warnings: &mut Vec<LintWarning>,
+) {
+    let mut base = false;
+    for line in lines.iter() {
+        let token = &line.parsedtoken;
+        match &token.command {
+            Command::Directive(d) if d == "BASE" => base = true,
+            Command::Directive(d) if d == "NOBASE" => base = false,
+            Command::Instruction(instr) if instr.opcode.format == 3 && !base => {
+                let Some(expr) = operand_expression(&token.operand1) else {
+                    continue;
+                };
+                let Ok(target) = evaluate(expr, symbol_table, line.locctr) else {
+                    continue;
+                };
+                let displacement = target.value - (line.locctr + 3) as i32;
+                if target.symbol_type == SymbolType::Relative
+                    && !(-2048..=2047).contains(&displacement)
+                {
+                    warnings.push(LintWarning {
+                        line: token.line,
+                        code: LintCode::MissingBase,
+                        message: format!(
+                            "{} is {} bytes from PC and no BASE is in effect; add LDB and BASE or use +{}",
+                            expr, displacement, instr.instr
+                        ),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// data with instructions on both sides, reported once per block of data
+fn data_between_instructions(lines: &[LabeledParsedLines], warnings: &mut Vec<LintWarning>) {
+    let mut seen_instruction = false;
+    let mut block_start: Option<usize> = None;
+    for line in lines.iter() {
+        let token = &line.parsedtoken;
+        if is_data(token) {
+            if seen_instruction && block_start.is_none() {
+                block_start = Some(token.line);
+            }
+        } else if is_instruction(token) {
+            if let Some(start) = block_start.take() {
+                warnings.push(LintWarning {
+                    line: start,
+                    code: LintCode::DataBetweenInstructions,
+                    message: format!(
+                        "data is declared between instructions, the code continues on line {}",
+                        token.line
+                    ),
+                });
+            }
+            seen_instruction = true;
+        }
+    }
+}
+
+fn byte_access_to_words(lines: &[LabeledParsedLines], warnings: &mut Vec<LintWarning>) {
+    for line in lines.iter() {
+        let token = &line.parsedtoken;
+        let name = mnemonic(token);
+        if !is_instruction(token) || !matches!(name, "LDCH" | "STCH") {
+            continue;
+        }
+        if let Some(target) = target_label(lines, &token.operand1)
+            && matches!(mnemonic(&target.parsedtoken), "WORD" | "RESW")
+        {
+            warnings.push(LintWarning {
+                line: token.line,
+                code: LintCode::ByteAccessToWord,
+                message: format!(
+                    "{} accesses one byte of {}, which is declared with {}",
+                    name,
+                    target.parsedtoken.label.as_deref().unwrap_or_default(),
+                    mnemonic(&target.parsedtoken)
+                ),
+            });
+        }
+    }
+}
+
+// lines with a `nolint` comment and the codes or names it lists, empty for all
+fn suppressions(source: &str, options: &AssemblerOptions) -> Vec<(usize, Vec<String>)> {
+    let mut result = Vec::new();
+    for line in cst::parse(source, options).lines.iter() {
+        let Some(comment) = line.find(CstKind::Comment) else {
+            continue;
+        };
+        let Some(index) = comment.text.find("nolint") else {
+            continue;
+        };
+        let rest = &comment.text[index + "nolint".len()..];
+        let codes: Vec<String> = rest
+            .strip_prefix('(')
+            .and_then(|r| r.split_once(')'))
+            .map(|(list, _)| list.split(',').map(|c| c.trim().to_string()).collect())
+            .unwrap_or_default();
+        result.push((line.line, codes));
+    }
+    result
+}
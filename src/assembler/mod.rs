@@ -1,4 +1,11 @@
+pub mod constant;
+pub mod cst;
+pub mod diagnostics;
+pub mod expression;
+pub mod format;
 pub mod lexer;
+pub mod lint;
 pub mod parser;
 pub mod pass1asm;
 pub mod pass2asm;
+pub mod preprocess;
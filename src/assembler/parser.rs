@@ -1,18 +1,19 @@
 use super::lexer;
-use crate::predefined::common::{Command, Instruction, ParsedToken};
+use crate::predefined::common::{AssemblerOptions, Command, Instruction, ParsedToken};
 
 #[warn(unused_mut)]
-pub fn parser(buffer: &str) -> Vec<ParsedToken> {
-    let lexed_token = lexer::tokenize(buffer);
+pub fn parser(buffer: &str, options: &AssemblerOptions) -> Vec<ParsedToken> {
+    let lexed_token = lexer::tokenize(buffer, options);
     let mut parsed_lines: Vec<ParsedToken> = Vec::new();
     for el in lexed_token.iter() {
+        let line = el.first().map_or(0, |t| t.span.line);
         let mut dir: String = String::new();
         let mut instr = Instruction::default();
         let mut lab: String = String::new();
         let mut opr1: String = String::new();
         let mut opr2: String = String::new();
         for token in el.iter() {
-            match token {
+            match &token.token {
                 lexer::Token::Directive(directive) => {
                     dir = directive.clone();
                 }
@@ -36,6 +37,7 @@ pub fn parser(buffer: &str) -> Vec<ParsedToken> {
             Command::Instruction(instr)
         };
         let parsed_token = ParsedToken {
+            line,
             label: if lab.is_empty() { None } else { Some(lab) },
             command,
             operand1: if opr1.is_empty() { None } else { Some(opr1) },
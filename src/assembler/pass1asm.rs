@@ -1,248 +1,254 @@
+use super::constant::{parse_bytes, parse_number};
+use super::diagnostics::{clear, report_error, report_warning};
+use super::expression::{evaluate, expression_evaluate, undefined_symbols};
 use super::parser::parser;
-use crate::error::{log_error, log_info};
+use super::preprocess::preprocess;
+use crate::error::log_info;
 use crate::predefined::common::{
-    Command, LITERALTABLE, LabeledParsedLines, LiteralTable, SYMBOLTABLE, SymbolTable,
+    AssemblerOptions, Command, LITERALTABLE, LabeledParsedLines, LiteralTable, SOURCEMAP,
+    SYMBOLTABLE, SymbolTable, SymbolType,
 };
-
-fn parse_literal(literal: &str) -> Option<(String, u32)> {
-    if let Some(stripped) = literal.strip_prefix('=') {
-        if let Some(char_lit) = stripped.strip_prefix("C'") {
-            if let Some(content) = char_lit.strip_suffix('\'') {
-                let hex_value: String = content.bytes().map(|b| format!("{:02X}", b)).collect();
-                let length = content.len() as u32;
-                return Some((hex_value, length));
-            }
-        } else if let Some(hex_lit) = stripped.strip_prefix("X'")
-            && let Some(content) = hex_lit.strip_suffix('\'')
-        {
-            let length = (content.len() / 2) as u32;
-            return Some((content.to_uppercase(), length));
-        }
+use crate::predefined::opcode::build_optab;
+
+// Pool value of a literal as hex, and whether it holds an address that needs relocating.
+// =C'..' and =X'..' are byte strings, =W'5', =5 and =* (the current location) are words.
+fn parse_literal(literal: &str, locctr: u32) -> Result<(String, bool), String> {
+    let body = literal
+        .strip_prefix('=')
+        .ok_or_else(|| format!("Invalid literal: {}", literal))?;
+    if body == "*" {
+        return Ok((format!("{:06X}", locctr & 0xFFFFFF), true));
     }
-    None
+    let word = body
+        .strip_prefix("W'")
+        .or_else(|| body.strip_prefix("w'"))
+        .and_then(|w| w.strip_suffix('\''))
+        .or_else(|| body.parse::<i32>().is_ok().then_some(body));
+    if let Some(word) = word {
+        let value = word
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid word literal: {}", literal))?;
+        return Ok((format!("{:06X}", value & 0xFFFFFF), false));
+    }
+    let bytes = parse_bytes(body)?;
+    if bytes.is_empty() {
+        return Err(format!("Empty literal: {}", literal));
+    }
+    Ok((hex::encode_upper(&bytes), false))
 }
 
-fn is_literal(operand: &str) -> bool {
+pub fn is_literal(operand: &str) -> bool {
     operand.starts_with('=')
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum Token {
-    Number(i32),
-    Symbol(usize),
-    Add,
-    Sub,
-    Mul,
-    Div,
-    LParen,
-    RParen,
+// Literals with the same value share a pool entry until the pool is placed,
+// so =C'A' and =X'41' take one 1-byte slot, and =65 and =X'000041' one 3-byte slot
+fn add_literal(
+    literal_table: &mut Vec<LiteralTable>,
+    literal: &str,
+    value: String,
+    relative: bool,
+    locctr: u32,
+) {
+    if let Some(entry) = literal_table
+        .iter_mut()
+        .find(|lit| lit.address.is_none() && lit.value == value && lit.relative == relative)
+    {
+        entry.references.push(locctr);
+        return;
+    }
+    let length = (value.len() / 2) as u32;
+    log_info(&format!(
+        "Found literal: {} (length: {} bytes)",
+        literal, length
+    ));
+    literal_table.push(LiteralTable {
+        literal: literal.to_string(),
+        value,
+        length,
+        address: None,
+        relative,
+        references: vec![locctr],
+    });
 }
 
-fn tokenize_expression(expr: &str, symbol_table: &[SymbolTable]) -> Result<Vec<Token>, String> {
-    let mut tokens = Vec::new();
-    let mut current = String::new();
+// EQU whose expression uses symbols defined further down, resolved after pass 1
+struct PendingEqu {
+    label: String,
+    expr: String,
+    locctr: u32, // value of `*` on the EQU line
+    line: usize,
+}
 
-    for ch in expr.chars() {
-        match ch {
-            '+' => {
-                if !current.is_empty() {
-                    tokens.push(parse_operand(&current, symbol_table)?);
-                    current.clear();
-                }
-                tokens.push(Token::Add);
+// Keeps evaluating pending EQUs until a round defines nothing new, then reports the rest
+fn resolve_pending_equs(mut pending: Vec<PendingEqu>, symbol_table: &mut Vec<SymbolTable>) {
+    loop {
+        let before = pending.len();
+        pending.retain(|equ| match evaluate(&equ.expr, symbol_table, equ.locctr) {
+            Ok(val) if val.value >= 0 => {
+                symbol_table.push(SymbolTable {
+                    label: equ.label.clone(),
+                    address: val.value as u32,
+                    symbol_type: val.symbol_type,
+                });
+                false
             }
-            '-' => {
-                if !current.is_empty() {
-                    tokens.push(parse_operand(&current, symbol_table)?);
-                    current.clear();
-                }
-                tokens.push(Token::Sub);
+            Ok(val) => {
+                report_error(
+                    equ.line,
+                    &format!(
+                        "EQU expression '{}' evaluated to negative value: {}",
+                        equ.expr, val.value
+                    ),
+                );
+                false
             }
-            '*' => {
-                if !current.is_empty() {
-                    tokens.push(parse_operand(&current, symbol_table)?);
-                    current.clear();
-                }
-                tokens.push(Token::Mul);
-            }
-            '/' => {
-                if !current.is_empty() {
-                    tokens.push(parse_operand(&current, symbol_table)?);
-                    current.clear();
-                }
-                tokens.push(Token::Div);
-            }
-            '(' => {
-                if !current.is_empty() {
-                    tokens.push(parse_operand(&current, symbol_table)?);
-                    current.clear();
-                }
-                tokens.push(Token::LParen);
-            }
-            ')' => {
-                if !current.is_empty() {
-                    tokens.push(parse_operand(&current, symbol_table)?);
-                    current.clear();
-                }
-                tokens.push(Token::RParen);
+            // still waiting on another symbol
+            Err(_) if !undefined_symbols(&equ.expr, symbol_table).is_empty() => true,
+            Err(e) => {
+                report_error(
+                    equ.line,
+                    &format!("Failed to evaluate EQU expression '{}': {}", equ.expr, e),
+                );
+                false
             }
-            ' ' | '\t' => {}
-            _ => {
-                current.push(ch);
-            }
-        }
-    }
-
-    if !current.is_empty() {
-        tokens.push(parse_operand(&current, symbol_table)?);
-    }
-
-    Ok(tokens)
-}
-
-fn parse_operand(operand: &str, symbol_table: &[SymbolTable]) -> Result<Token, String> {
-    // Try to parse as decimal number
-    if let Ok(num) = operand.parse::<i32>() {
-        return Ok(Token::Number(num));
-    }
-
-    // Try to parse as hexadecimal (0x prefix or $ prefix)
-    if let Some(hex_str) = operand
-        .strip_prefix("0x")
-        .or_else(|| operand.strip_prefix("0X"))
-        && let Ok(num) = i32::from_str_radix(hex_str, 16)
-    {
-        return Ok(Token::Number(num));
-    }
-
-    if let Some(hex_str) = operand.strip_prefix('$')
-        && let Ok(num) = i32::from_str_radix(hex_str, 16)
-    {
-        return Ok(Token::Number(num));
-    }
-
-    if let Some(index) = symbol_table.iter().position(|sym| sym.label == operand) {
-        return Ok(Token::Symbol(index));
-    }
-
-    Err(format!("Unknown symbol or invalid number: {}", operand))
-}
-
-// Evaluate expression using operator precedence (Shunting Yard algorithm)
-fn evaluate_expression(tokens: Vec<Token>, symbol_table: &[SymbolTable]) -> Result<i32, String> {
-    let mut output: Vec<i32> = Vec::new();
-    let mut operators: Vec<Token> = Vec::new();
-
-    fn precedence(op: &Token) -> i32 {
-        match op {
-            Token::Add | Token::Sub => 1,
-            Token::Mul | Token::Div => 2,
-            _ => 0,
+        });
+        if pending.is_empty() || pending.len() == before {
+            break;
         }
     }
 
-    fn apply_operator(op: Token, output: &mut Vec<i32>) -> Result<(), String> {
-        if output.len() < 2 {
-            return Err("Invalid expression: insufficient operands".to_string());
-        }
-
-        let b = output.pop().unwrap();
-        let a = output.pop().unwrap();
-
-        let result = match op {
-            Token::Add => a + b,
-            Token::Sub => a - b,
-            Token::Mul => a * b,
-            Token::Div => {
-                if b == 0 {
-                    return Err("Division by zero".to_string());
+    for equ in pending.iter() {
+        let message = match equ_cycle(equ, &pending, symbol_table) {
+            Some(cycle) => format!("Circular EQU definition: {}", cycle.join(" -> ")),
+            None => {
+                let missing = undefined_symbols(&equ.expr, symbol_table);
+                match missing
+                    .iter()
+                    .find(|name| !pending.iter().any(|p| p.label == **name))
+                {
+                    Some(name) => {
+                        format!("EQU '{}' refers to undefined symbol '{}'", equ.label, name)
+                    }
+                    None => format!(
+                        "EQU '{}' depends on a circular definition through '{}'",
+                        equ.label,
+                        missing.join("', '")
+                    ),
                 }
-                a / b
             }
-            _ => return Err("Invalid operator".to_string()),
         };
-
-        output.push(result);
-        Ok(())
+        report_error(equ.line, &message);
     }
+}
 
-    for token in tokens {
-        match token {
-            Token::Number(n) => {
-                output.push(n);
-            }
-            Token::Symbol(idx) => {
-                let value = symbol_table[idx].address as i32;
-                output.push(value);
-            }
-            Token::LParen => {
-                operators.push(Token::LParen);
-            }
-            Token::RParen => {
-                while let Some(op) = operators.last() {
-                    if *op == Token::LParen {
-                        break;
-                    }
-                    let op = operators.pop().unwrap();
-                    apply_operator(op, &mut output)?;
-                }
-
-                if operators.is_empty() || operators.pop() != Some(Token::LParen) {
-                    return Err("Mismatched parentheses".to_string());
-                }
+// Follows the unresolved names of `start` through the other pending EQUs and returns
+// the chain of labels if it leads back to `start`
+fn equ_cycle(
+    start: &PendingEqu,
+    pending: &[PendingEqu],
+    symbol_table: &[SymbolTable],
+) -> Option<Vec<String>> {
+    let mut stack: Vec<Vec<String>> = vec![vec![start.label.clone()]];
+    let mut visited: Vec<String> = Vec::new();
+
+    while let Some(path) = stack.pop() {
+        let current = path.last().unwrap();
+        let Some(equ) = pending.iter().find(|p| p.label == *current) else {
+            continue;
+        };
+        for name in undefined_symbols(&equ.expr, symbol_table) {
+            if name == start.label {
+                let mut cycle = path.clone();
+                cycle.push(name);
+                return Some(cycle);
             }
-            Token::Add | Token::Sub | Token::Mul | Token::Div => {
-                while let Some(top) = operators.last() {
-                    if *top == Token::LParen || precedence(top) < precedence(&token) {
-                        break;
-                    }
-                    let op = operators.pop().unwrap();
-                    apply_operator(op, &mut output)?;
-                }
-                operators.push(token);
+            if !visited.contains(&name) {
+                visited.push(name.clone());
+                let mut next = path.clone();
+                next.push(name);
+                stack.push(next);
             }
         }
     }
+    None
+}
 
-    while let Some(op) = operators.pop() {
-        if op == Token::LParen || op == Token::RParen {
-            return Err("Mismatched parentheses".to_string());
+// RESW/RESB sizes may be expressions over symbols defined earlier, e.g. `RESB 2*SIZE`
+fn reservation_count(
+    operand: &Option<String>,
+    symbol_table: &[SymbolTable],
+    locctr: u32,
+    line: usize,
+) -> Option<u32> {
+    let expr = operand.as_ref()?;
+    match expression_evaluate(expr, symbol_table, locctr) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            report_error(
+                line,
+                &format!("Failed to evaluate reservation size '{}': {}", expr, e),
+            );
+            None
         }
-        apply_operator(op, &mut output)?;
-    }
-
-    if output.len() != 1 {
-        return Err("Invalid expression".to_string());
     }
-
-    Ok(output[0])
 }
 
-pub fn expression_evaluate(expr: &str, symbol_table: &[SymbolTable]) -> Result<u32, String> {
-    let tokens = tokenize_expression(expr, symbol_table)?;
-    let result = evaluate_expression(tokens, symbol_table)?;
-
-    if result < 0 {
-        log_error(&format!(
-            "Expression '{}' evaluated to negative value: {}",
-            expr, result
-        ));
-        return Err(format!("Negative result: {}", result));
+// the location counter past `count` units of `size` bytes, left where it is when
+// that does not fit
+fn reserve(locctr: u32, count: u32, size: u32, line: usize) -> u32 {
+    match count
+        .checked_mul(size)
+        .and_then(|length| locctr.checked_add(length))
+    {
+        Some(end) => end,
+        None => {
+            report_error(
+                line,
+                &format!(
+                    "Reserving {} times {} bytes overflows the address",
+                    count, size
+                ),
+            );
+            locctr
+        }
     }
-
-    log_info(&format!("Expression '{}' = {}", expr, result));
-    Ok(result as u32)
 }
 
-pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolTable>) {
-    let parsed_lines = parser(buffer);
+// `promoted` lists source lines whose format 3 instruction is assembled as format 4
+pub fn pass1asm(
+    buffer: &str,
+    options: &AssemblerOptions,
+    promoted: &[usize],
+) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolTable>) {
+    // the lexer reports into the same diagnostics as the passes
+    clear();
+    let preprocessed = preprocess(buffer, options);
+    *SOURCEMAP.lock().unwrap() = preprocessed.origins;
+    let mut parsed_lines = parser(&preprocessed.text, options);
+    let optab = build_optab();
+    for line in parsed_lines.iter_mut() {
+        if let Command::Instruction(instr) = &mut line.command
+            && instr.opcode.format == 3
+            && promoted.contains(&line.line)
+            && let Some(opcode) = optab.get(format!("+{}", instr.instr).as_str())
+        {
+            instr.instr = format!("+{}", instr.instr);
+            instr.opcode = opcode.clone();
+        }
+    }
     let mut symbol_table = SYMBOLTABLE.lock().unwrap();
     let mut literal_table = LITERALTABLE.lock().unwrap();
+    symbol_table.clear();
+    literal_table.clear();
     let mut labeledparsedline: Vec<LabeledParsedLines> = Vec::new();
     let mut locctr: u32 = 0x9999999;
     let mut length = 0;
     let mut startaddr = 0x00;
-    let mut pending_literals: Vec<String> = Vec::new();
+    let mut pending_equs: Vec<PendingEqu> = Vec::new();
+    // ORG pushes the location it leaves so an ORG without operand can come back to it
+    let mut org_stack: Vec<u32> = Vec::new();
+    let mut highest_locctr: u32 = 0;
 
     for lines in parsed_lines.iter() {
         labeledparsedline.push(LabeledParsedLines {
@@ -253,28 +259,27 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
         match &lines.command {
             Command::Instruction(instr) => {
                 if locctr != 0x9999999 {
-                    // Check if operand is a literal
-                    if let Some(operand) = &lines.operand1
+                    if instr.opcode.format == 2 {
+                        // register operands have no memory address to put a literal at
+                        for operand in [&lines.operand1, &lines.operand2].into_iter().flatten() {
+                            if is_literal(operand) {
+                                report_error(
+                                    lines.line,
+                                    &format!(
+                                        "{} is a format 2 instruction and cannot take literal {}",
+                                        instr.instr, operand
+                                    ),
+                                );
+                            }
+                        }
+                    } else if let Some(operand) = &lines.operand1
                         && is_literal(operand)
                     {
-                        // Add to literal table if not already present
-                        if !literal_table.iter().any(|lit| lit.literal == *operand)
-                            && !pending_literals.contains(operand)
-                        {
-                            pending_literals.push(operand.clone());
-
-                            if let Some((value, lit_length)) = parse_literal(operand) {
-                                literal_table.push(LiteralTable {
-                                    literal: operand.clone(),
-                                    value,
-                                    length: lit_length,
-                                    address: None,
-                                });
-                                log_info(&format!(
-                                    "Found literal: {} (length: {} bytes)",
-                                    operand, lit_length
-                                ));
+                        match parse_literal(operand, locctr) {
+                            Ok((value, relative)) => {
+                                add_literal(&mut literal_table, operand, value, relative, locctr)
                             }
+                            Err(e) => report_error(lines.line, &e),
                         }
                     }
 
@@ -283,6 +288,7 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                         symbol_table.push(SymbolTable {
                             label,
                             address: locctr,
+                            symbol_type: SymbolType::Relative,
                         });
                     }
 
@@ -294,8 +300,11 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                     match directive.to_uppercase().as_str() {
                         "START" => {
                             let operand: Option<String> = lines.operand1.clone();
-                            let num: Option<u32> =
-                                operand.as_ref().and_then(|s| s.parse::<u32>().ok());
+                            let num: Option<u32> = operand.as_ref().and_then(|s| {
+                                parse_number(s, options.start_radix)
+                                    .map_err(|e| report_error(lines.line, &e))
+                                    .ok()
+                            });
                             if let Some(value) = num {
                                 startaddr = value;
                                 locctr = value;
@@ -303,6 +312,7 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                                     symbol_table.push(SymbolTable {
                                         label,
                                         address: locctr,
+                                        symbol_type: SymbolType::Relative,
                                     });
                                 }
                             } else {
@@ -310,36 +320,29 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                             }
                         }
                         _ => {
-                            println!("Did not get START");
+                            report_error(
+                                lines.line,
+                                &format!("{} before START", directive.to_uppercase()),
+                            );
                         }
                     }
                 } else {
                     match directive.to_uppercase().as_str() {
                         "LTORG" | "END" => {
-                            if !pending_literals.is_empty() {
+                            // the pool holds every literal seen since the previous LTORG
+                            for lit_entry in
+                                literal_table.iter_mut().filter(|lit| lit.address.is_none())
+                            {
+                                lit_entry.address = Some(locctr);
                                 log_info(&format!(
-                                    "Allocating {} literals at {:06X}",
-                                    pending_literals.len(),
-                                    locctr
+                                    "  Literal {} assigned address {:06X}",
+                                    lit_entry.literal, locctr
                                 ));
-
-                                for literal in &pending_literals {
-                                    if let Some(lit_entry) = literal_table.iter_mut().find(|lit| {
-                                        lit.literal == *literal && lit.address.is_none()
-                                    }) {
-                                        lit_entry.address = Some(locctr);
-                                        log_info(&format!(
-                                            "  Literal {} assigned address {:06X}",
-                                            literal, locctr
-                                        ));
-                                        locctr += lit_entry.length;
-                                    }
-                                }
-                                pending_literals.clear();
+                                locctr += lit_entry.length;
                             }
 
                             if directive.to_uppercase() == "END" {
-                                length = locctr - startaddr;
+                                length = highest_locctr.max(locctr) - startaddr;
                                 break;
                             }
                         }
@@ -347,23 +350,96 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                             if let Some(label) = lines.label.clone() {
                                 let operand: Option<String> = lines.operand1.clone();
 
-                                let address: u32 = if let Some(expr) = &operand {
+                                // the symbol takes the type of its expression:
+                                // `EQU *` is relative, `EQU BUFEND-BUFFER` is absolute
+                                let resolved = if let Some(expr) = &operand {
                                     // Try to evaluate as expression
-                                    match expression_evaluate(expr, &symbol_table) {
-                                        Ok(val) => val,
+                                    match evaluate(expr, &symbol_table, locctr) {
+                                        Ok(val) if val.value >= 0 => {
+                                            Some((val.value as u32, val.symbol_type))
+                                        }
+                                        Ok(val) => {
+                                            report_error(
+                                                lines.line,
+                                                &format!(
+                                                    "EQU expression '{}' evaluated to negative value: {}",
+                                                    expr, val.value
+                                                ),
+                                            );
+                                            None
+                                        }
+                                        Err(_)
+                                            if !undefined_symbols(expr, &symbol_table)
+                                                .is_empty() =>
+                                        {
+                                            // forward reference, try again once pass 1 is done
+                                            pending_equs.push(PendingEqu {
+                                                label: label.clone(),
+                                                expr: expr.clone(),
+                                                locctr,
+                                                line: lines.line,
+                                            });
+                                            None
+                                        }
                                         Err(e) => {
-                                            log_error(&format!(
-                                                "Failed to evaluate EQU expression '{}': {}",
-                                                expr, e
-                                            ));
-                                            locctr
+                                            report_error(
+                                                lines.line,
+                                                &format!(
+                                                    "Failed to evaluate EQU expression '{}': {}",
+                                                    expr, e
+                                                ),
+                                            );
+                                            None
                                         }
                                     }
                                 } else {
-                                    locctr
+                                    Some((locctr, SymbolType::Relative))
                                 };
 
-                                symbol_table.push(SymbolTable { label, address });
+                                if let Some((address, symbol_type)) = resolved {
+                                    symbol_table.push(SymbolTable {
+                                        label,
+                                        address,
+                                        symbol_type,
+                                    });
+                                }
+                            }
+                        }
+                        "ORG" => {
+                            highest_locctr = highest_locctr.max(locctr);
+                            match &lines.operand1 {
+                                Some(expr) => {
+                                    match expression_evaluate(expr, &symbol_table, locctr) {
+                                        Ok(value) => {
+                                            org_stack.push(locctr);
+                                            locctr = value;
+                                        }
+                                        Err(e) => report_error(
+                                            lines.line,
+                                            &format!(
+                                                "Failed to evaluate ORG operand '{}': {}",
+                                                expr, e
+                                            ),
+                                        ),
+                                    }
+                                }
+                                None => match org_stack.pop() {
+                                    Some(previous) => locctr = previous,
+                                    None => report_warning(
+                                        lines.line,
+                                        "ORG without operand has no previous location to return to",
+                                    ),
+                                },
+                            }
+                        }
+                        "EXTREF" => {
+                            // symbols defined in another program, resolved when linking
+                            for name in [&lines.operand1, &lines.operand2].into_iter().flatten() {
+                                symbol_table.push(SymbolTable {
+                                    label: name.clone(),
+                                    address: 0,
+                                    symbol_type: SymbolType::External,
+                                });
                             }
                         }
                         "WORD" => {
@@ -371,6 +447,7 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                                 symbol_table.push(SymbolTable {
                                     label,
                                     address: locctr,
+                                    symbol_type: SymbolType::Relative,
                                 });
                             }
                             locctr += 3;
@@ -380,13 +457,16 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                                 symbol_table.push(SymbolTable {
                                     label,
                                     address: locctr,
+                                    symbol_type: SymbolType::Relative,
                                 });
                             }
-                            let operand: Option<String> = lines.operand1.clone();
-                            let num: Option<u32> =
-                                operand.as_ref().and_then(|s| s.parse::<u32>().ok());
-                            if let Some(value) = num {
-                                locctr += 3 * value;
+                            if let Some(value) = reservation_count(
+                                &lines.operand1,
+                                &symbol_table,
+                                locctr,
+                                lines.line,
+                            ) {
+                                locctr = reserve(locctr, value, 3, lines.line);
                             }
                         }
                         "RESB" => {
@@ -394,13 +474,16 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                                 symbol_table.push(SymbolTable {
                                     label,
                                     address: locctr,
+                                    symbol_type: SymbolType::Relative,
                                 });
                             }
-                            let operand: Option<String> = lines.operand1.clone();
-                            let num: Option<u32> =
-                                operand.as_ref().and_then(|s| s.parse::<u32>().ok());
-                            if let Some(value) = num {
-                                locctr += value;
+                            if let Some(value) = reservation_count(
+                                &lines.operand1,
+                                &symbol_table,
+                                locctr,
+                                lines.line,
+                            ) {
+                                locctr = reserve(locctr, value, 1, lines.line);
                             }
                         }
                         "BYTE" => {
@@ -408,12 +491,16 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
                                 symbol_table.push(SymbolTable {
                                     label,
                                     address: locctr,
+                                    symbol_type: SymbolType::Relative,
                                 });
                             }
-                            let operand: Option<String> = lines.operand1.clone();
-                            let len: Option<u32> = operand.as_ref().map(|s| s.len() as u32);
-                            if let Some(value) = len {
-                                locctr += value - 3;
+                            let operand = lines.operand1.clone().unwrap_or_default();
+                            match parse_bytes(&operand) {
+                                Ok(bytes) => locctr += bytes.len() as u32,
+                                Err(e) => report_error(
+                                    lines.line,
+                                    &format!("Invalid BYTE operand '{}': {}", operand, e),
+                                ),
                             }
                         }
                         _ => {
@@ -425,6 +512,8 @@ pub fn pass1asm(buffer: &str) -> (Vec<LabeledParsedLines>, u32, u32, Vec<SymbolT
         }
     }
 
+    resolve_pending_equs(pending_equs, &mut symbol_table);
+
     // Log literal table summary
     log_info(&format!(
         "=== LITERAL TABLE ({} entries) ===",
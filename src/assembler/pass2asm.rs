@@ -1,107 +1,286 @@
-use super::pass1asm::pass1asm;
+use super::constant::parse_bytes;
+use super::diagnostics::{report_error, report_note, source_line};
+use super::expression::{Expression, evaluate};
+use super::pass1asm::{is_literal, pass1asm};
 use crate::error::{log_error, log_info, log_warning};
 use crate::predefined::common::{
-    Command, LITERALTABLE, LabeledParsedLines, OBJECTPROGRAM, ObjectRecord, SymbolTable,
+    AssemblerOptions, Command, LINEMAP, LITERALTABLE, LabeledParsedLines, LiteralTable,
+    OBJECTPROGRAM, ObjectRecord, SYMBOLTABLE, SourceLine, SymbolTable, SymbolType,
 };
 use crate::predefined::registers;
 
 // pass 2 creates the object program
 
-pub fn pass2asm(buffer: &str) -> Vec<ObjectRecord> {
+// a T record holds at most 30 bytes (0x1E) of object code
+const MAX_TEXT_LENGTH: u32 = 0x1E;
+
+// Collects object code into T records. A new record is started when the current one is
+// full or when the next object code does not directly follow it (RESW/RESB gaps).
+struct TextRecordBuilder {
+    start: u32,
+    length: u32,
+    bytes: Vec<u8>,
+}
+
+impl TextRecordBuilder {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            length: 0,
+            bytes: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, address: u32, obj_code: String, records: &mut Vec<ObjectRecord>) {
+        let size = (obj_code.len() / 2) as u32;
+        if size > MAX_TEXT_LENGTH {
+            // long BYTE constants are split over several records
+            let (head, tail) = obj_code.split_at((MAX_TEXT_LENGTH * 2) as usize);
+            self.push(address, head.to_string(), records);
+            self.push(address + MAX_TEXT_LENGTH, tail.to_string(), records);
+            return;
+        }
+
+        if !self.bytes.is_empty()
+            && (self.start + self.length != address || self.length + size > MAX_TEXT_LENGTH)
+        {
+            self.flush(records);
+        }
+        if self.bytes.is_empty() {
+            self.start = address;
+        }
+        self.length += size;
+        self.bytes
+            .extend(hex::decode(&obj_code).expect("object code is whole bytes of hex"));
+    }
+
+    fn flush(&mut self, records: &mut Vec<ObjectRecord>) {
+        if self.bytes.is_empty() {
+            return;
+        }
+        records.push(ObjectRecord::Text {
+            start: self.start,
+            length: self.length as u8,
+            bytes: std::mem::take(&mut self.bytes),
+        });
+        self.length = 0;
+    }
+}
+
+// Why an operand does not fit a format 3 instruction
+#[derive(Debug, Clone)]
+pub struct NeedsFormat4 {
+    pub target: Option<u32>, // None for external references
+    pub reason: String,
+}
+
+// Instruction that relaxation assembles as format 4 on the next round
+struct Widening {
+    line: usize,
+    address: u32,
+    mnemonic: String,
+    operand: Option<String>, // target expression, for an external reference None
+    without_base: bool,      // no BASE was in effect, so a BASE could have helped
+    reason: String,
+}
+
+pub fn pass2asm(buffer: &str, options: &AssemblerOptions) -> Vec<ObjectRecord> {
+    if !options.relax {
+        return assemble(buffer, options, &[]).0;
+    }
+
+    // Widening an instruction moves everything after it, which can push other operands
+    // out of range, so keep assembling until a round widens nothing new
+    let mut promoted: Vec<usize> = Vec::new();
+    let mut widenings: Vec<Widening> = Vec::new();
+    loop {
+        let (object_program, new_widenings) = assemble(buffer, options, &promoted);
+        if new_widenings.is_empty() {
+            report_widenings(&widenings);
+            return object_program;
+        }
+        for widening in new_widenings {
+            promoted.push(widening.line);
+            widenings.push(widening);
+        }
+    }
+}
+
+// Relaxation report: one note per widened instruction and, where several widened
+// operands fall in one 4K window, a BASE that would have kept them in format 3
+fn report_widenings(widenings: &[Widening]) {
+    // the suggestion uses the final addresses, widening moves the targets
+    let symbol_table = SYMBOLTABLE.lock().unwrap().clone();
+    for widening in widenings {
+        report_note(
+            widening.line,
+            &format!(
+                "{} at {:06X} widened to format 4: {}",
+                widening.mnemonic, widening.address, widening.reason
+            ),
+        );
+    }
+
+    let mut candidates: Vec<(u32, usize)> = widenings
+        .iter()
+        .filter(|w| w.without_base)
+        .filter_map(|w| {
+            let target = evaluate(w.operand.as_ref()?, &symbol_table, w.address).ok()?;
+            (target.symbol_type == SymbolType::Relative).then_some((target.value as u32, w.line))
+        })
+        .collect();
+    candidates.sort();
+    let mut i = 0;
+    while i < candidates.len() {
+        let base = candidates[i].0;
+        let reachable: Vec<&(u32, usize)> = candidates[i..]
+            .iter()
+            .take_while(|(target, _)| target - base <= 4095)
+            .collect();
+        if reachable.len() >= 2 {
+            let name = symbol_table
+                .iter()
+                .find(|s| s.address == base && s.symbol_type == SymbolType::Relative)
+                .map(|s| s.label.clone())
+                .unwrap_or_else(|| format!("{:06X}", base));
+            let first_line = reachable.iter().map(|(_, line)| *line).min().unwrap();
+            report_note(
+                first_line,
+                &format!(
+                    "LDB #{} and BASE {} before line {} would keep {} widened instructions in format 3",
+                    name,
+                    name,
+                    source_line(first_line).line,
+                    reachable.len()
+                ),
+            );
+        }
+        i += reachable.len();
+    }
+}
+
+// One pass 1 + pass 2 round. Lines in `promoted` are assembled as format 4; format 3
+// instructions that do not fit are returned when relaxing, and reported otherwise.
+fn assemble(
+    buffer: &str,
+    options: &AssemblerOptions,
+    promoted: &[usize],
+) -> (Vec<ObjectRecord>, Vec<Widening>) {
     let (labeled_parsed_lines, len, start_addr, symbol_table): (
         Vec<LabeledParsedLines>,
         u32,
         u32,
         Vec<SymbolTable>,
-    ) = pass1asm(buffer);
-    let mut object_program = OBJECTPROGRAM.lock().unwrap();
+    ) = pass1asm(buffer, options, promoted);
+    let mut widenings: Vec<Widening> = Vec::new();
     let literal_table = LITERALTABLE.lock().unwrap();
-    let mut base_address = start_addr;
-    let mut text_length = 0;
-    let mut text = ObjectRecord::Text {
-        start: start_addr,
-        length: 0,
-        objcodes: Vec::new(),
-    };
+    let mut object_program: Vec<ObjectRecord> = Vec::new();
+    let mut base_address: Option<u32> = None;
+    let mut text = TextRecordBuilder::new();
     let mut modification_records: Vec<ObjectRecord> = Vec::new();
+    let mut program_name = String::from("DEFAULT");
 
     for lines in labeled_parsed_lines.iter() {
-        if text_length == 0 {
-            text = ObjectRecord::Text {
-                start: lines.locctr,
-                length: 0,
-                objcodes: Vec::new(),
-            };
-        }
+        let locctr = lines.locctr;
+        let operand1 = &lines.parsedtoken.operand1;
+        let operand2 = &lines.parsedtoken.operand2;
         match &lines.parsedtoken.command {
             Command::Directive(directive) => match directive.to_uppercase().as_str() {
                 "START" => {
                     let prog_name = lines.parsedtoken.label.clone();
+                    if let Some(name) = &prog_name {
+                        program_name = name.clone();
+                    }
                     object_program.push(header_record(prog_name, len, start_addr))
                 }
                 "BASE" => {
-                    let base_operand = lines.parsedtoken.operand1.clone().unwrap();
-                    if let Some(sym) = symbol_table.iter().find(|sym| sym.label == base_operand) {
-                        base_address = sym.address;
+                    let base_operand = operand1.clone().unwrap_or_default();
+                    match evaluate(&base_operand, &symbol_table, locctr) {
+                        Ok(value) => base_address = Some(value.value as u32),
+                        Err(e) => report_error(
+                            lines.parsedtoken.line,
+                            &format!("Invalid BASE operand '{}': {}", base_operand, e),
+                        ),
+                    }
+                }
+                "NOBASE" => {
+                    base_address = None;
+                }
+                "WORD" => {
+                    let word_operand = operand1.clone().unwrap_or_default();
+                    match evaluate(&word_operand, &symbol_table, locctr) {
+                        Ok(value) => {
+                            text.push(locctr, word_object_code(value.value), &mut object_program);
+                            if let Some(symbol) = modification_symbol(&value, &program_name) {
+                                modification_records
+                                    .push(make_word_modification_record(locctr, symbol));
+                            }
+                        }
+                        Err(e) => {
+                            report_error(
+                                lines.parsedtoken.line,
+                                &format!("Invalid WORD operand '{}': {}", word_operand, e),
+                            );
+                            // the word keeps its place, zero, so what follows stays put
+                            text.push(locctr, word_object_code(0), &mut object_program);
+                        }
+                    }
+                }
+                "BYTE" => {
+                    let byte_operand = operand1.clone().unwrap_or_default();
+                    if let Some(obj_code) = byte_object_code(&byte_operand) {
+                        text.push(locctr, obj_code, &mut object_program);
+                    } else {
+                        log_error(&format!("Invalid BYTE operand '{}'", byte_operand));
                     }
                 }
+                "RESW" | "RESB" | "EQU" | "ORG" | "EXTREF" => {
+                    // nothing to emit, the next object code starts a new text record
+                }
                 "LTORG" | "END" => {
-                    // Generate object code for literals at this location
-                    let literals_at_this_location: Vec<_> = literal_table
+                    // Generate object code for literals at this location,
+                    // the pool is laid out contiguously from the LTORG/END address
+                    let mut pool_addr = locctr;
+                    while let Some(lit) = literal_table
                         .iter()
-                        .filter(|lit| lit.address == Some(lines.locctr))
-                        .collect();
-
-                    if !literals_at_this_location.is_empty() {
+                        .find(|lit| lit.address == Some(pool_addr) && lit.length > 0)
+                    {
+                        text.push(pool_addr, lit.value.clone(), &mut object_program);
+                        if lit.relative {
+                            modification_records.push(make_word_modification_record(
+                                pool_addr,
+                                program_name.clone(),
+                            ));
+                        }
                         log_info(&format!(
-                            "Generating object code for {} literals at {:06X}",
-                            literals_at_this_location.len(),
-                            lines.locctr
+                            "  Added literal {} = {} (length: {}) at {:06X}",
+                            lit.literal, lit.value, lit.length, pool_addr
                         ));
-
-                        for lit in literals_at_this_location {
-                            // Add literal data to text record
-                            text_length += lit.length as u8;
-                            if let ObjectRecord::Text {
-                                length, objcodes, ..
-                            } = &mut text
-                            {
-                                *length = text_length;
-                                objcodes.push(lit.value.clone());
-                                log_info(&format!(
-                                    "  Added literal {} = {} (length: {})",
-                                    lit.literal, lit.value, lit.length
-                                ));
-                            }
-
-                            if text_length >= 55 {
-                                text_length = 0;
-                                object_program.push(text.clone());
-                                text = ObjectRecord::Text {
-                                    start: lines.locctr + lit.length,
-                                    length: 0,
-                                    objcodes: Vec::new(),
-                                };
-                            }
-                        }
+                        pool_addr += lit.length;
                     }
 
                     if directive.to_uppercase() == "END" {
                         // Add final text record if it has content
-                        if text_length > 0 {
-                            if let ObjectRecord::Text { length, .. } = &mut text {
-                                *length = text_length;
-                            }
-                            object_program.push(text.clone());
-                        }
+                        text.flush(&mut object_program);
 
                         // Add all modification records before END record
-                        for mod_record in modification_records.iter() {
-                            object_program.push(mod_record.clone());
-                        }
-
-                        // Add END record
-                        object_program.push(ObjectRecord::End { start: start_addr });
+                        object_program.append(&mut modification_records);
+
+                        // Execution starts at the END operand, or at the program start without one
+                        let entry = match operand1 {
+                            Some(expr) => match evaluate(expr, &symbol_table, locctr) {
+                                Ok(value) => value.value as u32,
+                                Err(e) => {
+                                    report_error(
+                                        lines.parsedtoken.line,
+                                        &format!("Invalid END operand '{}': {}", expr, e),
+                                    );
+                                    start_addr
+                                }
+                            },
+                            None => start_addr,
+                        };
+                        object_program.push(ObjectRecord::End { start: entry });
                     }
                 }
                 _ => {
@@ -111,130 +290,124 @@ pub fn pass2asm(buffer: &str) -> Vec<ObjectRecord> {
             Command::Instruction(instr) => {
                 let format = instr.opcode.format;
                 let opcode = instr.opcode.code;
-                let locctr = lines.locctr;
-                let obj_code;
-                match &format {
-                    1 => {
-                        obj_code = object_code1(opcode);
-                        text_length += 1;
-                        if let ObjectRecord::Text {
-                            length, objcodes, ..
-                        } = &mut text
-                        {
-                            *length = text_length;
-                            objcodes.push(obj_code);
-                        }
-                        if text_length >= 55 {
-                            text_length = 0;
-                            object_program.push(text.clone());
-                        }
-                    }
-                    2 => {
-                        obj_code = object_code2(
-                            opcode,
-                            &lines.parsedtoken.operand1.clone(),
-                            &lines.parsedtoken.operand2.clone(),
-                        );
-                        text_length += 2;
-                        if let ObjectRecord::Text {
-                            length, objcodes, ..
-                        } = &mut text
-                        {
-                            *length = text_length;
-                            objcodes.push(obj_code);
-                        }
-                        if text_length >= 55 {
-                            text_length = 0;
-                            object_program.push(text.clone());
-                        }
-                    }
-                    3 => {
-                        obj_code = object_code3(
-                            opcode,
-                            &lines.parsedtoken.operand1.clone(),
-                            &lines.parsedtoken.operand2.clone(),
-                            &symbol_table,
-                            &literal_table,
-                            locctr,
-                            base_address,
-                        );
-
-                        // Check if format 3 was extended to format 4
-                        let actual_length = if obj_code.len() == 8 {
-                            // Extended to format 4 - add modification record
-                            log_info(&format!(
-                                "Format 3 at {:06X} extended to format 4, adding modification record",
-                                locctr
-                            ));
-
-                            // Modification record: address + 1 (skip opcode byte), modify 5 half-bytes (20 bits)
-                            modification_records.push(make_modification_record(
-                                locctr,
-                                &lines.parsedtoken.operand1,
-                            ));
-                            4
-                        } else {
-                            3
-                        };
-
-                        text_length += actual_length;
-                        if let ObjectRecord::Text {
-                            length, objcodes, ..
-                        } = &mut text
-                        {
-                            *length = text_length;
-                            objcodes.push(obj_code);
-                        }
-                        if text_length >= 55 {
-                            text_length = 0;
-                            object_program.push(text.clone());
-                        }
-                    }
-                    4 => {
-                        obj_code = object_code4(
-                            opcode,
-                            &lines.parsedtoken.operand1.clone(),
-                            &lines.parsedtoken.operand2.clone(),
-                            &symbol_table,
-                            &literal_table,
-                            locctr,
-                        );
-
-                        log_info(&format!(
-                            "Format 4 instruction at {:06X}, adding modification record",
-                            locctr
-                        ));
-
-                        modification_records.push(make_modification_record(
-                            locctr,
-                            &lines.parsedtoken.operand1,
-                        ));
-
-                        text_length += 4;
-                        if let ObjectRecord::Text {
-                            length, objcodes, ..
-                        } = &mut text
-                        {
-                            *length = text_length;
-                            objcodes.push(obj_code);
+                if matches!(format, 3 | 4)
+                    && let Some(opr) = operand1
+                    && let Err(e) = resolve_target(
+                        addressing_flags(opr).2,
+                        &symbol_table,
+                        &literal_table,
+                        locctr,
+                    )
+                {
+                    report_error(
+                        lines.parsedtoken.line,
+                        &format!("Cannot resolve operand '{}': {}", opr, e),
+                    );
+                }
+                let obj_code = match &format {
+                    1 => object_code1(opcode),
+                    2 => object_code2(
+                        opcode,
+                        operand1,
+                        operand2,
+                        &symbol_table,
+                        locctr,
+                        lines.parsedtoken.line,
+                    ),
+                    3 => match object_code3(
+                        opcode,
+                        operand1,
+                        operand2,
+                        &symbol_table,
+                        &literal_table,
+                        locctr,
+                        base_address,
+                    ) {
+                        Ok(obj_code) => obj_code,
+                        Err(needs) if options.relax => {
+                            widenings.push(Widening {
+                                line: lines.parsedtoken.line,
+                                address: locctr,
+                                mnemonic: instr.instr.clone(),
+                                operand: needs
+                                    .target
+                                    .and(operand1.as_deref())
+                                    .map(|opr| addressing_flags(opr).2.to_string()),
+                                without_base: base_address.is_none(),
+                                reason: needs.reason,
+                            });
+                            String::new()
                         }
-                        if text_length >= 55 {
-                            text_length = 0;
-                            object_program.push(text.clone());
+                        Err(needs) => {
+                            report_error(
+                                lines.parsedtoken.line,
+                                &format!(
+                                    "{}, use +{} or assemble with --relax",
+                                    needs.reason, instr.instr
+                                ),
+                            );
+                            String::new()
                         }
-                    }
+                    },
+                    4 => object_code4(
+                        opcode,
+                        operand1,
+                        operand2,
+                        &symbol_table,
+                        &literal_table,
+                        locctr,
+                    ),
                     _ => {
                         log_error(&format!(
                             "Invalid format {} found to make object code",
                             format
                         ));
+                        String::new()
                     }
+                };
+
+                // A format 4 address only needs relocating when it depends on the load address
+                // or on another program, `+LDT #4096` is left alone
+                if obj_code.len() == 8
+                    && let Some(target) =
+                        operand_target(operand1, &symbol_table, &literal_table, locctr)
+                    && let Some(symbol) = modification_symbol(&target, &program_name)
+                {
+                    log_info(&format!(
+                        "Format 4 instruction at {:06X}, adding modification record",
+                        locctr
+                    ));
+                    // Modification record: address + 1 (skip opcode byte), modify 5 half-bytes (20 bits)
+                    modification_records.push(make_modification_record(locctr, symbol));
+                }
+
+                if !obj_code.is_empty() {
+                    text.push(locctr, obj_code, &mut object_program);
                 }
             }
         }
     }
 
-    object_program.to_vec()
+    *OBJECTPROGRAM.lock().unwrap() = object_program.clone();
+    *LINEMAP.lock().unwrap() = line_map(&labeled_parsed_lines);
+    (object_program, widenings)
+}
+
+// the lines that put instructions or data in memory, for source-level debugging
+fn line_map(lines: &[LabeledParsedLines]) -> Vec<SourceLine> {
+    lines
+        .iter()
+        .filter(|line| match &line.parsedtoken.command {
+            Command::Instruction(_) => true,
+            Command::Directive(directive) => {
+                matches!(directive.to_uppercase().as_str(), "BYTE" | "WORD")
+            }
+        })
+        .map(|line| SourceLine {
+            address: line.locctr,
+            origin: source_line(line.parsedtoken.line),
+        })
+        .collect()
 }
 
 fn header_record(prog_name: Option<String>, len: u32, starting_addr: u32) -> ObjectRecord {
@@ -263,320 +436,323 @@ fn header_record(prog_name: Option<String>, len: u32, starting_addr: u32) -> Obj
 
 //object code for format 1
 pub fn object_code1(opcode: u8) -> String {
-    opcode.to_string()
+    format!("{:02X}", opcode)
 }
 
-//object code for fromat 3
-pub fn object_code2(opcode: u8, operand1: &Option<String>, operand2: &Option<String>) -> String {
-    let reg = registers::register_map();
-    let r1 = operand1.as_ref().unwrap().to_uppercase();
-    let r2 = operand2.as_ref().unwrap().to_uppercase();
-    let r1_code = reg.get(r1.as_str()).expect("error getting r1 hexcode");
-    let r2_code = reg.get(r2.as_str()).expect("error getting r2 hexcode");
+//object code for fromat 2
+pub fn object_code2(
+    opcode: u8,
+    operand1: &Option<String>,
+    operand2: &Option<String>,
+    symbol_table: &[SymbolTable],
+    current_locctr: u32,
+    line: usize,
+) -> String {
+    let (r1_code, r2_code) = match opcode {
+        // SVC n
+        0xB0 => (
+            count_operand(operand1, symbol_table, current_locctr, line),
+            0,
+        ),
+        // SHIFTL/SHIFTR r1,n  -- n is stored as n-1
+        0xA4 | 0xA8 => (
+            register_operand(operand1, line),
+            count_operand(operand2, symbol_table, current_locctr, line).wrapping_sub(1) & 0x0F,
+        ),
+        _ => (
+            register_operand(operand1, line),
+            register_operand(operand2, line),
+        ),
+    };
 
     let combined_reg = (r1_code << 4) | r2_code;
 
     format!("{:02X}{:02X}", opcode, combined_reg)
 }
 
-//object code for format 3
-#[warn(unused_mut)]
+fn register_operand(operand: &Option<String>, line: usize) -> u8 {
+    let Some(name) = operand else {
+        return 0;
+    };
+    let reg = registers::register_map();
+    match reg.get(name.to_uppercase().as_str()) {
+        Some(code) => *code,
+        // pass 1 reported the literal already
+        None if is_literal(name) => 0,
+        None => {
+            report_error(line, &format!("Unknown register '{}'", name));
+            0
+        }
+    }
+}
+
+fn count_operand(
+    operand: &Option<String>,
+    symbol_table: &[SymbolTable],
+    locctr: u32,
+    line: usize,
+) -> u8 {
+    let Some(expr) = operand else {
+        return 0;
+    };
+    match evaluate(expr, symbol_table, locctr) {
+        Ok(value) => (value.value & 0x0F) as u8,
+        Err(e) => {
+            report_error(line, &format!("Invalid format 2 operand '{}': {}", expr, e));
+            0
+        }
+    }
+}
+
+// splits the addressing prefix off a format 3/4 operand and returns (n, i, expression)
+fn addressing_flags(operand: &str) -> (u8, u8, &str) {
+    if let Some(stripped) = operand.strip_prefix('#') {
+        (0, 1, stripped)
+    } else if let Some(stripped) = operand.strip_prefix('@') {
+        (1, 0, stripped)
+    } else {
+        (1, 1, operand)
+    }
+}
+
+fn index_flag(operand2: &Option<String>) -> u8 {
+    match operand2 {
+        Some(v) if v.to_uppercase() == "X" => 1,
+        Some(v) => {
+            log_warning(&format!("Incorrect register '{}' in indexed mode", v));
+            0
+        }
+        None => 0,
+    }
+}
+
+// Target of a format 3/4 operand: a literal's pool address or the value of an expression
+fn resolve_target(
+    operand: &str,
+    symbol_table: &[SymbolTable],
+    literal_table: &[LiteralTable],
+    current_locctr: u32,
+) -> Result<Expression, String> {
+    if operand.starts_with('=') {
+        // equal literals share an entry, so look it up by the instruction using it
+        let lit = literal_table
+            .iter()
+            .find(|l| l.references.contains(&current_locctr))
+            .ok_or_else(|| format!("Literal {} not found in literal table", operand))?;
+        let lit_addr = lit
+            .address
+            .ok_or_else(|| format!("Literal {} has no assigned address", operand))?;
+        log_info(&format!(
+            "Using literal {} at address {:06X}",
+            operand, lit_addr
+        ));
+        return Ok(Expression {
+            value: lit_addr as i32,
+            symbol_type: SymbolType::Relative,
+            external: None,
+        });
+    }
+    evaluate(operand, symbol_table, current_locctr)
+}
+
+fn operand_target(
+    operand1: &Option<String>,
+    symbol_table: &[SymbolTable],
+    literal_table: &[LiteralTable],
+    current_locctr: u32,
+) -> Option<Expression> {
+    let (_, _, expr) = addressing_flags(operand1.as_ref()?);
+    resolve_target(expr, symbol_table, literal_table, current_locctr).ok()
+}
+
+// Symbol an M record adds to the field: the program itself for relative values,
+// the referenced symbol for external ones and nothing for absolute ones
+fn modification_symbol(target: &Expression, program_name: &str) -> Option<String> {
+    match target.symbol_type {
+        SymbolType::Absolute => None,
+        SymbolType::Relative => Some(program_name.to_string()),
+        SymbolType::External => target.external.clone(),
+    }
+}
+
+fn format3_object_code(opcode: u8, flags: [u8; 5], displacement: i32) -> String {
+    let [flag_n, flag_i, flag_x, flag_b, flag_p] = flags;
+    let flag_e: u8 = 0;
+    let disp_12bit = (displacement & 0xFFF) as u16;
+    let first_byte: u8 = opcode | flag_n << 1 | flag_i;
+    let second_byte = (flag_x << 7)
+        | (flag_b << 6)
+        | (flag_p << 5)
+        | (flag_e << 4)
+        | ((disp_12bit >> 8) & 0x0F) as u8;
+    let third_byte = (disp_12bit & 0xFF) as u8;
+
+    format!("{:02X}{:02X}{:02X}", first_byte, second_byte, third_byte)
+}
+
+//object code for format 3, or why the operand needs format 4
 pub fn object_code3(
-    //TODO: Add feature of literal
     opcode: u8,
     operand1: &Option<String>,
     operand2: &Option<String>,
     symbol_table: &[SymbolTable],
-    literal_table: &[crate::predefined::common::LiteralTable],
+    literal_table: &[LiteralTable],
     current_locctr: u32,
-    base_address: u32,
-) -> String {
-    let mut flag_n: u8 = 0;
-    let mut flag_i: u8 = 0;
-    let mut flag_x: u8 = 0;
-    let flag_e: u8 = 0;
-    let mut flag_b: u8 = 0;
-    let mut flag_p: u8 = 0;
-
-    let _reg = registers::register_map();
-    if let Some(v) = operand2 {
-        if v.to_uppercase() == "X" {
-            flag_x = 1;
-        } else {
-            log_warning(&format!("Incorrect register '{}' in indexed mode", v));
-        }
-    }
-    if let Some(opr) = operand1 {
-        let mut operand = opr.clone();
+    base_address: Option<u32>,
+) -> Result<String, NeedsFormat4> {
+    let flag_x = index_flag(operand2);
 
-        if let Some(_stripped) = opr.strip_prefix('=') {
-            if let Some(lit) = literal_table.iter().find(|l| l.literal == *opr) {
-                if let Some(lit_addr) = lit.address {
-                    log_info(&format!(
-                        "Using literal {} at address {:06X}",
-                        opr, lit_addr
-                    ));
+    // no operand (RSUB): simple addressing with a zero displacement
+    let Some(opr) = operand1 else {
+        return Ok(format3_object_code(opcode, [1, 1, flag_x, 0, 0], 0));
+    };
 
-                    let target_addr = lit_addr;
-                    let program_counter = current_locctr + 3;
-                    let mut displacement = target_addr as i32 - program_counter as i32;
-
-                    // Simple addressing (n=1, i=1)
-                    flag_n = 1;
-                    flag_i = 1;
-
-                    if (-2048..=2047).contains(&displacement) {
-                        flag_p = 1;
-                        let disp_12bit = (displacement & 0xFFF) as u16;
-                        let first_byte: u8 = opcode | flag_n << 1 | flag_i;
-                        let second_byte = (flag_x << 7)
-                            | (flag_b << 6)
-                            | (flag_p << 5)
-                            | (flag_e << 4)
-                            | ((disp_12bit >> 8) & 0x0F) as u8;
-                        let third_byte = (disp_12bit & 0xFF) as u8;
-
-                        return format!("{:02X}{:02X}{:02X}", first_byte, second_byte, third_byte);
-                    } else {
-                        displacement =
-                            target_addr as i32 - base_address as i32 + program_counter as i32;
-                        if (0..=4095).contains(&displacement) {
-                            flag_b = 1;
-                            let disp_12bit = (displacement & 0xFFF) as u16;
-                            let first_byte: u8 = opcode | flag_n << 1 | flag_i;
-                            let second_byte = (flag_x << 7)
-                                | (flag_b << 6)
-                                | (flag_p << 5)
-                                | (flag_e << 4)
-                                | ((disp_12bit >> 8) & 0x0F) as u8;
-                            let third_byte = (disp_12bit & 0xFF) as u8;
-
-                            return format!(
-                                "{:02X}{:02X}{:02X}",
-                                first_byte, second_byte, third_byte
-                            );
-                        } else {
-                            // Extend to format 4
-                            log_warning(&format!(
-                                "Literal {} address {:06X} out of range for format 3, extending to format 4",
-                                opr, target_addr
-                            ));
-                            return object_code4(
-                                opcode,
-                                operand1,
-                                operand2,
-                                symbol_table,
-                                literal_table,
-                                current_locctr,
-                            );
-                        }
-                    }
-                } else {
-                    log_warning(&format!("Literal {} has no assigned address", opr));
-                    return String::new();
-                }
-            } else {
-                log_warning(&format!("Literal {} not found in literal table", opr));
-                return String::new();
-            }
+    let (flag_n, flag_i, expr) = addressing_flags(opr);
+    let target = match resolve_target(expr, symbol_table, literal_table, current_locctr) {
+        Ok(target) => target,
+        // reported by the caller, a zero operand keeps the bytes where they belong
+        Err(_) => {
+            return Ok(format3_object_code(
+                opcode,
+                [flag_n, flag_i, flag_x, 0, 0],
+                0,
+            ));
         }
+    };
 
-        if let Some(stripped) = opr.strip_prefix('#') {
-            flag_i = 1;
-            operand = stripped.to_string();
-        } else if let Some(stripped) = opr.strip_prefix('@') {
-            flag_n = 1;
-            operand = stripped.to_string();
+    let reason = match target.symbol_type {
+        SymbolType::External => {
+            return Err(NeedsFormat4 {
+                target: None,
+                reason: format!("external reference '{}' is only known when linking", expr),
+            });
         }
-
-        if let Some(sym) = symbol_table.iter().find(|sym| sym.label == operand) {
-            let target_addr = sym.address;
+        // Absolute values go straight into the displacement: an immediate constant
+        // or a direct address, which must not move when the program is relocated
+        SymbolType::Absolute => {
+            if (0..=4095).contains(&target.value) {
+                return Ok(format3_object_code(
+                    opcode,
+                    [flag_n, flag_i, flag_x, 0, 0],
+                    target.value,
+                ));
+            }
+            format!("value {} does not fit in 12 bits", target.value)
+        }
+        SymbolType::Relative => {
             let program_counter = current_locctr + 3;
-            let mut displacement = target_addr as i32 - program_counter as i32;
+            let displacement = target.value - program_counter as i32;
 
             if (-2048..=2047).contains(&displacement) {
-                flag_p = 1;
-                if flag_n == 0 && flag_i == 0 {
-                    flag_i = 1;
-                    flag_n = 1;
-                }
-                let disp_12bit = (displacement & 0xFFF) as u16;
-                let first_byte: u8 = opcode | flag_n << 1 | flag_i;
-                let second_byte = (flag_x << 7)
-                    | (flag_b << 6)
-                    | (flag_p << 5)
-                    | (flag_e << 4)
-                    | ((disp_12bit >> 8) & 0x0F) as u8;
-                let third_byte = (disp_12bit & 0xFF) as u8;
-
-                return format!("{:02X}{:02X}{:02X}", first_byte, second_byte, third_byte);
-            } else {
-                displacement = target_addr as i32 - base_address as i32 + program_counter as i32;
-                if (0..=4095).contains(&displacement) {
-                    flag_b = 1;
-                    if flag_n == 0 && flag_i == 0 {
-                        flag_i = 1;
-                        flag_n = 1;
+                return Ok(format3_object_code(
+                    opcode,
+                    [flag_n, flag_i, flag_x, 0, 1],
+                    displacement,
+                ));
+            }
+            match base_address {
+                Some(base) => {
+                    let base_displacement = target.value - base as i32;
+                    if (0..=4095).contains(&base_displacement) {
+                        return Ok(format3_object_code(
+                            opcode,
+                            [flag_n, flag_i, flag_x, 1, 0],
+                            base_displacement,
+                        ));
                     }
-                    let disp_12bit = (displacement & 0xFFF) as u16;
-                    let first_byte: u8 = opcode | flag_n << 1 | flag_i;
-                    let second_byte = (flag_x << 7)
-                        | (flag_b << 6)
-                        | (flag_p << 5)
-                        | (flag_e << 4)
-                        | ((disp_12bit >> 8) & 0x0F) as u8;
-                    let third_byte = (disp_12bit & 0xFF) as u8;
-
-                    return format!("{:02X}{:02X}{:02X}", first_byte, second_byte, third_byte);
-                } else {
-                    // Neither PC-relative nor base-relative works - extend to format 4
-                    log_warning(&format!(
-                        "Address {:06X} out of range for format 3, extending to format 4",
-                        target_addr
-                    ));
-                    //TODO: update locctr  --- chicken egg problem
-                    return object_code4(
-                        opcode,
-                        operand1,
-                        operand2,
-                        symbol_table,
-                        literal_table,
-                        current_locctr,
-                    );
+                    format!(
+                        "target {:06X} is {} from PC and {} from BASE {:06X}",
+                        target.value, displacement, base_displacement, base
+                    )
                 }
+                None => format!(
+                    "target {:06X} is {} from PC and no BASE is in effect",
+                    target.value, displacement
+                ),
             }
-        } else {
-            log_warning(&format!(
-                "Symbol '{}' not found in symbol table, treating as immediate value",
-                operand
-            ));
-            let displacement: i32 = operand.parse().expect("not a vaid string");
-            let disp_12bit = (displacement & 0xFFF) as u16;
-            let first_byte: u8 = opcode | flag_n << 1 | flag_i;
-            let second_byte = (flag_x << 7)
-                | (flag_b << 6)
-                | (flag_p << 5)
-                | (flag_e << 4)
-                | ((disp_12bit >> 8) & 0x0F) as u8;
-            let third_byte = (disp_12bit & 0xFF) as u8;
-
-            return format!("{:02X}{:02X}{:02X}", first_byte, second_byte, third_byte);
         }
-    }
-    String::new()
+    };
+
+    log_warning(&format!(
+        "Address {:06X} out of range for format 3",
+        target.value
+    ));
+    Err(NeedsFormat4 {
+        target: Some(target.value as u32),
+        reason,
+    })
 }
 
 // objct code for format 4
-#[warn(unused_mut)]
 pub fn object_code4(
     opcode: u8,
     operand1: &Option<String>,
     operand2: &Option<String>,
     symbol_table: &[SymbolTable],
-    literal_table: &[crate::predefined::common::LiteralTable],
-    _current_locctr: u32,
+    literal_table: &[LiteralTable],
+    current_locctr: u32,
 ) -> String {
-    let mut flag_n: u8 = 0;
-    let mut flag_i: u8 = 0;
-    let mut flag_x: u8 = 0;
+    let flag_x = index_flag(operand2);
     let flag_e: u8 = 1;
     let flag_b: u8 = 0;
     let flag_p: u8 = 0;
 
-    let _reg = registers::register_map();
-    if let Some(v) = operand2 {
-        if v.to_uppercase() == "X" {
-            flag_x = 1;
-        } else {
-            log_warning(&format!("Incorrect register '{}' in indexed mode", v));
-        }
-    }
-    if let Some(opr) = operand1 {
-        let mut operand = opr.clone();
-
-        // Check if operand is a literal
-        if let Some(_stripped) = opr.strip_prefix('=') {
-            if let Some(lit) = literal_table.iter().find(|l| l.literal == *opr) {
-                if let Some(lit_addr) = lit.address {
-                    log_info(&format!(
-                        "Using literal {} at address {:06X} in format 4",
-                        opr, lit_addr
-                    ));
-
-                    let addr_20bit = lit_addr & 0xFFFFF;
-
-                    // Simple addressing (n=1, i=1)
-                    flag_n = 1;
-                    flag_i = 1;
-
-                    let first_byte = opcode | (flag_n << 1) | flag_i;
-                    let second_byte = (flag_x << 7)
-                        | (flag_b << 6)
-                        | (flag_p << 5)
-                        | (flag_e << 4)
-                        | ((addr_20bit >> 16) & 0x0F) as u8;
-                    let third_byte = ((addr_20bit >> 8) & 0xFF) as u8;
-                    let fourth_byte = (addr_20bit & 0xFF) as u8;
-
-                    return format!(
-                        "{:02X}{:02X}{:02X}{:02X}",
-                        first_byte, second_byte, third_byte, fourth_byte
-                    );
-                } else {
-                    log_warning(&format!("Literal {} has no assigned address", opr));
-                    return String::new();
-                }
-            } else {
-                log_warning(&format!("Literal {} not found in literal table", opr));
-                return String::new();
+    let (flag_n, flag_i, target_addr) = match operand1 {
+        Some(opr) => {
+            let (flag_n, flag_i, expr) = addressing_flags(opr);
+            match resolve_target(expr, symbol_table, literal_table, current_locctr) {
+                Ok(target) => (flag_n, flag_i, target.value as u32),
+                Err(_) => (flag_n, flag_i, 0),
             }
         }
+        None => (1, 1, 0),
+    };
 
-        if let Some(stripped) = opr.strip_prefix('#') {
-            flag_i = 1;
-            operand = stripped.to_string();
-        } else if let Some(stripped) = opr.strip_prefix('@') {
-            flag_n = 1;
-            operand = stripped.to_string();
-        }
-
-        if let Some(sym) = symbol_table.iter().find(|sym| sym.label == operand) {
-            let target_addr = sym.address;
+    let addr_20bit = target_addr & 0xFFFFF; // 20-bit address
 
-            let addr_20bit = target_addr & 0xFFFFF; // 20-bit address
+    let first_byte = opcode | (flag_n << 1) | flag_i;
 
-            if flag_n == 0 && flag_i == 0 {
-                flag_i = 1;
-                flag_n = 1;
-            }
+    let second_byte = (flag_x << 7)
+        | (flag_b << 6)
+        | (flag_p << 5)
+        | (flag_e << 4)
+        | ((addr_20bit >> 16) & 0x0F) as u8;
 
-            let first_byte = opcode | (flag_n << 1) | flag_i;
+    let third_byte = ((addr_20bit >> 8) & 0xFF) as u8;
 
-            let second_byte = (flag_x << 7)
-                | (flag_b << 6)
-                | (flag_p << 5)
-                | (flag_e << 4)
-                | ((addr_20bit >> 16) & 0x0F) as u8;
+    let fourth_byte = (addr_20bit & 0xFF) as u8;
 
-            let third_byte = ((addr_20bit >> 8) & 0xFF) as u8;
+    format!(
+        "{:02X}{:02X}{:02X}{:02X}",
+        first_byte, second_byte, third_byte, fourth_byte
+    )
+}
 
-            let fourth_byte = (addr_20bit & 0xFF) as u8;
+// WORD constants are 24-bit two's complement
+pub fn word_object_code(value: i32) -> String {
+    format!("{:06X}", value & 0xFFFFFF)
+}
 
-            return format!(
-                "{:02X}{:02X}{:02X}{:02X}",
-                first_byte, second_byte, third_byte, fourth_byte
-            );
-        }
-    }
-    String::new()
+// BYTE constants: C'EOF', X'F1' or a decimal byte
+pub fn byte_object_code(operand: &str) -> Option<String> {
+    parse_bytes(operand).ok().map(hex::encode_upper)
 }
 
-pub fn make_modification_record(current_locctr: u32, operand1: &Option<String>) -> ObjectRecord {
+pub fn make_modification_record(current_locctr: u32, variable: String) -> ObjectRecord {
     ObjectRecord::Modification {
         address: current_locctr + 1,
         length: 5,
         sign: true,
-        variable: operand1.clone().unwrap_or_default(),
+        variable,
+    }
+}
+
+// a relative WORD is patched as a whole: 6 half-bytes starting at the word itself
+pub fn make_word_modification_record(current_locctr: u32, variable: String) -> ObjectRecord {
+    ObjectRecord::Modification {
+        address: current_locctr,
+        length: 6,
+        sign: true,
+        variable,
     }
 }
-//TODO : add the feature of Literals support
@@ -0,0 +1,234 @@
+use super::diagnostics::report_at;
+use super::expression::evaluate;
+use super::lexer::statement;
+use crate::predefined::common::{AssemblerOptions, Origin, Severity, SymbolTable, SymbolType};
+use std::path::{Path, PathBuf};
+
+// Runs before the lexer: INCLUDE pastes in another source file, and IF, IFDEF,
+// IFNDEF, ELSE and ENDIF leave out the lines whose condition is false. Directive
+// and left-out lines become blank lines so every line keeps an origin.
+
+pub struct Preprocessed {
+    pub text: String,
+    pub origins: Vec<Origin>, // where each line of `text` came from
+}
+
+// an IF, IFDEF or IFNDEF that has not seen its ENDIF yet
+struct Conditional {
+    line: usize,
+    taken: bool,  // the branch being read is assembled
+    parent: bool, // the lines around the block are assembled
+    in_else: bool,
+}
+
+struct Preprocessor<'a> {
+    options: &'a AssemblerOptions,
+    // -D defines, then EQUs with a constant value, for IF and IFDEF
+    defines: Vec<SymbolTable>,
+    // canonical paths of the files being read, the main source first when it has one
+    includes: Vec<PathBuf>,
+    text: String,
+    origins: Vec<Origin>,
+}
+
+pub fn preprocess(buffer: &str, options: &AssemblerOptions) -> Preprocessed {
+    let mut preprocessor = Preprocessor {
+        options,
+        defines: options
+            .defines
+            .iter()
+            .map(|(name, value)| SymbolTable {
+                label: name.clone(),
+                address: *value,
+                symbol_type: SymbolType::Absolute,
+            })
+            .collect(),
+        includes: options
+            .source
+            .iter()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+            .collect(),
+        text: String::new(),
+        origins: Vec::new(),
+    };
+    preprocessor.read(buffer, None, None);
+    Preprocessed {
+        text: preprocessor.text,
+        origins: preprocessor.origins,
+    }
+}
+
+impl Preprocessor<'_> {
+    // `file` is the name diagnostics use, `dir` where its own INCLUDEs are looked up first
+    fn read(&mut self, source: &str, file: Option<&str>, dir: Option<&Path>) {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (index, text) in source.lines().enumerate() {
+            let origin = Origin {
+                file: file.map(str::to_string),
+                line: index + 1,
+            };
+            let active = conditionals.last().is_none_or(|c| c.taken);
+            let statement = statement(text, self.options);
+            let Some((label, mnemonic, operand)) = statement else {
+                self.push(text, origin, active);
+                continue;
+            };
+            let operand = operand.unwrap_or_default();
+            match mnemonic.as_str() {
+                "IF" | "IFDEF" | "IFNDEF" => {
+                    let taken = active && self.condition(&mnemonic, &operand, &origin);
+                    conditionals.push(Conditional {
+                        line: origin.line,
+                        taken,
+                        parent: active,
+                        in_else: false,
+                    });
+                }
+                "ELSE" => match conditionals.last_mut() {
+                    Some(c) if !c.in_else => {
+                        c.in_else = true;
+                        c.taken = c.parent && !c.taken;
+                    }
+                    Some(_) => report_at(origin.clone(), Severity::Error, "Second ELSE for one IF"),
+                    None => report_at(origin.clone(), Severity::Error, "ELSE without IF"),
+                },
+                "ENDIF" => {
+                    if conditionals.pop().is_none() {
+                        report_at(origin.clone(), Severity::Error, "ENDIF without IF");
+                    }
+                }
+                "INCLUDE" => {
+                    // the INCLUDE line stays blank, the file's lines follow it
+                    self.push(text, origin.clone(), false);
+                    if active {
+                        self.include(&operand, &origin, dir);
+                    }
+                    continue;
+                }
+                "EQU" if active => {
+                    // a constant EQU can be tested by a later IF
+                    if let Some(label) = label
+                        && let Ok(value) = evaluate(&self.name(&operand), &self.defines, 0)
+                        && value.symbol_type == SymbolType::Absolute
+                        && value.value >= 0
+                    {
+                        self.defines.push(SymbolTable {
+                            label: self.name(&label),
+                            address: value.value as u32,
+                            symbol_type: SymbolType::Absolute,
+                        });
+                    }
+                    self.push(text, origin, true);
+                    continue;
+                }
+                _ => {
+                    self.push(text, origin, active);
+                    continue;
+                }
+            }
+            // the directive itself is not assembled
+            self.push(text, origin, false);
+        }
+        for conditional in conditionals {
+            report_at(
+                Origin {
+                    file: file.map(str::to_string),
+                    line: conditional.line,
+                },
+                Severity::Error,
+                "IF without ENDIF",
+            );
+        }
+    }
+
+    fn push(&mut self, text: &str, origin: Origin, keep: bool) {
+        if keep {
+            self.text.push_str(text);
+        }
+        self.text.push('\n');
+        self.origins.push(origin);
+    }
+
+    fn condition(&self, directive: &str, operand: &str, origin: &Origin) -> bool {
+        let defined = |name: &str| {
+            let name = self.name(name);
+            self.defines.iter().any(|d| d.label == name)
+        };
+        match directive {
+            "IFDEF" => defined(operand),
+            "IFNDEF" => !defined(operand),
+            _ => match evaluate(&self.name(operand), &self.defines, 0) {
+                Ok(value) => value.value != 0,
+                Err(e) => {
+                    report_at(
+                        origin.clone(),
+                        Severity::Error,
+                        &format!("Cannot evaluate IF '{}': {}", operand, e),
+                    );
+                    false
+                }
+            },
+        }
+    }
+
+    fn name(&self, name: &str) -> String {
+        if self.options.fold_case {
+            name.to_uppercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn include(&mut self, operand: &str, origin: &Origin, dir: Option<&Path>) {
+        let name = operand.trim_matches('\'');
+        let Some(path) = self.find(name, dir) else {
+            report_at(
+                origin.clone(),
+                Severity::Error,
+                &format!("Cannot find INCLUDE file '{}'", name),
+            );
+            return;
+        };
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if let Some(start) = self.includes.iter().position(|p| *p == canonical) {
+            let chain: Vec<String> = self.includes[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|p| p.display().to_string())
+                .collect();
+            report_at(
+                origin.clone(),
+                Severity::Error,
+                &format!("Circular INCLUDE: {}", chain.join(" -> ")),
+            );
+            return;
+        }
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                report_at(
+                    origin.clone(),
+                    Severity::Error,
+                    &format!("Cannot read INCLUDE file '{}': {}", path.display(), e),
+                );
+                return;
+            }
+        };
+        self.includes.push(canonical);
+        let file = path.display().to_string();
+        self.read(&source, Some(&file), path.parent());
+        self.includes.pop();
+    }
+
+    // next to the including file first, then along the include path
+    fn find(&self, name: &str, dir: Option<&Path>) -> Option<PathBuf> {
+        let name = Path::new(name);
+        if name.is_absolute() {
+            return name.is_file().then(|| name.to_path_buf());
+        }
+        dir.into_iter()
+            .chain(self.options.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+}
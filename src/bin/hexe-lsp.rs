@@ -0,0 +1,4 @@
+// Language server for SIC/XE assembly, spoken over stdio
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    hexe::lsp::server::run()
+}
@@ -1,10 +1,12 @@
 use crate::error::{log_error, log_info, log_warning};
 use crate::predefined::common::{
-    AddressFlags, Command, DisAssembledToken, Instruction, OBJECTPROGRAM, ObjectRecord, OpCode, Reg,
+    AddressFlags, Command, DisAssembledToken, Instruction, LINEMAP, OBJECTPROGRAM, ObjectRecord,
+    OpCode, Reg,
 };
-use crate::predefined::opcode::reverse_optab;
+use crate::predefined::opcode::{get_instruction_format, reverse_optab};
 use crate::predefined::registers::reverse_register_map;
 use hex;
+use std::collections::BTreeSet;
 
 // Notes point to remember:::--->
 
@@ -19,6 +21,13 @@ pub fn disassemble() -> Vec<DisAssembledToken> {
     let mut locctr: u32;
     let mut parsed_dissassembled_code: Vec<DisAssembledToken> = Vec::new();
     let modification_addresses: Vec<(u32, u8)> = Vec::new();
+    // an assembled program knows where each instruction and constant starts
+    let boundaries: BTreeSet<u32> = LINEMAP
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|line| line.address)
+        .collect();
 
     for lines in OBJECTPROGRAM.lock().unwrap().iter() {
         match lines {
@@ -36,7 +45,7 @@ pub fn disassemble() -> Vec<DisAssembledToken> {
             ObjectRecord::Text {
                 start,
                 length,
-                objcodes,
+                bytes,
             } => {
                 locctr = *start;
                 log_info(&format!(
@@ -44,12 +53,15 @@ pub fn disassemble() -> Vec<DisAssembledToken> {
                     start, length
                 ));
 
-                for item in objcodes.iter() {
+                for item in split_record(*start, bytes, &boundaries)
+                    .into_iter()
+                    .map(hex::encode_upper)
+                {
                     let instruction_size = match item.len() / 2 {
                         1 => {
                             //format 1
                             let reverse_table = reverse_optab();
-                            let opcode = u8::from_str_radix(item, 16).expect("Invalid hex string");
+                            let opcode = u8::from_str_radix(&item, 16).expect("Invalid hex string");
                             let instr_name = reverse_table.get(&opcode);
 
                             let instr = Instruction {
@@ -74,7 +86,7 @@ pub fn disassemble() -> Vec<DisAssembledToken> {
                         }
                         2 => {
                             //format 2
-                            let bytes = hex::decode(item).expect("Invalid hex string");
+                            let bytes = hex::decode(&item).expect("Invalid hex string");
                             let reverse_table: std::collections::HashMap<u8, (&'static str, u8)> =
                                 reverse_optab();
                             let instr_name = reverse_table.get(&bytes[0]);
@@ -119,7 +131,7 @@ pub fn disassemble() -> Vec<DisAssembledToken> {
                         }
                         3 => {
                             //format 3
-                            let bytes = hex::decode(item).expect("invalid hex string");
+                            let bytes = hex::decode(&item).expect("invalid hex string");
                             let reverse_table = reverse_optab();
                             let opcode = bytes[0] & 0xFC;
                             let instr_name = reverse_table.get(&opcode);
@@ -157,7 +169,7 @@ pub fn disassemble() -> Vec<DisAssembledToken> {
                         }
                         4 => {
                             //format 4
-                            let bytes = hex::decode(item).expect("invalid hex string");
+                            let bytes = hex::decode(&item).expect("invalid hex string");
                             let reverse_table = reverse_optab();
                             let opcode = bytes[0] & 0xFC;
                             let instr_name = reverse_table.get(&opcode);
@@ -335,3 +347,61 @@ fn format_disassembled_instruction(token: &DisAssembledToken) -> String {
         }
     }
 }
+
+// Splits the bytes of a T record at the known instruction addresses first, and
+// guesses inside the pieces, which only happens for code no source came with
+pub fn split_record<'a>(start: u32, bytes: &'a [u8], boundaries: &BTreeSet<u32>) -> Vec<&'a [u8]> {
+    let end = start + bytes.len() as u32;
+    let mut pieces = Vec::new();
+    let mut from = 0;
+    for address in boundaries.range(start + 1..end) {
+        let at = (address - start) as usize;
+        pieces.extend(split_instructions(&bytes[from..at]));
+        from = at;
+    }
+    pieces.extend(split_instructions(&bytes[from..]));
+    pieces
+}
+
+// Splits the bytes of a T record into instructions. Format 1 and 2 opcodes match
+// exactly, format 3 ones without the n and i bits, and are format 4 when e is set
+// and what follows still decodes. A byte that starts no instruction, or one cut
+// off by the record's end, stands alone.
+pub fn split_instructions(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut instructions = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (instruction, tail) = rest.split_at(guess_size(rest));
+        instructions.push(instruction);
+        rest = tail;
+    }
+    instructions
+}
+
+fn guess_size(bytes: &[u8]) -> usize {
+    match instruction_size(bytes) {
+        Some(4) if bytes.len() >= 4 && decodes(&bytes[4..]) => 4,
+        Some(3 | 4) if bytes.len() >= 3 => 3,
+        Some(size) if size <= bytes.len() => size,
+        _ => 1,
+    }
+}
+
+// whether `bytes` is empty or starts with an instruction that fits
+fn decodes(bytes: &[u8]) -> bool {
+    bytes.is_empty() || instruction_size(bytes).is_some_and(|size| size.min(3) <= bytes.len())
+}
+
+// the size of the instruction `bytes` starts with, None if it is not an opcode
+pub fn instruction_size(bytes: &[u8]) -> Option<usize> {
+    let first = *bytes.first()?;
+    match get_instruction_format(first) {
+        Some(1) => return Some(1),
+        Some(2) => return Some(2),
+        _ => {}
+    }
+    get_instruction_format(first & 0xFC)?;
+    // SIC instructions (n = i = 0) have no e bit
+    let extended = first & 0x03 != 0 && bytes.get(1).is_some_and(|b| b & 0x10 != 0);
+    Some(if extended { 4 } else { 3 })
+}
@@ -2,6 +2,7 @@ pub mod assembler;
 pub mod disassembler;
 pub mod error;
 pub mod loader;
+pub mod lsp;
 pub mod predefined;
 pub mod simulator;
 pub mod tui;
@@ -0,0 +1,351 @@
+use super::loader::loader;
+use crate::predefined::common::ObjectRecord;
+use std::path::Path;
+
+// Memory images for other tools: a raw binary, Intel HEX or Motorola S-records.
+// An image is the runs of memory a program's T records fill, read back from the
+// machine's memory, plus the entry point the formats that have one carry along.
+
+// a T record holds at most 30 bytes (0x1E) of object code
+const MAX_TEXT_LENGTH: usize = 0x1E;
+const BYTES_PER_LINE: usize = 16;
+// H, T and E records write addresses in six hex digits
+const MAX_ADDRESS: u32 = 0xFFFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub name: String,
+    pub segments: Vec<(u32, Vec<u8>)>, // start address and bytes of each run
+    pub entry: Option<u32>,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "bin" => Some(ImageFormat::Binary),
+            "hex" | "ihex" => Some(ImageFormat::IntelHex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(ImageFormat::SRecord),
+            _ => None,
+        }
+    }
+}
+
+// the runs of memory the T records fill, with the bytes `memory` holds there now
+pub fn image(records: &[ObjectRecord], memory: &[u8]) -> Image {
+    let mut ranges: Vec<(u32, u32)> = records
+        .iter()
+        .filter_map(|r| match r {
+            ObjectRecord::Text { start, bytes, .. } => Some((*start, bytes.len() as u32)),
+            _ => None,
+        })
+        .filter(|(_, length)| *length > 0)
+        .collect();
+    ranges.sort();
+
+    let mut segments: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (start, length) in ranges {
+        let end = (start + length) as usize;
+        let bytes = memory.get(start as usize..end).unwrap_or_default();
+        match segments.last_mut() {
+            Some((last, data)) if *last as usize + data.len() >= start as usize => {
+                // overlapping or adjacent runs become one
+                let overlap = *last as usize + data.len() - start as usize;
+                data.extend_from_slice(bytes.get(overlap..).unwrap_or_default());
+            }
+            _ => segments.push((start, bytes.to_vec())),
+        }
+    }
+
+    Image {
+        name: records
+            .iter()
+            .find_map(|r| match r {
+                ObjectRecord::Header { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        segments,
+        entry: records.iter().find_map(|r| match r {
+            ObjectRecord::End { start } => Some(*start),
+            _ => None,
+        }),
+    }
+}
+
+// H, T and E records for an image, T records split at 30 bytes
+pub fn object_program(image: &Image) -> Vec<ObjectRecord> {
+    let start = image.segments.iter().map(|(s, _)| *s).min().unwrap_or(0);
+    let end = image
+        .segments
+        .iter()
+        .map(|(s, data)| s + data.len() as u32)
+        .max()
+        .unwrap_or(0);
+    let mut records = vec![ObjectRecord::Header {
+        name: image.name.clone(),
+        start,
+        length: end - start,
+    }];
+    for (address, data) in image.segments.iter() {
+        for (i, chunk) in data.chunks(MAX_TEXT_LENGTH).enumerate() {
+            records.push(ObjectRecord::Text {
+                start: address + (i * MAX_TEXT_LENGTH) as u32,
+                length: chunk.len() as u8,
+                bytes: chunk.to_vec(),
+            });
+        }
+    }
+    records.push(ObjectRecord::End {
+        start: image.entry.unwrap_or(start),
+    });
+    records
+}
+
+// loads an image like an object program, through its text form
+pub fn load(image: &Image) -> Vec<ObjectRecord> {
+    let text: Vec<String> = object_program(image)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    loader(text.join("\n"))
+}
+
+pub fn export(image: &Image, format: ImageFormat) -> Vec<u8> {
+    match format {
+        ImageFormat::Binary => binary(image),
+        ImageFormat::IntelHex => intel_hex(image).into_bytes(),
+        ImageFormat::SRecord => s_records(image).into_bytes(),
+    }
+}
+
+// `base` is where a raw binary starts, the other formats carry their addresses
+pub fn import(data: &[u8], format: ImageFormat, base: u32) -> Result<Image, String> {
+    match format {
+        ImageFormat::Binary => {
+            let mut segments = Vec::new();
+            add_data(&mut segments, base, data)?;
+            Ok(Image {
+                name: String::new(),
+                segments,
+                entry: None,
+            })
+        }
+        ImageFormat::IntelHex => read_intel_hex(&text(data)?),
+        ImageFormat::SRecord => read_s_records(&text(data)?),
+    }
+}
+
+fn text(data: &[u8]) -> Result<String, String> {
+    String::from_utf8(data.to_vec()).map_err(|_| "image is not a text file".to_string())
+}
+
+// from the lowest to the highest address, gaps filled with zeros
+fn binary(image: &Image) -> Vec<u8> {
+    let Some(start) = image.segments.iter().map(|(s, _)| *s).min() else {
+        return Vec::new();
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    for (address, data) in image.segments.iter() {
+        let offset = (address - start) as usize;
+        if bytes.len() < offset + data.len() {
+            bytes.resize(offset + data.len(), 0);
+        }
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+    }
+    bytes
+}
+
+fn intel_hex(image: &Image) -> String {
+    let mut out = String::new();
+    let mut upper: u32 = 0;
+    for (start, data) in image.segments.iter() {
+        let mut offset = 0;
+        while offset < data.len() {
+            let address = start + offset as u32;
+            // a line must not cross into the next 64K
+            let room = 0x10000 - (address & 0xFFFF) as usize;
+            let length = BYTES_PER_LINE.min(room).min(data.len() - offset);
+            if address >> 16 != upper {
+                upper = address >> 16;
+                out.push_str(&intel_hex_record(0, 0x04, &(upper as u16).to_be_bytes()));
+            }
+            out.push_str(&intel_hex_record(
+                address as u16,
+                0x00,
+                &data[offset..offset + length],
+            ));
+            offset += length;
+        }
+    }
+    if let Some(entry) = image.entry {
+        out.push_str(&intel_hex_record(0, 0x05, &entry.to_be_bytes()));
+    }
+    out.push_str(&intel_hex_record(0, 0x01, &[]));
+    out
+}
+
+fn intel_hex_record(address: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+    format!(":{}\n", hex::encode_upper(bytes))
+}
+
+fn read_intel_hex(text: &str) -> Result<Image, String> {
+    let mut image = Image {
+        name: String::new(),
+        segments: Vec::new(),
+        entry: None,
+    };
+    let mut base: u32 = 0;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let bytes = line
+            .strip_prefix(':')
+            .and_then(|hex| hex::decode(hex).ok())
+            .ok_or_else(|| error("not an Intel HEX record"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(error("bad checksum"));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        let value = || data.iter().fold(0u32, |value, b| value << 8 | *b as u32);
+        match bytes[3] {
+            0x00 => add_data(&mut image.segments, base + address, data).map_err(|e| error(&e))?,
+            0x01 => break,
+            0x02 => base = value() << 4,
+            0x03 => {
+                image.entry =
+                    Some(entry((value() >> 16 << 4) + (value() & 0xFFFF)).map_err(|e| error(&e))?)
+            }
+            0x04 => base = value() << 16,
+            0x05 => image.entry = Some(entry(value()).map_err(|e| error(&e))?),
+            kind => return Err(error(&format!("unknown record type {:02X}", kind))),
+        }
+    }
+    Ok(image)
+}
+
+// S0 with the program name, S2 data with 24-bit addresses, S8 with the entry point
+fn s_records(image: &Image) -> String {
+    let mut out = s_record(0, &[0, 0], image.name.as_bytes());
+    for (start, data) in image.segments.iter() {
+        for (i, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+            let address = start + (i * BYTES_PER_LINE) as u32;
+            out.push_str(&s_record(2, &address.to_be_bytes()[1..], chunk));
+        }
+    }
+    let entry = image
+        .entry
+        .or(image.segments.first().map(|(s, _)| *s))
+        .unwrap_or(0);
+    out.push_str(&s_record(8, &entry.to_be_bytes()[1..], &[]));
+    out
+}
+
+fn s_record(kind: u8, address: &[u8], data: &[u8]) -> String {
+    let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+    bytes.extend_from_slice(address);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(!sum);
+    format!("S{}{}\n", kind, hex::encode_upper(bytes))
+}
+
+fn read_s_records(text: &str) -> Result<Image, String> {
+    let mut image = Image {
+        name: String::new(),
+        segments: Vec::new(),
+        entry: None,
+    };
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let (kind, bytes) = line
+            .strip_prefix('S')
+            .and_then(|rest| {
+                let kind = rest.chars().next()?.to_digit(10)?;
+                Some((kind, hex::decode(&rest[1..]).ok()?))
+            })
+            .ok_or_else(|| error("not an S-record"))?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error("record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(error("bad checksum"));
+        }
+        let address_size = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(error(&format!("unknown record type S{}", kind))),
+        };
+        if bytes.len() < address_size + 2 {
+            return Err(error("record too short for its address"));
+        }
+        let address = bytes[1..=address_size]
+            .iter()
+            .fold(0u32, |value, b| value << 8 | *b as u32);
+        let data = &bytes[address_size + 1..bytes.len() - 1];
+        match kind {
+            0 => image.name = String::from_utf8_lossy(data).trim().to_string(),
+            1..=3 => add_data(&mut image.segments, address, data).map_err(|e| error(&e))?,
+            7..=9 => image.entry = Some(entry(address).map_err(|e| error(&e))?),
+            _ => {} // S5 and S6 only count the records
+        }
+    }
+    Ok(image)
+}
+
+// data that follows the last run directly is added to it, data past the addresses
+// an object program can hold is refused
+fn add_data(segments: &mut Vec<(u32, Vec<u8>)>, address: u32, data: &[u8]) -> Result<(), String> {
+    u32::try_from(data.len())
+        .ok()
+        .and_then(|length| address.checked_add(length))
+        .filter(|end| *end <= MAX_ADDRESS + 1)
+        .ok_or_else(|| {
+            format!(
+                "data at {:X} runs past address {:06X}",
+                address, MAX_ADDRESS
+            )
+        })?;
+    match segments.last_mut() {
+        Some((start, bytes)) if *start + bytes.len() as u32 == address => {
+            bytes.extend_from_slice(data)
+        }
+        _ => segments.push((address, data.to_vec())),
+    }
+    Ok(())
+}
+
+fn entry(address: u32) -> Result<u32, String> {
+    if address > MAX_ADDRESS {
+        return Err(format!(
+            "entry point {:X} is past address {:06X}",
+            address, MAX_ADDRESS
+        ));
+    }
+    Ok(address)
+}
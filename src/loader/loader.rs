@@ -1,6 +1,7 @@
-use crate::error::{log_info, log_warning};
-use crate::predefined::common::{OBJECTPROGRAM, ObjectRecord};
-use crate::predefined::opcode::get_instruction_format;
+use crate::error::{log_error, log_info, log_warning};
+use crate::predefined::common::{
+    LINEMAP, LITERALTABLE, OBJECTPROGRAM, ObjectRecord, SYMBOLTABLE, SymbolType,
+};
 
 //Object program structure
 //H == 3byte name ,3 byte starting addr of program,  3byte length
@@ -8,6 +9,9 @@ use crate::predefined::opcode::get_instruction_format;
 //E == 3byte starting address of executable instructions
 //M == 3byte starting address, 1byte length(in half bytes), 1/2byte modification flag, 3byte external symbol
 
+// an M record patches at most a whole word, 6 half-bytes
+pub const MAX_MODIFICATION_LENGTH: u8 = 6;
+
 pub fn loader(buffer: String) -> Vec<ObjectRecord> {
     let mut parsed_obj_prog = OBJECTPROGRAM.lock().unwrap();
 
@@ -16,11 +20,7 @@ pub fn loader(buffer: String) -> Vec<ObjectRecord> {
         if trimmed_line.is_empty() {
             continue;
         }
-        // Remove spaces and ^ characters from the line
-        let filtered_line = trimmed_line
-            .chars()
-            .filter(|&c| c != ' ' && c != '^')
-            .collect::<String>();
+        let filtered_line = normalize(trimmed_line);
         trimmed_line = &filtered_line;
 
         let record_header: char = match trimmed_line.chars().next() {
@@ -68,80 +68,30 @@ pub fn loader(buffer: String) -> Vec<ObjectRecord> {
                 let start_addr = u32::from_str_radix(start_addr_hex, 16).unwrap_or(0);
                 let length = u8::from_str_radix(length_hex, 16).unwrap_or(0);
 
-                let mut objcodes: Vec<String> = Vec::new();
-                let mut i = 0;
-
-                while i < obj_code.len() {
-                    if i + 1 >= obj_code.len() {
+                // the bytes are kept as they are, a bad pair still takes its place
+                let mut bytes: Vec<u8> = Vec::new();
+                for pair in obj_code.as_bytes().chunks(2) {
+                    let pair = String::from_utf8_lossy(pair);
+                    if pair.len() < 2 {
+                        log_warning(&format!(
+                            "Odd hex digit {} at the end of text record {:06X}, dropped",
+                            pair, start_addr
+                        ));
                         break;
                     }
-
-                    let s = &obj_code[i..i + 2];
-
-                    if let Ok(byte_val) = u8::from_str_radix(s, 16) {
-                        let mut processed = false;
-
-                        // First check for format 1 and 2 (exact opcode match)
-                        if let Some(format) = get_instruction_format(byte_val) {
-                            match format {
-                                1 => {
-                                    objcodes.push(obj_code[i..i + 2].to_string());
-                                    i += 2;
-                                    processed = true;
-                                }
-                                2 => {
-                                    if i + 4 <= obj_code.len() {
-                                        objcodes.push(obj_code[i..i + 4].to_string());
-                                        i += 4;
-                                        processed = true;
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-
-                        // If not processed, check for format 3/4 (masked opcode)
-                        if !processed {
-                            let opcode = byte_val & 0xFC;
-                            if let Some(format) = get_instruction_format(opcode) {
-                                match format {
-                                    3 => {
-                                        if i + 6 <= obj_code.len() {
-                                            objcodes.push(obj_code[i..i + 6].to_string());
-                                            i += 6;
-                                            processed = true;
-                                        }
-                                    }
-                                    4 => {
-                                        if i + 8 <= obj_code.len() {
-                                            objcodes.push(obj_code[i..i + 8].to_string());
-                                            i += 8;
-                                            processed = true;
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-
-                        //  If nothing was processed, increment by 2 to avoid infinite loop
-                        if !processed {
-                            log_warning(&format!(
-                                "Unknown opcode: 0x{:02X}, skipping 2 bytes",
-                                byte_val
-                            ));
-                            i += 2;
-                        }
-                    } else {
-                        log_warning(&format!("Invalid hex string: {}, skipping 2 bytes", s));
-                        i += 2;
-                    }
+                    bytes.push(u8::from_str_radix(&pair, 16).unwrap_or_else(|_| {
+                        log_warning(&format!(
+                            "Invalid hex string {} in text record {:06X}, loaded as 00",
+                            pair, start_addr
+                        ));
+                        0
+                    }));
                 }
 
                 let parsed_obj = ObjectRecord::Text {
                     start: start_addr,
                     length,
-                    objcodes,
+                    bytes,
                 };
                 parsed_obj_prog.push(parsed_obj);
             }
@@ -153,8 +103,7 @@ pub fn loader(buffer: String) -> Vec<ObjectRecord> {
                 parsed_obj_prog.push(parsed_obj);
             }
             'M' => {
-                //TODO: implement properly
-                // Modification record: M + 6char address + 2char length (half-bytes) + sign(1bit) + variable
+                // M + 6 char address + 2 char length (half-bytes) + sign + symbol
                 if record.len() < 8 {
                     log_warning("Invalid modification record: too short");
                     continue;
@@ -165,14 +114,20 @@ pub fn loader(buffer: String) -> Vec<ObjectRecord> {
 
                 let address = u32::from_str_radix(addr_hex, 16).unwrap_or(0);
                 let length = u8::from_str_radix(length_hex, 16).unwrap_or(0);
+                let sign = !record[8..].starts_with('-');
+                let variable = record[8..].trim_start_matches(['+', '-']).to_string();
 
                 log_info(&format!(
                     "Loaded modification record: address {:06X}, length {} half-bytes",
                     address, length
                 ));
 
-                // let parsed_obj = ObjectRecord::Modification { address, length};
-                // parsed_obj_prog.push(parsed_obj);
+                parsed_obj_prog.push(ObjectRecord::Modification {
+                    address,
+                    length,
+                    sign,
+                    variable,
+                });
             }
             _ => {
                 log_warning(&format!("Unknown record type: {}", record_header));
@@ -184,3 +139,166 @@ pub fn loader(buffer: String) -> Vec<ObjectRecord> {
     // }
     parsed_obj_prog.clone()
 }
+
+// A record without its spaces and ^ separators, except the blanks padding the program
+// name in the header. The loader and the validator both read records this way.
+pub fn normalize(record: &str) -> String {
+    match record.strip_prefix('H') {
+        Some(rest) => {
+            let rest: String = rest.chars().filter(|&c| c != '^').collect();
+            let name: String = rest.chars().take(6).collect();
+            let fields: String = rest.chars().skip(6).filter(|&c| c != ' ').collect();
+            format!("H{}{}", name, fields)
+        }
+        None => record.chars().filter(|&c| c != ' ' && c != '^').collect(),
+    }
+}
+
+// Moves a program to `load_at`. The fields named by M records for the program itself
+// are patched by the distance moved, then every address in the records is shifted.
+// M records for another program's symbols need a linker and are left as they are.
+pub fn relocate(records: &mut [ObjectRecord], load_at: u32) {
+    let Some((name, origin)) = records.iter().find_map(|r| match r {
+        ObjectRecord::Header { name, start, .. } => Some((name.clone(), *start)),
+        _ => None,
+    }) else {
+        log_warning("Cannot relocate a program without a header record");
+        return;
+    };
+    let delta = load_at.wrapping_sub(origin);
+
+    let patches: Vec<(u32, u8, bool)> = records
+        .iter()
+        .filter_map(|r| match r {
+            ObjectRecord::Modification {
+                address,
+                length,
+                sign,
+                variable,
+            } if variable.trim() == name => Some((*address, *length, *sign)),
+            ObjectRecord::Modification { variable, .. } => {
+                log_warning(&format!(
+                    "Modification for external symbol {} needs a linker, left as is",
+                    variable
+                ));
+                None
+            }
+            _ => None,
+        })
+        .collect();
+    for (address, length, sign) in patches {
+        if length > MAX_MODIFICATION_LENGTH {
+            log_error(&format!(
+                "Modification record at {:06X} is {} half-bytes long, at most {} can be patched",
+                address, length, MAX_MODIFICATION_LENGTH
+            ));
+            continue;
+        }
+        let amount = if sign { delta } else { delta.wrapping_neg() };
+        if !patch(records, address, length, amount) {
+            log_warning(&format!(
+                "Modification record at {:06X} points outside the text records",
+                address
+            ));
+        }
+    }
+
+    for record in records.iter_mut() {
+        match record {
+            ObjectRecord::Header { start, .. } => *start = load_at,
+            ObjectRecord::Text { start, .. } | ObjectRecord::End { start } => {
+                *start = start.wrapping_add(delta)
+            }
+            ObjectRecord::Modification { address, .. } => *address = address.wrapping_add(delta),
+        }
+    }
+    log_info(&format!(
+        "Relocated {} from {:06X} to {:06X}",
+        name, origin, load_at
+    ));
+}
+
+// Relocates the program loaded last, and the assembler's symbols, literals and line
+// addresses with it
+pub fn relocate_program(load_at: u32) {
+    let mut program = OBJECTPROGRAM.lock().unwrap();
+    let first = program
+        .iter()
+        .rposition(|r| matches!(r, ObjectRecord::Header { .. }))
+        .unwrap_or(0);
+    let Some(origin) = program.get(first).and_then(|r| match r {
+        ObjectRecord::Header { start, .. } => Some(*start),
+        _ => None,
+    }) else {
+        log_warning("Cannot relocate a program without a header record");
+        return;
+    };
+    relocate(&mut program[first..], load_at);
+
+    let delta = load_at.wrapping_sub(origin);
+    for symbol in SYMBOLTABLE.lock().unwrap().iter_mut() {
+        if symbol.symbol_type == SymbolType::Relative {
+            symbol.address = symbol.address.wrapping_add(delta);
+        }
+    }
+    for literal in LITERALTABLE.lock().unwrap().iter_mut() {
+        literal.address = literal.address.map(|a| a.wrapping_add(delta));
+        for reference in literal.references.iter_mut() {
+            *reference = reference.wrapping_add(delta);
+        }
+    }
+    for line in LINEMAP.lock().unwrap().iter_mut() {
+        line.address = line.address.wrapping_add(delta);
+    }
+}
+
+// Starts the program loaded last at `entry` instead of the address in its E record
+pub fn set_entry(entry: u32) {
+    let mut program = OBJECTPROGRAM.lock().unwrap();
+    match program
+        .iter_mut()
+        .rev()
+        .find(|r| matches!(r, ObjectRecord::End { .. }))
+    {
+        Some(ObjectRecord::End { start }) => *start = entry,
+        _ => program.push(ObjectRecord::End { start: entry }),
+    }
+}
+
+// adds `amount` to the `length` half-bytes ending the field at `address`
+fn patch(records: &mut [ObjectRecord], address: u32, length: u8, amount: u32) -> bool {
+    let size = (length as u32).div_ceil(2);
+    let Some(places) = (0..size)
+        .map(|offset| byte_at(records, address + offset))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    let byte = |records: &[ObjectRecord], (record, i): (usize, usize)| match &records[record] {
+        ObjectRecord::Text { bytes, .. } => bytes[i] as u32,
+        _ => 0,
+    };
+    let value = places
+        .iter()
+        .fold(0u32, |value, place| value << 8 | byte(records, *place));
+    let mask = (1u32 << (length as u32 * 4)) - 1;
+    let value = (value & !mask) | (value.wrapping_add(amount) & mask);
+    for (n, (record, i)) in places.into_iter().enumerate() {
+        if let ObjectRecord::Text { bytes, .. } = &mut records[record] {
+            bytes[i] = (value >> ((size as usize - 1 - n) * 8)) as u8;
+        }
+    }
+    true
+}
+
+// record and byte index of the byte at `address` in the text records
+fn byte_at(records: &[ObjectRecord], address: u32) -> Option<(usize, usize)> {
+    records.iter().enumerate().find_map(|(record, r)| match r {
+        ObjectRecord::Text { start, bytes, .. }
+            if (*start..*start + bytes.len() as u32).contains(&address) =>
+        {
+            Some((record, (address - start) as usize))
+        }
+        _ => None,
+    })
+}
@@ -1,2 +1,4 @@
 #![allow(clippy::module_inception)]
+pub mod image;
 pub mod loader;
+pub mod validate;
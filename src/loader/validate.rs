@@ -0,0 +1,240 @@
+use super::loader::{MAX_MODIFICATION_LENGTH, normalize};
+use crate::predefined::common::{Diagnostic, Severity};
+
+// A strict reading of an object program. The loader makes the best of bad records,
+// this reports every record that breaks the format at the line it is on: the order
+// of H, T, M and E, the hex fields, T lengths and where T and M records point.
+
+const MAX_TEXT_LENGTH: u32 = 0x1E;
+
+// the program between an H record and its E record
+struct Program {
+    name: String,
+    start: u32,
+    end: u32,
+    texts: Vec<(u32, u32, usize)>, // start, end and line of each T record
+}
+
+pub fn validate(buffer: &str) -> Vec<Diagnostic> {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    let mut error = |line: usize, message: String| {
+        errors.push(Diagnostic {
+            line,
+            file: None,
+            severity: Severity::Error,
+            message,
+        })
+    };
+    let mut program: Option<Program> = None;
+    let mut seen_header = false;
+    let mut last_line = 0;
+
+    for (index, text) in buffer.lines().enumerate() {
+        let line = index + 1;
+        let record = normalize(text.trim());
+        if record.is_empty() {
+            continue;
+        }
+        last_line = line;
+        if !record.is_ascii() {
+            error(line, "Record has characters that are not ASCII".to_string());
+            continue;
+        }
+        let (kind, fields) = record.split_at(1);
+        match kind {
+            "H" => {
+                if let Some(open) = &program {
+                    error(
+                        line,
+                        format!("H record before the E record of program {}", open.name),
+                    );
+                }
+                seen_header = true;
+                program = None;
+                if fields.len() != 18 {
+                    error(
+                        line,
+                        format!("H record has {} characters, expected 18", fields.len()),
+                    );
+                    continue;
+                }
+                let name = &fields[0..6];
+                if !legal_name(name) {
+                    error(line, format!("Illegal program name '{}'", name.trim_end()));
+                }
+                let (Some(start), Some(length)) = (
+                    hex(line, &fields[6..12], "start", &mut error),
+                    hex(line, &fields[12..18], "length", &mut error),
+                ) else {
+                    continue;
+                };
+                program = Some(Program {
+                    name: name.trim_end().to_string(),
+                    start,
+                    end: start + length,
+                    texts: Vec::new(),
+                });
+            }
+            "T" | "M" | "E" if program.is_none() => {
+                let message = if seen_header {
+                    format!("{} record after the E record", kind)
+                } else {
+                    format!("{} record before the H record", kind)
+                };
+                error(line, message);
+            }
+            "T" => {
+                let program = program.as_mut().unwrap();
+                if fields.len() < 8 {
+                    error(
+                        line,
+                        "T record is too short for its start and length".to_string(),
+                    );
+                    continue;
+                }
+                let (Some(start), Some(length)) = (
+                    hex(line, &fields[0..6], "start", &mut error),
+                    hex(line, &fields[6..8], "length", &mut error),
+                ) else {
+                    continue;
+                };
+                let code = &fields[8..];
+                if code.len() % 2 != 0 || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+                    error(
+                        line,
+                        format!("T record object code '{}' is not hex bytes", code),
+                    );
+                    continue;
+                }
+                let bytes = (code.len() / 2) as u32;
+                if length != bytes {
+                    error(
+                        line,
+                        format!(
+                            "T record length {:02X} but it holds {:02X} bytes",
+                            length, bytes
+                        ),
+                    );
+                }
+                if bytes > MAX_TEXT_LENGTH {
+                    error(
+                        line,
+                        format!("T record holds {:02X} bytes, at most 1E are allowed", bytes),
+                    );
+                }
+                let end = start + bytes;
+                if start < program.start || end > program.end {
+                    error(
+                        line,
+                        format!(
+                            "T record {:06X}-{:06X} is outside program {} ({:06X}-{:06X})",
+                            start, end, program.name, program.start, program.end
+                        ),
+                    );
+                }
+                if let Some((_, _, other)) = program
+                    .texts
+                    .iter()
+                    .find(|(s, e, _)| start < *e && *s < end)
+                {
+                    error(
+                        line,
+                        format!("T record overlaps the T record on line {}", other),
+                    );
+                }
+                program.texts.push((start, end, line));
+            }
+            "M" => {
+                let program = program.as_ref().unwrap();
+                if fields.len() < 8 {
+                    error(
+                        line,
+                        "M record is too short for its address and length".to_string(),
+                    );
+                    continue;
+                }
+                let (Some(address), Some(length)) = (
+                    hex(line, &fields[0..6], "address", &mut error),
+                    hex(line, &fields[6..8], "length", &mut error),
+                ) else {
+                    continue;
+                };
+                let symbol = &fields[8..];
+                let signed = |sign: char| symbol.strip_prefix(sign).is_some_and(legal_name);
+                if !symbol.is_empty() && !signed('+') && !signed('-') {
+                    error(line, format!("M record has an illegal symbol '{}'", symbol));
+                }
+                if length > MAX_MODIFICATION_LENGTH as u32 {
+                    error(
+                        line,
+                        format!(
+                            "M record length {} is over {} half-bytes",
+                            length, MAX_MODIFICATION_LENGTH
+                        ),
+                    );
+                    continue;
+                }
+                let end = address + length.div_ceil(2);
+                if length == 0 || address < program.start || end > program.end {
+                    error(
+                        line,
+                        format!(
+                            "M record field {:06X}, {} half-bytes, is outside program {}",
+                            address, length, program.name
+                        ),
+                    );
+                }
+            }
+            "E" => {
+                let open = program.take().unwrap();
+                // a control section other than the first may leave out its entry point
+                if fields.is_empty() {
+                    continue;
+                }
+                if fields.len() != 6 {
+                    error(
+                        line,
+                        format!("E record has {} characters, expected 6", fields.len()),
+                    );
+                    continue;
+                }
+                if let Some(entry) = hex(line, fields, "entry point", &mut error)
+                    && (entry < open.start || entry >= open.end.max(open.start + 1))
+                {
+                    error(
+                        line,
+                        format!(
+                            "Entry point {:06X} is outside program {} ({:06X}-{:06X})",
+                            entry, open.name, open.start, open.end
+                        ),
+                    );
+                }
+            }
+            _ => error(line, format!("Unknown record type '{}'", kind)),
+        }
+    }
+
+    if let Some(open) = program {
+        error(last_line, format!("Program {} has no E record", open.name));
+    } else if !seen_header {
+        error(last_line.max(1), "No H record".to_string());
+    }
+    errors
+}
+
+// a letter, then letters and digits, then blanks to pad the field
+fn legal_name(name: &str) -> bool {
+    let name = name.trim_end();
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric())
+        && name.len() <= 6
+}
+
+fn hex(line: usize, field: &str, what: &str, error: &mut impl FnMut(usize, String)) -> Option<u32> {
+    let value = u32::from_str_radix(field, 16).ok();
+    if value.is_none() {
+        error(line, format!("Bad hex {} '{}'", what, field));
+    }
+    value
+}
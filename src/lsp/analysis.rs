@@ -0,0 +1,352 @@
+use crate::assembler::constant::Quotes;
+use crate::assembler::cst::{self, CstKind};
+use crate::assembler::lexer::{SpannedToken, Token, lex};
+use crate::assembler::lint::lint;
+use crate::assembler::pass2asm::pass2asm;
+use crate::predefined::common::{
+    ASSEMBLER_LOCK, AssemblerOptions, DIAGNOSTICS, SYMBOLTABLE, Severity, Span, SymbolTable,
+    SymbolType, clear_poison,
+};
+use crate::predefined::directive::directives;
+use crate::predefined::opcode::build_optab;
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, Hover, HoverContents,
+    MarkupContent, MarkupKind, NumberOrString, Position, Range, SemanticToken, SemanticTokenType,
+};
+use std::panic::{self, AssertUnwindSafe};
+
+// Everything the language server answers from: one source file assembled once, with
+// its diagnostics, symbol table and tokens. Columns are counted in characters, which
+// matches the client's UTF-16 positions for the ASCII sources the assembler reads.
+
+pub struct Document {
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<SymbolTable>,
+    tokens: Vec<Vec<SpannedToken>>,
+    options: AssemblerOptions,
+}
+
+// index of each type in this legend is what a semantic token carries
+pub const TOKEN_TYPES: [SemanticTokenType; 6] = [
+    SemanticTokenType::VARIABLE, // label or symbol
+    SemanticTokenType::KEYWORD,  // instruction
+    SemanticTokenType::MACRO,    // directive
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING, // C'..' and X'..' constants
+    SemanticTokenType::COMMENT,
+];
+
+const REGISTERS: [&str; 9] = ["A", "X", "L", "B", "S", "T", "F", "PC", "SW"];
+
+impl Document {
+    pub fn new(source: String, options: &AssemblerOptions) -> Self {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // a bug the source trips in the assembler is one more error, the server goes on
+        let analysis = panic::catch_unwind(AssertUnwindSafe(|| analyse(&source, options)));
+        let (diagnostics, symbols, tokens) = analysis.unwrap_or_else(|payload| {
+            clear_poison();
+            let reason = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            let message = format!("The assembler failed on this file: {}", reason);
+            let error = line_diagnostic(&source, 1, DiagnosticSeverity::ERROR, None, &message);
+            (vec![error], Vec::new(), Vec::new())
+        });
+        Self {
+            source,
+            diagnostics,
+            symbols,
+            tokens,
+            options: options.clone(),
+        }
+    }
+
+    // where the label under the cursor is defined
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let (name, _) = self.symbol_at(position)?;
+        self.tokens
+            .iter()
+            .flatten()
+            .find(|t| matches!(&t.token, Token::Label(label) if self.same_symbol(label, &name)))
+            .map(|t| range(t.span))
+    }
+
+    // every use of the symbol under the cursor, and its definition if asked for
+    pub fn references(&self, position: Position, include_declaration: bool) -> Vec<Range> {
+        let Some((name, _)) = self.symbol_at(position) else {
+            return Vec::new();
+        };
+        let mut references: Vec<Range> = Vec::new();
+        for token in self.tokens.iter().flatten() {
+            match &token.token {
+                Token::Label(label) if include_declaration && self.same_symbol(label, &name) => {
+                    references.push(range(token.span));
+                }
+                Token::Operand1(_) | Token::Operand2(_) => {
+                    references.extend(
+                        self.symbols_in(token.span)
+                            .into_iter()
+                            .filter(|(symbol, _)| self.same_symbol(symbol, &name))
+                            .map(|(_, span)| range(span)),
+                    );
+                }
+                _ => {}
+            }
+        }
+        references
+    }
+
+    // opcode and format of a mnemonic, address of a symbol
+    pub fn hover(&self, position: Position) -> Option<Hover> {
+        let token = self.token_at(position)?;
+        let (value, span) = match &token.token {
+            Token::Instruction(instr) => (
+                format!(
+                    "**{}**: opcode 0x{:02X}, format {}",
+                    instr.instr, instr.opcode.code, instr.opcode.format
+                ),
+                token.span,
+            ),
+            Token::Directive(directive) => (
+                format!("**{}**: assembler directive", directive),
+                token.span,
+            ),
+            Token::Label(_) | Token::Operand1(_) | Token::Operand2(_) => {
+                let (name, span) = self.symbol_at(position)?;
+                let symbol = self
+                    .symbols
+                    .iter()
+                    .find(|s| self.same_symbol(&s.label, &name))?;
+                let kind = match symbol.symbol_type {
+                    SymbolType::Absolute => "absolute",
+                    SymbolType::Relative => "relative",
+                    SymbolType::External => "external",
+                };
+                (
+                    format!("**{}**: {:06X} ({})", symbol.label, symbol.address, kind),
+                    span,
+                )
+            }
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range(span)),
+        })
+    }
+
+    // tokens of the whole file, each relative to the one before as the protocol wants
+    pub fn semantic_tokens(&self) -> Vec<SemanticToken> {
+        let directives = directives();
+        let tree = cst::parse(&self.source, &self.options);
+        let mut tokens: Vec<SemanticToken> = Vec::new();
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for line in tree.lines.iter() {
+            for element in line.elements.iter() {
+                let token_type = match element.kind {
+                    CstKind::Label => 0,
+                    CstKind::Mnemonic if directives.contains(&element.text.to_uppercase()) => 2,
+                    CstKind::Mnemonic => 1,
+                    CstKind::Operand => operand_type(&element.text),
+                    CstKind::Comment => 5,
+                    CstKind::Whitespace | CstKind::Comma | CstKind::Error => continue,
+                };
+                let line = (element.span.line - 1) as u32;
+                let start = element.span.start as u32;
+                tokens.push(SemanticToken {
+                    delta_line: line - previous_line,
+                    delta_start: if line == previous_line {
+                        start - previous_start
+                    } else {
+                        start
+                    },
+                    length: (element.span.end - element.span.start) as u32,
+                    token_type,
+                    token_modifiers_bitset: 0,
+                });
+                (previous_line, previous_start) = (line, start);
+            }
+        }
+        tokens
+    }
+
+    fn token_at(&self, position: Position) -> Option<&SpannedToken> {
+        let line = position.line as usize + 1;
+        let column = position.character as usize;
+        self.tokens
+            .iter()
+            .flatten()
+            .find(|t| t.span.line == line && (t.span.start..=t.span.end).contains(&column))
+    }
+
+    // the symbol a label or operand has under the cursor, `BUFFER` in `BUFFER,X`
+    fn symbol_at(&self, position: Position) -> Option<(String, Span)> {
+        let token = self.token_at(position)?;
+        let column = position.character as usize;
+        match token.token {
+            Token::Label(_) | Token::Operand1(_) | Token::Operand2(_) => self
+                .symbols_in(token.span)
+                .into_iter()
+                .find(|(_, span)| (span.start..=span.end).contains(&column)),
+            _ => None,
+        }
+    }
+
+    // Names in a field's source text, outside quotes. Register names and the `C`/`X`
+    // in front of a quoted constant are not symbols.
+    fn symbols_in(&self, span: Span) -> Vec<(String, Span)> {
+        let Some(text) = self.source.lines().nth(span.line - 1) else {
+            return Vec::new();
+        };
+        let chars: Vec<char> = text.chars().collect();
+        let end = span.end.min(chars.len());
+        let mut symbols: Vec<(String, Span)> = Vec::new();
+        let mut quotes = Quotes::default();
+        let mut i = span.start;
+        while i < end {
+            let ch = chars[i];
+            if quotes.step(ch) || !(ch.is_ascii_alphanumeric() || ch == '_') {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < end && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            // a number, `0FFF` included
+            if ch.is_ascii_digit() {
+                continue;
+            }
+            let name: String = chars[start..i].iter().collect();
+            if chars.get(i) != Some(&'\'') && !REGISTERS.contains(&name.to_uppercase().as_str()) {
+                symbols.push((
+                    name,
+                    Span {
+                        line: span.line,
+                        start,
+                        end: i,
+                    },
+                ));
+            }
+        }
+        symbols
+    }
+
+    fn same_symbol(&self, a: &str, b: &str) -> bool {
+        if self.options.fold_case {
+            a.eq_ignore_ascii_case(b)
+        } else {
+            a == b
+        }
+    }
+}
+
+// mnemonics with their opcode and format, then the directives
+pub fn completions() -> Vec<CompletionItem> {
+    let mut optab: Vec<_> = build_optab().into_iter().collect();
+    optab.sort_by_key(|(name, _)| *name);
+    let instructions = optab.into_iter().map(|(name, opcode)| CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        detail: Some(format!(
+            "opcode 0x{:02X}, format {}",
+            opcode.code, opcode.format
+        )),
+        ..Default::default()
+    });
+    let directives = directives().into_iter().map(|name| CompletionItem {
+        label: name,
+        kind: Some(CompletionItemKind::KEYWORD),
+        detail: Some("assembler directive".to_string()),
+        ..Default::default()
+    });
+    instructions.chain(directives).collect()
+}
+
+fn operand_type(text: &str) -> u32 {
+    let value = text.trim_start_matches(['#', '@', '=']);
+    if value.contains('\'') {
+        4
+    } else if value.starts_with(|c: char| c.is_ascii_digit()) {
+        3
+    } else {
+        0
+    }
+}
+
+fn range(span: Span) -> Range {
+    let line = (span.line - 1) as u32;
+    Range {
+        start: Position::new(line, span.start as u32),
+        end: Position::new(line, span.end as u32),
+    }
+}
+
+// the assembler reports by line, so a diagnostic covers the whole line
+// the diagnostics, symbols and tokens of a source, with ASSEMBLER_LOCK held
+fn analyse(
+    source: &str,
+    options: &AssemblerOptions,
+) -> (Vec<Diagnostic>, Vec<SymbolTable>, Vec<Vec<SpannedToken>>) {
+    pass2asm(source, options);
+    let symbols = SYMBOLTABLE.lock().unwrap().clone();
+    let mut diagnostics: Vec<Diagnostic> = DIAGNOSTICS
+        .lock()
+        .unwrap()
+        .iter()
+        // errors inside included files have no line of this file to mark
+        .filter(|d| d.file.is_none())
+        .map(|d| {
+            let severity = match d.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+            };
+            line_diagnostic(source, d.line, severity, None, &d.message)
+        })
+        .collect();
+    // lint starts the diagnostics over, so it runs after they are read
+    for warning in lint(source, options) {
+        let code = format!("{}/{}", warning.code.code(), warning.code.name());
+        diagnostics.push(line_diagnostic(
+            source,
+            warning.line,
+            DiagnosticSeverity::WARNING,
+            Some(code),
+            &warning.message,
+        ));
+    }
+    // its errors are among the diagnostics already
+    let (tokens, _) = lex(source, options);
+    (diagnostics, symbols, tokens)
+}
+
+fn line_diagnostic(
+    source: &str,
+    line: usize,
+    severity: DiagnosticSeverity,
+    code: Option<String>,
+    message: &str,
+) -> Diagnostic {
+    let width = source
+        .lines()
+        .nth(line.saturating_sub(1))
+        .map_or(0, |text| text.trim_end().chars().count());
+    Diagnostic {
+        range: range(Span {
+            line: line.max(1),
+            start: 0,
+            end: width,
+        }),
+        severity: Some(severity),
+        code: code.map(NumberOrString::String),
+        source: Some("hexe".to_string()),
+        message: message.to_string(),
+        ..Default::default()
+    }
+}
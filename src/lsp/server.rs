@@ -0,0 +1,217 @@
+use super::analysis::{Document, TOKEN_TYPES, completions};
+use crate::predefined::common::AssemblerOptions;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationMethod, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, GotoDefinition, HoverRequest, References, Request as RequestMethod,
+    SemanticTokensFullRequest,
+};
+use lsp_types::{
+    CompletionOptions, CompletionResponse, GotoDefinitionParams, GotoDefinitionResponse,
+    HoverParams, HoverProviderCapability, Location, OneOf, PublishDiagnosticsParams,
+    ReferenceParams, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Uri,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+// `hexe-lsp`: a language server for SIC/XE sources over stdio. Every open file is
+// assembled again on each change, with the default assembler options and its own
+// directory to look for INCLUDE files in.
+
+type ServerResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+pub fn run() -> ServerResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    // the writer thread only finishes once the connection is gone
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+// answers on `connection` until the client shuts the server down
+pub fn serve(connection: &Connection) -> ServerResult<()> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    let mut documents: HashMap<String, Document> = HashMap::new();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                let Some((uri, text)) = handle_notification(notification)? else {
+                    continue;
+                };
+                let diagnostics = match text {
+                    Some(text) => {
+                        let document = Document::new(text, &options(&uri));
+                        let diagnostics = document.diagnostics.clone();
+                        documents.insert(uri.as_str().to_string(), document);
+                        diagnostics
+                    }
+                    None => {
+                        documents.remove(uri.as_str());
+                        Vec::new()
+                    }
+                };
+                let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+                connection
+                    .sender
+                    .send(Message::Notification(Notification::new(
+                        PublishDiagnostics::METHOD.to_string(),
+                        params,
+                    )))?;
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn options(uri: &Uri) -> AssemblerOptions {
+    let source = file_path(uri);
+    let dir = source
+        .as_deref()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    AssemblerOptions {
+        include_paths: dir.into_iter().collect(),
+        source,
+        ..Default::default()
+    }
+}
+
+// the path of a file: URI, percent-decoded, with the slash before a Windows drive
+// (file:///C:/src/a.asm) left out
+pub fn file_path(uri: &Uri) -> Option<PathBuf> {
+    if !uri.scheme()?.as_str().eq_ignore_ascii_case("file") {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_string().ok()?;
+    let drive = path.strip_prefix('/').filter(|rest| {
+        let bytes = rest.as_bytes();
+        bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+    });
+    Some(PathBuf::from(drive.unwrap_or(&path)))
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: TOKEN_TYPES.to_vec(),
+                    token_modifiers: Vec::new(),
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
+}
+
+// The file a notification opened or changed, with its new text, or closed, without
+fn handle_notification(notification: Notification) -> ServerResult<Option<(Uri, Option<String>)>> {
+    Ok(match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: <DidOpenTextDocument as NotificationMethod>::Params =
+                serde_json::from_value(notification.params)?;
+            Some((params.text_document.uri, Some(params.text_document.text)))
+        }
+        // with full sync the last change holds the whole text
+        DidChangeTextDocument::METHOD => {
+            let params: <DidChangeTextDocument as NotificationMethod>::Params =
+                serde_json::from_value(notification.params)?;
+            params
+                .content_changes
+                .into_iter()
+                .last()
+                .map(|change| (params.text_document.uri, Some(change.text)))
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: <DidCloseTextDocument as NotificationMethod>::Params =
+                serde_json::from_value(notification.params)?;
+            Some((params.text_document.uri, None))
+        }
+        _ => None,
+    })
+}
+
+fn handle_request(documents: &HashMap<String, Document>, request: Request) -> Response {
+    let id = request.id.clone();
+    match request.method.as_str() {
+        GotoDefinition::METHOD => respond(id, request, |params: GotoDefinitionParams| {
+            let position = params.text_document_position_params;
+            let uri = position.text_document.uri;
+            documents
+                .get(uri.as_str())
+                .and_then(|d| d.definition(position.position))
+                .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+        }),
+        References::METHOD => respond(id, request, |params: ReferenceParams| {
+            let position = params.text_document_position;
+            let uri = position.text_document.uri;
+            documents.get(uri.as_str()).map(|d| {
+                d.references(position.position, params.context.include_declaration)
+                    .into_iter()
+                    .map(|range| Location::new(uri.clone(), range))
+                    .collect::<Vec<_>>()
+            })
+        }),
+        HoverRequest::METHOD => respond(id, request, |params: HoverParams| {
+            let position = params.text_document_position_params;
+            documents
+                .get(position.text_document.uri.as_str())
+                .and_then(|d| d.hover(position.position))
+        }),
+        Completion::METHOD => respond(id, request, |_: serde_json::Value| {
+            Some(CompletionResponse::Array(completions()))
+        }),
+        SemanticTokensFullRequest::METHOD => {
+            respond(id, request, |params: SemanticTokensParams| {
+                documents.get(params.text_document.uri.as_str()).map(|d| {
+                    SemanticTokensResult::Tokens(SemanticTokens {
+                        result_id: None,
+                        data: d.semantic_tokens(),
+                    })
+                })
+            })
+        }
+        method => Response::new_err(
+            id,
+            ErrorCode::MethodNotFound as i32,
+            format!("unsupported request '{}'", method),
+        ),
+    }
+}
+
+// reads the parameters, and answers null when `handler` has nothing
+fn respond<P: DeserializeOwned, R: Serialize>(
+    id: RequestId,
+    request: Request,
+    handler: impl FnOnce(P) -> Option<R>,
+) -> Response {
+    match serde_json::from_value::<P>(request.params) {
+        Ok(params) => Response::new_ok(id, handler(params)),
+        Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+    }
+}
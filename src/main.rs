@@ -1,8 +1,8 @@
-use std::env;
+use clap::{Args, Parser, Subcommand};
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 mod assembler;
 mod disassembler;
 mod error;
@@ -10,9 +10,12 @@ mod loader;
 mod predefined;
 mod simulator;
 mod tui;
-use assembler::pass2asm;
-use error::{log_error, log_info};
-use simulator::sim::calling_tui;
+use assembler::{constant, pass2asm};
+use error::{log_error, log_info, log_warning};
+use loader::image::{self, ImageFormat};
+use predefined::common::AssemblerOptions;
+use simulator::sim::{Simulator, calling_tui};
+use simulator::trace::{Trace, TraceFilter};
 
 //when Assembly file is given
 // Assembly Program (.asm)
@@ -36,54 +39,387 @@ use simulator::sim::calling_tui;
 //    ↓
 // Simulator (Disassemble + tui +  Execution)
 
-fn main() -> Result<(), Box<dyn Error>> {
-    log_info("HexE Simulator started");
+#[derive(Parser)]
+#[command(
+    name = "hexe",
+    about = "SIC/XE assembler, loader and simulator",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Assembly source (.asm), object program (.txt) or memory image (.bin, .hex, .srec)
+    #[arg(required = true)]
+    file: Option<String>,
+
+    #[command(flatten)]
+    assembler: AssemblerArgs,
+
+    #[command(flatten)]
+    load: LoadArgs,
+
+    /// Stop a run, until or finish after this many instructions, so a program that
+    /// never ends cannot hang the debugger
+    #[arg(long, value_name = "N", default_value_t = simulator::sim::DEFAULT_INSTRUCTION_LIMIT)]
+    max_instructions: usize,
+}
+
+#[derive(Args)]
+struct LoadArgs {
+    /// Load the program at this hex address instead of its START, applying its M records.
+    /// A raw binary image is loaded here, at 0 without it
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    load_at: Option<u32>,
+
+    /// Start the program at this hex address instead of the one in its E or start record
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    entry: Option<u32>,
+
+    /// Check every record of an object program (.txt) and refuse to load it if one is bad
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Args)]
+struct AssemblerArgs {
+    /// Radix of a plain START operand
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(2..=16))]
+    start_radix: u32,
+
+    /// Widen format 3 instructions whose operand is out of range to format 4
+    #[arg(long)]
+    relax: bool,
+
+    /// Treat symbols case-insensitively
+    #[arg(long)]
+    fold_case: bool,
+
+    /// Read the source in fixed columns (label 1-8, mnemonic 10-15, operand 17-35)
+    #[arg(long)]
+    fixed_columns: bool,
+
+    /// Define a symbol for IF and IFDEF, the value defaults to 1
+    #[arg(short = 'D', long = "define", value_name = "NAME[=VALUE]", value_parser = parse_define)]
+    defines: Vec<(String, u32)>,
+
+    /// Directory searched for INCLUDE files, after the including file's own
+    #[arg(short = 'I', long = "include-path", value_name = "DIR")]
+    include_paths: Vec<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Rewrite assembly sources with aligned columns and upper-case mnemonics
+    Fmt {
+        /// Source files to format in place
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Only report files that are not formatted, exit with 1 if there are any
+        #[arg(long)]
+        check: bool,
+
+        /// Print the formatted source instead of writing it back
+        #[arg(long, conflicts_with = "check")]
+        stdout: bool,
+    },
+    /// Warn about common SIC/XE mistakes, exit with 1 if there are any
+    Lint {
+        /// Source files to check
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        #[command(flatten)]
+        assembler: AssemblerArgs,
+    },
+    /// Write the loaded memory image as a raw binary, Intel HEX or Motorola S-records
+    Export {
+        /// Assembly source, object program or memory image to load
+        file: String,
+
+        /// Image to write, the format follows its extension (.bin, .hex, .srec)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        assembler: AssemblerArgs,
+
+        #[command(flatten)]
+        load: LoadArgs,
+    },
+    /// Run a program without the debugger, logging every instruction it executes
+    Trace {
+        /// Assembly source, object program or memory image to run
+        file: String,
+
+        /// Trace to write, CSV for .csv and JSON lines for .jsonl
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Only log the instructions at START..END, both ends included, labels work too
+        #[arg(long, value_name = "START..END")]
+        range: Option<String>,
+
+        /// Only log these opcode classes: load, store, arithmetic, compare, register,
+        /// jump, io and misc
+        #[arg(long, value_name = "CLASS,...")]
+        class: Option<String>,
+
+        /// Stop after this many instructions, so a program that never ends still does
+        #[arg(long, value_name = "N", default_value_t = simulator::sim::DEFAULT_INSTRUCTION_LIMIT)]
+        max_instructions: usize,
+
+        #[command(flatten)]
+        assembler: AssemblerArgs,
+
+        #[command(flatten)]
+        load: LoadArgs,
+    },
+}
+
+impl AssemblerArgs {
+    // INCLUDEs in `source` are looked up next to it first
+    fn options(&self, source: &str) -> AssemblerOptions {
+        let dir = Path::new(source).parent().map(Path::to_path_buf);
+        AssemblerOptions {
+            start_radix: self.start_radix,
+            relax: self.relax,
+            fold_case: self.fold_case,
+            fixed_columns: self.fixed_columns,
+            include_paths: dir.into_iter().chain(self.include_paths.clone()).collect(),
+            defines: self.defines.clone(),
+            source: Some(PathBuf::from(source)),
+        }
+    }
+}
 
-    let args: Vec<String> = env::args().collect();
+fn parse_address(address: &str) -> Result<u32, String> {
+    constant::parse_number(address, 16)
+}
 
-    if args.len() != 2 {
-        log_error("Usage: cargo run -- <filename>");
-        eprintln!("Usage: cargo run -- <filename>");
-        return Ok(());
+fn parse_define(define: &str) -> Result<(String, u32), String> {
+    match define.split_once('=') {
+        Some((name, value)) => Ok((name.to_string(), constant::parse_number(value, 10)?)),
+        None => Ok((define.to_string(), 1)),
     }
+}
 
-    let file_path: String = args[1].clone();
-    let ext = Path::new(&file_path)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("");
-    let mut file = File::open(&file_path).expect("Failed to open the file");
-    let mut buffer = String::new();
-    file.read_to_string(&mut buffer)?;
-    match ext {
-        "asm" => {
-            log_info("Assembling source file");
-            pass2asm::pass2asm(&buffer);
-            simulator::sim::calling_tui().unwrap_or_else(|e| {
-                log_error(&format!("TUI error: {}", e));
-                eprintln!("Error: {}", e);
-            });
+fn run_lint(files: &[String], assembler: &AssemblerArgs) -> Result<(), Box<dyn Error>> {
+    let mut found = false;
+    for path in files {
+        let source = std::fs::read_to_string(path)?;
+        let options = assembler.options(path);
+        // the assembler's own errors come first, they usually explain the warnings. Both
+        // passes run for them, operands that do not resolve are only found in pass 2
+        pass2asm::pass2asm(&source, &options);
+        let diagnostics = predefined::common::DIAGNOSTICS.lock().unwrap().clone();
+        let warnings = assembler::lint::lint(&source, &options);
+        for diagnostic in diagnostics.iter() {
+            println!("{}: {}", path, diagnostic);
+            found = true;
         }
-        "txt" => {
-            log_info("Loading object file");
-            loader::loader::loader(buffer.clone());
-            simulator::sim::calling_tui().unwrap_or_else(|e| {
-                log_error(&format!("TUI error: {}", e));
-                eprintln!("Error: {}", e);
-            });
+        for warning in warnings.iter() {
+            println!("{}: {}", path, warning);
+            found = true;
         }
-        _ => {
-            log_error(&format!("Unsupported file extension: {}", ext));
-            eprintln!("Error: Unsupported file type. Use .asm or .txt files.");
+    }
+    if found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// fills the object program from a source, an object program or a memory image
+fn load(file_path: &str, assembler: &AssemblerArgs, load: &LoadArgs) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(file_path);
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if let Some(format) = ImageFormat::from_path(path) {
+        log_info("Loading memory image");
+        let data = std::fs::read(path)?;
+        let mut image = image::import(&data, format, load.load_at.unwrap_or(0))
+            .map_err(|e| format!("{}: {}", file_path, e))?;
+        if image.name.is_empty() {
+            image.name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_uppercase();
+        }
+        if load.load_at.is_some() && format != ImageFormat::Binary {
+            log_warning("--load-at only places raw binary images, the others keep their addresses");
+        }
+        image::load(&image);
+    } else {
+        let mut buffer = String::new();
+        File::open(file_path)?.read_to_string(&mut buffer)?;
+        match ext {
+            "asm" => {
+                log_info("Assembling source file");
+                pass2asm::pass2asm(&buffer, &assembler.options(file_path));
+                for diagnostic in predefined::common::DIAGNOSTICS.lock().unwrap().iter() {
+                    eprintln!("{}: {}", file_path, diagnostic);
+                }
+            }
+            "txt" => {
+                log_info("Loading object file");
+                if load.strict {
+                    let errors = loader::validate::validate(&buffer);
+                    for error in errors.iter() {
+                        eprintln!("{}: {}", file_path, error);
+                    }
+                    if !errors.is_empty() {
+                        return Err(format!("{} is not a valid object program", file_path).into());
+                    }
+                }
+                loader::loader::loader(buffer);
+            }
+            _ => {
+                log_error(&format!("Unsupported file extension: {}", ext));
+                return Err(
+                    "Unsupported file type. Use .asm, .txt, .bin, .hex or .srec files.".into(),
+                );
+            }
+        }
+        if let Some(load_at) = load.load_at {
+            loader::loader::relocate_program(load_at);
         }
     }
+    if let Some(entry) = load.entry {
+        loader::loader::set_entry(entry);
+    }
+    Ok(())
+}
+
+fn run_export(
+    file_path: &str,
+    output: &Path,
+    assembler: &AssemblerArgs,
+    load_args: &LoadArgs,
+) -> Result<(), Box<dyn Error>> {
+    let format = ImageFormat::from_path(output).ok_or_else(|| {
+        format!(
+            "Cannot tell the image format of {}, use .bin, .hex or .srec",
+            output.display()
+        )
+    })?;
+    load(file_path, assembler, load_args)?;
+    let program = predefined::common::OBJECTPROGRAM.lock().unwrap().clone();
+    let mut machine = simulator::inistialize_machine::Machine::new();
+    machine.load_records(&program);
+    let image = image::image(&program, &machine.memory);
+    std::fs::write(output, image::export(&image, format))?;
+    log_info(&format!("Exported {} to {}", file_path, output.display()));
+    Ok(())
+}
 
-    let mut sim = simulator::sim::Simulator::new();
+// the range and classes are read after loading, so that labels can name addresses
+fn run_trace(
+    file_path: &str,
+    output: &Path,
+    range: Option<&str>,
+    class: Option<&str>,
+    max_instructions: usize,
+    assembler: &AssemblerArgs,
+    load_args: &LoadArgs,
+) -> Result<(), Box<dyn Error>> {
+    load(file_path, assembler, load_args)?;
+    let filter = TraceFilter {
+        range: range.map(TraceFilter::parse_range).transpose()?,
+        classes: class
+            .map(TraceFilter::parse_classes)
+            .transpose()?
+            .unwrap_or_default(),
+    };
+    let mut sim = Simulator::new();
     sim.load_program();
-    // sim.add_breakpoint(0x1000);
-    sim.step();
+    sim.instruction_limit = max_instructions;
+    sim.trace = Some(Trace::create(output, filter)?);
+    sim.run();
+    let Some(trace) = sim.stop_trace() else {
+        return Err(format!("Could not write the trace to {}", output.display()).into());
+    };
+    let reason = sim.stop_reason.map(|r| r.to_string()).unwrap_or_default();
+    println!(
+        "{}, {} of {} instructions traced to {}",
+        reason,
+        trace.written,
+        trace.steps,
+        output.display()
+    );
+    Ok(())
+}
+
+fn run_fmt(files: &[String], check: bool, stdout: bool) -> Result<(), Box<dyn Error>> {
+    let mut unformatted = false;
+    for path in files {
+        let source = std::fs::read_to_string(path)?;
+        let formatted = assembler::format::format_source(&source);
+        if stdout {
+            print!("{}", formatted);
+        } else if formatted != source {
+            if check {
+                println!("{} is not formatted", path);
+                unformatted = true;
+            } else {
+                std::fs::write(path, formatted)?;
+                log_info(&format!("Formatted {}", path));
+            }
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    log_info("HexE Simulator started");
+
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Commands::Fmt {
+            files,
+            check,
+            stdout,
+        }) => return run_fmt(files, *check, *stdout),
+        Some(Commands::Lint { files, assembler }) => return run_lint(files, assembler),
+        Some(Commands::Export {
+            file,
+            output,
+            assembler,
+            load,
+        }) => return run_export(file, output, assembler, load),
+        Some(Commands::Trace {
+            file,
+            output,
+            range,
+            class,
+            max_instructions,
+            assembler,
+            load,
+        }) => {
+            return run_trace(
+                file,
+                output,
+                range.as_deref(),
+                class.as_deref(),
+                *max_instructions,
+                assembler,
+                load,
+            );
+        }
+        None => {}
+    }
 
-    if let Err(e) = calling_tui() {
+    let file_path: String = cli.file.unwrap_or_default();
+    load(&file_path, &cli.assembler, &cli.load)?;
+    // an assembled program is debugged next to its source
+    let path = Path::new(&file_path);
+    let source = (path.extension().and_then(|e| e.to_str()) == Some("asm")).then_some(path);
+    if let Err(e) = calling_tui(source, cli.max_instructions) {
+        log_error(&format!("TUI error: {}", e));
         eprintln!("Error occurred in calling_tui: {}", e);
         std::process::exit(1);
     }
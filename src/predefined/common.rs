@@ -1,9 +1,30 @@
 // use super::opcode::OpCode;
 use once_cell::sync::Lazy;
+use std::path::PathBuf;
 use std::sync::Mutex;
 pub static OBJECTPROGRAM: Lazy<Mutex<Vec<ObjectRecord>>> = Lazy::new(|| Mutex::new(vec![]));
 pub static SYMBOLTABLE: Lazy<Mutex<Vec<SymbolTable>>> = Lazy::new(|| Mutex::new(vec![]));
 pub static LITERALTABLE: Lazy<Mutex<Vec<LiteralTable>>> = Lazy::new(|| Mutex::new(vec![]));
+pub static DIAGNOSTICS: Lazy<Mutex<Vec<Diagnostic>>> = Lazy::new(|| Mutex::new(vec![]));
+// origin of every line the preprocessor handed to the assembler, by line number - 1
+pub static SOURCEMAP: Lazy<Mutex<Vec<Origin>>> = Lazy::new(|| Mutex::new(vec![]));
+// address of every source line that assembled to object code, in source order
+pub static LINEMAP: Lazy<Mutex<Vec<SourceLine>>> = Lazy::new(|| Mutex::new(vec![]));
+// held by whoever assembles and then reads the tables back, as they are shared
+#[allow(dead_code)]
+pub static ASSEMBLER_LOCK: Mutex<()> = Mutex::new(());
+
+// a panic while a table was held poisons it, whoever caught the panic overwrites
+// the tables with the next assembly anyway
+#[allow(dead_code)]
+pub fn clear_poison() {
+    OBJECTPROGRAM.clear_poison();
+    SYMBOLTABLE.clear_poison();
+    LITERALTABLE.clear_poison();
+    DIAGNOSTICS.clear_poison();
+    SOURCEMAP.clear_poison();
+    LINEMAP.clear_poison();
+}
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -16,7 +37,8 @@ pub enum ObjectRecord {
     Text {
         start: u32,
         length: u8,
-        objcodes: Vec<String>,
+        // the record's bytes as they are, the decoder finds the instructions
+        bytes: Vec<u8>,
     },
     Modification {
         address: u32,
@@ -29,6 +51,44 @@ pub enum ObjectRecord {
     },
 }
 
+// the record as a line of an object program file
+impl std::fmt::Display for ObjectRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectRecord::Header {
+                name,
+                start,
+                length,
+            } => write!(f, "H{:<6.6}{:06X}{:06X}", name, start, length),
+            ObjectRecord::Text {
+                start,
+                length,
+                bytes,
+            } => write!(
+                f,
+                "T{:06X}{:02X}{}",
+                start,
+                length,
+                hex::encode_upper(bytes)
+            ),
+            ObjectRecord::Modification {
+                address,
+                length,
+                sign,
+                variable,
+            } => write!(
+                f,
+                "M{:06X}{:02X}{}{}",
+                address,
+                length,
+                if *sign { '+' } else { '-' },
+                variable
+            ),
+            ObjectRecord::End { start } => write!(f, "E{:06X}", start),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 #[allow(dead_code)]
 pub struct Instruction {
@@ -59,6 +119,7 @@ pub struct OpCode {
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct ParsedToken {
+    pub line: usize, // 1-based line in the source file
     pub label: Option<String>,
     pub command: Command,
     pub operand1: Option<String>,
@@ -70,6 +131,26 @@ pub struct ParsedToken {
 pub struct SymbolTable {
     pub label: String,
     pub address: u32,
+    pub symbol_type: SymbolType,
+}
+
+// absolute values stay put when the program is moved, relative ones (labels, `*`) move
+// with it and external ones are only known once the referenced program is linked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    Absolute,
+    Relative,
+    External,
+}
+
+impl std::fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymbolType::Absolute => write!(f, "ABS"),
+            SymbolType::Relative => write!(f, "REL"),
+            SymbolType::External => write!(f, "EXT"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -116,4 +197,89 @@ pub struct LiteralTable {
     pub value: String,        // e.g., "454F46" (hex)
     pub length: u32,          // Length in bytes
     pub address: Option<u32>, // Address assigned in Pass 2
+    pub relative: bool,       // =* holds an address, relocated like a WORD
+    pub references: Vec<u32>, // addresses of the instructions using this entry
+}
+
+// Where a token was written: 1-based line and 0-based columns, end exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(dead_code)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// file and line an assembled line was read from, `file` is None for the main source
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Origin {
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+// a source line and the address its instruction or data was assembled at
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub address: u32,
+    pub origin: Origin,
+}
+
+// problems found while assembling, tied to the source line they come from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub file: Option<String>, // the included file the line is in
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        match &self.file {
+            Some(file) => write!(
+                f,
+                "line {} of {}: {}: {}",
+                self.line, file, severity, self.message
+            ),
+            None => write!(f, "line {}: {}: {}", self.line, severity, self.message),
+        }
+    }
+}
+
+// settings that change how the assembler reads a source file, filled in from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblerOptions {
+    pub start_radix: u32, // radix of a plain START operand, SIC programs write it in hex
+    pub relax: bool,      // widen format 3 instructions whose operand does not fit
+    pub fold_case: bool,  // read symbols case-insensitively by upper-casing them
+    pub fixed_columns: bool, // punched-card layout: label 1-8, mnemonic 10-15, operand 17-35
+    pub include_paths: Vec<PathBuf>, // searched for INCLUDE files after the including file's own directory
+    pub defines: Vec<(String, u32)>, // symbols IF and IFDEF see, from `-D NAME=value`
+    pub source: Option<PathBuf>,     // the file being assembled, so an INCLUDE of it is a cycle
+}
+
+impl Default for AssemblerOptions {
+    fn default() -> Self {
+        Self {
+            start_radix: 16,
+            relax: false,
+            fold_case: false,
+            fixed_columns: false,
+            include_paths: Vec::new(),
+            defines: Vec::new(),
+            source: None,
+        }
+    }
 }
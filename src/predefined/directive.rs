@@ -11,5 +11,12 @@ pub fn directives() -> Vec<String> {
         "EQU".to_string(),
         "ORG".to_string(),
         "LTORG".to_string(),
+        "EXTREF".to_string(),
+        "INCLUDE".to_string(),
+        "IF".to_string(),
+        "IFDEF".to_string(),
+        "IFNDEF".to_string(),
+        "ELSE".to_string(),
+        "ENDIF".to_string(),
     ]
 }
@@ -0,0 +1,428 @@
+use super::sim::{RunTarget, Simulator};
+use super::trace::{Trace, TraceFilter};
+use crate::assembler::constant::{parse_bytes, parse_number};
+use crate::loader::image::{self, ImageFormat};
+use crate::loader::loader::loader;
+use crate::predefined::common::{LINEMAP, LITERALTABLE, OBJECTPROGRAM, SYMBOLTABLE};
+use std::path::Path;
+
+// The commands typed at the `:` prompt of the debugger, named after their gdb
+// counterparts. Wherever an address is expected a label from the symbol table works
+// too, with an optional +/- hex offset: `LOOP`, `BUFFER+3`, `0x1030` or `1030`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCommand {
+    Break(Option<u32>),    // break [LOC], lists the breakpoints without one
+    Delete(Option<usize>), // delete [N], every breakpoint without a number
+    Examine {
+        count: usize,
+        format: char, // x hex, d decimal, c character
+        unit: char,   // b byte, w 3-byte word
+        address: u32,
+    },
+    SetRegister {
+        register: String,
+        value: String,
+    },
+    SetMemory {
+        address: u32,
+        bytes: Vec<u8>,
+    },
+    Until(Option<u32>),
+    Next, // steps over a JSUB
+    Finish,
+    Goto(u32),
+    Find(Vec<u8>),
+    Load(String),
+    Memory(u32),     // mem LOC, scrolls the active memory window of the TUI
+    Search(Vec<u8>), // search BYTES, the next match after the memory window
+    Pane {
+        name: String,
+        visible: bool, // show PANE or hide PANE
+    },
+    Trace(Option<(String, TraceFilter)>), // trace FILE [START..END] [CLASS,...], or trace off
+}
+
+pub fn parse_command(line: &str) -> Result<DebugCommand, String> {
+    let line = line.trim();
+    let (name, rest) = match line.find(|c: char| c.is_whitespace() || c == '/') {
+        Some(i) => (&line[..i], line[i..].trim_start()),
+        None => (line, ""),
+    };
+    let location = |rest: &str| -> Result<Option<u32>, String> {
+        if rest.is_empty() {
+            Ok(None)
+        } else {
+            address(rest).map(Some)
+        }
+    };
+    match name {
+        "" => Err("Empty command".to_string()),
+        "break" | "b" => Ok(DebugCommand::Break(location(rest)?)),
+        "delete" | "d" => match rest {
+            "" => Ok(DebugCommand::Delete(None)),
+            number => number
+                .parse::<usize>()
+                .map(|n| DebugCommand::Delete(Some(n)))
+                .map_err(|_| format!("Invalid breakpoint number '{}'", number)),
+        },
+        "x" => examine(rest),
+        "set" => set(rest),
+        "until" | "u" => Ok(DebugCommand::Until(location(rest)?)),
+        "next" | "over" => Ok(DebugCommand::Next),
+        "finish" | "out" => Ok(DebugCommand::Finish),
+        "goto" | "jump" => match location(rest)? {
+            Some(address) => Ok(DebugCommand::Goto(address)),
+            None => Err("goto needs an address".to_string()),
+        },
+        "find" => Ok(DebugCommand::Find(bytes(rest)?)),
+        "mem" => match location(rest)? {
+            Some(address) => Ok(DebugCommand::Memory(address)),
+            None => Err("mem needs an address".to_string()),
+        },
+        "search" => Ok(DebugCommand::Search(bytes(rest)?)),
+        "show" | "hide" => match rest {
+            "" => Err(format!("{} needs a pane", name)),
+            pane => Ok(DebugCommand::Pane {
+                name: pane.to_string(),
+                visible: name == "show",
+            }),
+        },
+        "trace" => match rest {
+            "" => Err("trace needs a file, or off".to_string()),
+            "off" => Ok(DebugCommand::Trace(None)),
+            _ => trace(rest),
+        },
+        "load" => match rest {
+            "" => Err("load needs a file".to_string()),
+            file => Ok(DebugCommand::Load(file.to_string())),
+        },
+        _ => Err(format!("Unknown command '{}'", name)),
+    }
+}
+
+// a label with an optional hex offset, or a number, hex by default
+pub fn address(text: &str) -> Result<u32, String> {
+    let text = text.trim();
+    let (base, offset) = match text
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '+' || *c == '-')
+    {
+        Some((i, sign)) => {
+            let offset = parse_number(text[i + 1..].trim(), 16)?;
+            (
+                text[..i].trim(),
+                if sign == '+' {
+                    offset as i64
+                } else {
+                    -(offset as i64)
+                },
+            )
+        }
+        None => (text, 0),
+    };
+    let symbol = SYMBOLTABLE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|symbol| symbol.label.eq_ignore_ascii_case(base))
+        .map(|symbol| symbol.address);
+    let base = match symbol {
+        Some(address) => address,
+        None => parse_number(base, 16).map_err(|_| format!("No symbol or address '{}'", base))?,
+    };
+    u32::try_from(base as i64 + offset).map_err(|_| format!("Address '{}' is negative", text))
+}
+
+// x/NFU LOC, each of N, F and U may be left out
+fn examine(rest: &str) -> Result<DebugCommand, String> {
+    let (spec, location) = match rest.strip_prefix('/') {
+        Some(spec) => spec.split_once(char::is_whitespace).unwrap_or((spec, "")),
+        None => ("", rest),
+    };
+    let digits = spec.chars().take_while(char::is_ascii_digit).count();
+    let count = match &spec[..digits] {
+        "" => 1,
+        count => count.parse::<usize>().map_err(|e| e.to_string())?,
+    };
+    let (mut format, mut unit) = ('x', 'b');
+    for c in spec[digits..].chars() {
+        match c {
+            'x' | 'd' | 'c' => format = c,
+            'b' | 'w' => unit = c,
+            _ => return Err(format!("Unknown examine letter '{}', use x d c and b w", c)),
+        }
+    }
+    if location.trim().is_empty() {
+        return Err("x needs an address".to_string());
+    }
+    Ok(DebugCommand::Examine {
+        count,
+        format,
+        unit,
+        address: address(location)?,
+    })
+}
+
+// set REG = VALUE or set mem LOC = BYTES
+fn set(rest: &str) -> Result<DebugCommand, String> {
+    let (target, value) = rest
+        .split_once('=')
+        .ok_or_else(|| "set needs '=', as in set A = 0x41".to_string())?;
+    let (target, value) = (target.trim(), value.trim());
+    match target.strip_prefix("mem") {
+        Some(location) if location.starts_with(char::is_whitespace) => {
+            Ok(DebugCommand::SetMemory {
+                address: address(location)?,
+                bytes: bytes(value)?,
+            })
+        }
+        _ => Ok(DebugCommand::SetRegister {
+            register: target.to_uppercase(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+// trace FILE [START..END] [CLASS,...], the filters in either order
+fn trace(rest: &str) -> Result<DebugCommand, String> {
+    let mut words = rest.split_whitespace();
+    let file = words.next().unwrap_or_default().to_string();
+    let mut filter = TraceFilter::default();
+    for word in words {
+        if word.contains("..") {
+            filter.range = Some(TraceFilter::parse_range(word)?);
+        } else {
+            filter.classes = TraceFilter::parse_classes(word)?;
+        }
+    }
+    Ok(DebugCommand::Trace(Some((file, filter))))
+}
+
+// hex bytes, spaced or not, or a C'..' / X'..' constant as the assembler writes it
+fn bytes(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if text.contains('\'') {
+        return parse_bytes(text);
+    }
+    if let Some(ascii) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+        && !ascii.is_empty()
+    {
+        return Ok(ascii.as_bytes().to_vec());
+    }
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    match hex::decode(&digits) {
+        Ok(bytes) if !bytes.is_empty() => Ok(bytes),
+        _ => Err(format!(
+            "Invalid bytes '{}', expected hex such as 05 or 4C0000",
+            text
+        )),
+    }
+}
+
+impl Simulator {
+    // runs a command, returning the lines it prints
+    pub fn execute_command(&mut self, command: &DebugCommand) -> Result<Vec<String>, String> {
+        match command {
+            DebugCommand::Break(Some(address)) => {
+                self.add_breakpoint(*address);
+                let number = self.breakpoints.iter().position(|b| b == address).unwrap() + 1;
+                Ok(vec![format!("Breakpoint {} at {:06X}", number, address)])
+            }
+            DebugCommand::Break(None) if self.breakpoints.is_empty() => {
+                Ok(vec!["No breakpoints".to_string()])
+            }
+            DebugCommand::Break(None) => Ok(self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(i, address)| format!("{}  {:06X}", i + 1, address))
+                .collect()),
+            DebugCommand::Delete(None) => {
+                self.breakpoints.clear();
+                Ok(vec!["Deleted all breakpoints".to_string()])
+            }
+            DebugCommand::Delete(Some(number)) => {
+                if *number == 0 || *number > self.breakpoints.len() {
+                    return Err(format!("No breakpoint number {}", number));
+                }
+                let address = self.breakpoints.remove(number - 1);
+                Ok(vec![format!(
+                    "Deleted breakpoint {} at {:06X}",
+                    number, address
+                )])
+            }
+            DebugCommand::Examine {
+                count,
+                format,
+                unit,
+                address,
+            } => self.examine(*count, *format, *unit, *address),
+            DebugCommand::SetRegister { register, value } => {
+                self.machine.set_register(register, value)?;
+                Ok(vec![format!("{} = {}", register, value)])
+            }
+            DebugCommand::SetMemory { address, bytes } => {
+                let start = *address as usize;
+                let end = start + bytes.len();
+                if end > self.machine.memory.len() {
+                    return Err(format!("{:06X} is outside memory", address));
+                }
+                self.machine.memory[start..end].copy_from_slice(bytes);
+                self.changed = (*address..*address + bytes.len() as u32).collect();
+                self.changed_since_stop.extend(self.changed.iter().copied());
+                Ok(vec![format!(
+                    "Set {} bytes at {:06X}",
+                    bytes.len(),
+                    address
+                )])
+            }
+            DebugCommand::Until(_) | DebugCommand::Next | DebugCommand::Finish => {
+                let target = self.run_target(command).unwrap();
+                Ok(vec![self.run_until(target)])
+            }
+            DebugCommand::Goto(address) => {
+                self.machine.reg_pc = *address;
+                Ok(vec![format!("PC = {:06X}", address)])
+            }
+            DebugCommand::Find(pattern) => {
+                let found: Vec<String> = self
+                    .machine
+                    .memory
+                    .windows(pattern.len())
+                    .enumerate()
+                    .filter(|(_, window)| window == pattern)
+                    .map(|(address, _)| format!("{:06X}", address))
+                    .collect();
+                if found.is_empty() {
+                    return Ok(vec!["Not found".to_string()]);
+                }
+                Ok(found.chunks(8).map(|chunk| chunk.join(" ")).collect())
+            }
+            DebugCommand::Load(file) => self.load_file(file),
+            DebugCommand::Trace(Some((file, filter))) => {
+                self.stop_trace();
+                self.trace = Some(Trace::create(Path::new(file), filter.clone())?);
+                Ok(vec![format!("Tracing to {}", file)])
+            }
+            DebugCommand::Trace(None) => match self.stop_trace() {
+                Some(trace) => Ok(vec![format!(
+                    "Trace stopped, {} of {} instructions written",
+                    trace.written, trace.steps
+                )]),
+                None => Err("No trace is on".to_string()),
+            },
+            DebugCommand::Memory(_) | DebugCommand::Search(_) | DebugCommand::Pane { .. } => {
+                Err("mem, search, show and hide only work in the TUI".to_string())
+            }
+        }
+    }
+
+    // the first match at or after `from`, wrapping round to the start of memory
+    pub fn search_memory(&self, pattern: &[u8], from: u32) -> Option<u32> {
+        let memory = &self.machine.memory;
+        let from = (from as usize).min(memory.len());
+        let find = |start: usize, end: usize| {
+            memory
+                .get(start..end)?
+                .windows(pattern.len())
+                .position(|window| window == pattern)
+                .map(|i| (start + i) as u32)
+        };
+        if pattern.is_empty() {
+            return None;
+        }
+        find(from, memory.len()).or_else(|| find(0, (from + pattern.len() - 1).min(memory.len())))
+    }
+
+    // where until, next or finish stop, None for the other commands
+    pub fn run_target(&self, command: &DebugCommand) -> Option<RunTarget> {
+        match command {
+            DebugCommand::Until(Some(address)) => Some(RunTarget::Address(*address)),
+            // out of a loop: on to an address past the one it started from
+            DebugCommand::Until(None) => Some(RunTarget::Past(self.machine.reg_pc)),
+            DebugCommand::Next => Some(self.step_over_target()),
+            DebugCommand::Finish => Some(self.step_out_target()),
+            _ => None,
+        }
+    }
+
+    fn examine(
+        &self,
+        count: usize,
+        format: char,
+        unit: char,
+        address: u32,
+    ) -> Result<Vec<String>, String> {
+        let size = if unit == 'w' { 3 } else { 1 };
+        let start = address as usize;
+        let outside = || format!("{:06X} is outside memory", address);
+        let end = count
+            .checked_mul(size)
+            .and_then(|length| start.checked_add(length))
+            .ok_or_else(outside)?;
+        let memory = self.machine.memory.get(start..end).ok_or_else(outside)?;
+        let values: Vec<String> = memory
+            .chunks(size)
+            .map(|chunk| {
+                let value = chunk.iter().fold(0u32, |v, b| (v << 8) | *b as u32);
+                match format {
+                    'd' if size == 3 => (((value << 8) as i32) >> 8).to_string(),
+                    'd' => value.to_string(),
+                    'c' => chunk
+                        .iter()
+                        .map(|b| {
+                            if b.is_ascii_graphic() {
+                                *b as char
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect(),
+                    _ => format!("{:0width$X}", value, width = size * 2),
+                }
+            })
+            .collect();
+        let per_line = if size == 3 { 4 } else { 8 };
+        Ok(values
+            .chunks(per_line)
+            .enumerate()
+            .map(|(i, line)| format!("{:06X}: {}", start + i * per_line * size, line.join(" ")))
+            .collect())
+    }
+
+    // replaces the program with an object program or memory image
+    fn load_file(&mut self, file: &str) -> Result<Vec<String>, String> {
+        let path = Path::new(file);
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", file, e))?;
+        // parse the whole file before dropping the program it replaces
+        let image = ImageFormat::from_path(path)
+            .map(|format| image::import(&data, format, 0))
+            .transpose()
+            .map_err(|e| format!("{}: {}", file, e))?;
+        let text = match image {
+            Some(_) => String::new(),
+            None => {
+                String::from_utf8(data).map_err(|_| format!("{} is not an object program", file))?
+            }
+        };
+        OBJECTPROGRAM.lock().unwrap().clear();
+        SYMBOLTABLE.lock().unwrap().clear();
+        LITERALTABLE.lock().unwrap().clear();
+        LINEMAP.lock().unwrap().clear();
+        match image {
+            Some(image) => image::load(&image),
+            None => loader(text),
+        };
+        self.reset();
+        self.breakpoints.clear();
+        self.load_program();
+        Ok(vec![format!(
+            "Loaded {}, {} instructions from {:06X}",
+            file,
+            self.instructions.len(),
+            self.program_start
+        )])
+    }
+}
@@ -1,3 +1,6 @@
+use crate::assembler::constant::parse_number;
+use crate::predefined::common::ObjectRecord;
+
 #[derive(Debug, Clone)]
 pub struct Machine {
     // Registers
@@ -13,12 +16,63 @@ pub struct Machine {
 
     // Memory
     pub memory: Vec<u8>,
+    pub writes: Vec<u32>, // addresses stored to, collected by the simulator after each step
 
     // Control
     // pub running: bool,
     pub cc: i8, // Condition code (-1, 0, 1)
 }
 
+// The register file without the memory, to compare and show it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Registers {
+    pub a: u32,
+    pub x: u32,
+    pub l: u32,
+    pub b: u32,
+    pub s: u32,
+    pub t: u32,
+    pub f: f64,
+    pub pc: u32,
+    pub sw: u32, // with the condition code in its CC bits
+}
+
+pub const REGISTER_NAMES: [&str; 9] = ["A", "X", "L", "B", "S", "T", "F", "PC", "SW"];
+
+// SIC/XE addresses 1 MB
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+// CC is bits 6 and 7 of SW, counting from the left: 00 is <, 01 is = and 10 is >
+const CC_MASK: u32 = 0x030000;
+
+impl Registers {
+    // the integer registers by name, F has no 24-bit value
+    pub fn get(&self, name: &str) -> Option<u32> {
+        match name {
+            "A" => Some(self.a),
+            "X" => Some(self.x),
+            "L" => Some(self.l),
+            "B" => Some(self.b),
+            "S" => Some(self.s),
+            "T" => Some(self.t),
+            "PC" => Some(self.pc),
+            "SW" => Some(self.sw),
+            _ => None,
+        }
+    }
+
+    // names of the registers that differ from `before`
+    pub fn changed(&self, before: &Registers) -> Vec<&'static str> {
+        REGISTER_NAMES
+            .into_iter()
+            .filter(|name| match *name {
+                "F" => self.f != before.f,
+                name => self.get(name) != before.get(name),
+            })
+            .collect()
+    }
+}
+
 impl Default for Machine {
     fn default() -> Self {
         Self::new()
@@ -37,9 +91,85 @@ impl Machine {
             reg_f: 0.0,
             reg_pc: 0,
             reg_sw: 0,
-            memory: vec![0; 1048576], // 1MB
+            memory: vec![0; MEMORY_SIZE],
+            writes: Vec::new(),
             // running: false,
             cc: 0,
         }
     }
+
+    // copies the bytes of the T records into memory
+    pub fn load_records(&mut self, records: &[ObjectRecord]) {
+        for record in records {
+            let ObjectRecord::Text { start, bytes, .. } = record else {
+                continue;
+            };
+            for (address, byte) in (*start as usize..).zip(bytes) {
+                if let Some(cell) = self.memory.get_mut(address) {
+                    *cell = *byte;
+                }
+            }
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        let cc = match self.cc {
+            c if c < 0 => 0b00,
+            0 => 0b01,
+            _ => 0b10,
+        };
+        Registers {
+            a: self.reg_a,
+            x: self.reg_x,
+            l: self.reg_l,
+            b: self.reg_b,
+            s: self.reg_s,
+            t: self.reg_t,
+            f: self.reg_f,
+            pc: self.reg_pc,
+            sw: (self.reg_sw & !CC_MASK) | cc << 16,
+        }
+    }
+
+    // Sets a register from what was typed: hex by default, decimal after # or with a
+    // minus sign, and a decimal fraction for F. Values are cut to 24 bits.
+    pub fn set_register(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        if name == "F" {
+            self.reg_f = value
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid floating point value '{}'", value))?;
+            return Ok(());
+        }
+        let number = match value.strip_prefix('#') {
+            Some(decimal) => decimal.parse::<i32>().map(|v| v as u32),
+            None if value.starts_with('-') => value.parse::<i32>().map(|v| v as u32),
+            None => return self.set_word(name, parse_number(value, 16)?),
+        }
+        .map_err(|_| format!("Invalid decimal value '{}'", value))?;
+        self.set_word(name, number)
+    }
+
+    fn set_word(&mut self, name: &str, value: u32) -> Result<(), String> {
+        let value = value & 0xFFFFFF;
+        match name {
+            "A" => self.reg_a = value,
+            "X" => self.reg_x = value,
+            "L" => self.reg_l = value,
+            "B" => self.reg_b = value,
+            "S" => self.reg_s = value,
+            "T" => self.reg_t = value,
+            "PC" => self.reg_pc = value,
+            "SW" => {
+                self.reg_sw = value;
+                self.cc = match (value & CC_MASK) >> 16 {
+                    0b00 => -1,
+                    0b01 => 0,
+                    _ => 1,
+                };
+            }
+            _ => return Err(format!("Unknown register '{}'", name)),
+        }
+        Ok(())
+    }
 }
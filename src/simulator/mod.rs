@@ -1,6 +1,8 @@
+pub mod command;
 pub mod inistialize_machine;
 pub mod opcode_implementation;
 pub mod sim;
+pub mod trace;
 
 use opcode_implementation::Opcode;
 
@@ -184,6 +184,7 @@ impl Opcode {
             machine.memory[addr] = ((value >> 16) & 0xFF) as u8;
             machine.memory[addr + 1] = ((value >> 8) & 0xFF) as u8;
             machine.memory[addr + 2] = (value & 0xFF) as u8;
+            machine.writes.extend(address..address + 3);
         }
     }
 
@@ -199,6 +200,7 @@ impl Opcode {
         let addr = address as usize;
         if addr < machine.memory.len() {
             machine.memory[addr] = value;
+            machine.writes.push(address);
         }
     }
 
@@ -362,8 +364,8 @@ impl Opcode {
 
     fn divide(&self, machine: &mut Machine, operand: u32, mode: AddressingMode) {
         let value = self.get_operand_value(machine, operand, &mode);
-        if value != 0 {
-            machine.reg_a /= value;
+        if let Some(quotient) = machine.reg_a.checked_div(value) {
+            machine.reg_a = quotient;
         }
     }
 
@@ -441,8 +443,8 @@ impl Opcode {
         let r2 = operand & 0xF;
         let val1 = self.get_register_value(machine, r1 as u8);
         let val2 = self.get_register_value(machine, r2 as u8);
-        if val1 != 0 {
-            self.set_register_value(machine, r2 as u8, val2 / val1);
+        if let Some(quotient) = val2.checked_div(val1) {
+            self.set_register_value(machine, r2 as u8, quotient);
         }
     }
 
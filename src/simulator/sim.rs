@@ -1,22 +1,90 @@
-use super::inistialize_machine::Machine;
+use super::command::{DebugCommand, parse_command};
+use super::inistialize_machine::{Machine, Registers};
 use super::opcode_implementation::{AddressingMode, Opcode};
+use super::trace::{OpcodeClass, Trace, TraceEntry};
 use super::{name_to_opcode, register_name_to_code};
 use crate::disassembler::disassembler;
-use crate::error::{log_error, log_info};
+use crate::error::{log_error, log_info, log_warning};
 use crate::predefined::common::{
-    AddressFlags, Command, DisAssembledToken, LITERALTABLE, OBJECTPROGRAM, ObjectRecord,
-    SYMBOLTABLE,
+    AddressFlags, Command, DisAssembledToken, LINEMAP, LITERALTABLE, OBJECTPROGRAM, ObjectRecord,
+    Origin, SYMBOLTABLE, SourceLine,
 };
 use crate::predefined::opcode::reverse_optab;
 use crate::predefined::registers::reverse_register_map;
 use crate::tui::Tui;
+use crate::tui::config::Config;
+use crate::tui::keys::Action;
+use crate::tui::layout::LayoutConfig;
+use crate::tui::memory::MemoryWidget;
+use crate::tui::source::SourceFile;
+use crate::tui::tui::{Divider, MouseAction};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use ratatui::{Terminal, backend::CrosstermBackend};
+use std::collections::HashSet;
+use std::fmt;
 use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// a run stops after this many instructions unless told otherwise, so that a program
+// that never ends cannot hang the debugger
+pub const DEFAULT_INSTRUCTION_LIMIT: usize = 1_000_000;
+
+// instructions per second `-` and `+` step through while running, None is as fast as
+// it goes. The slow ones animate the program one instruction at a time.
+pub const RUN_SPEEDS: [Option<u32>; 6] =
+    [Some(1), Some(10), Some(100), Some(1000), Some(10000), None];
+
+// A subroutine call that has not returned. SIC/XE has no stack, JSUB leaves the
+// return address in L, so the simulator keeps this one on the side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub call_site: u32,
+    pub entry: u32,
+    pub return_to: u32,
+    pub saved_at: Option<u32>, // where STL stored the return address
+    pub clobbered: bool,       // L was changed before it was saved, RSUB will go astray
+}
+
+// why execution last stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Breakpoint(u32),
+    Halted(u32), // the instruction jumped to itself, as `J *` ending a program does
+    NoInstruction(u32),
+    Limit(usize),
+    Paused,
+    Reached(u32), // the target of until, next, finish or a line step
+}
+
+// where until, next, finish and stepping a source line stop a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunTarget {
+    Address(u32),
+    Past(u32),     // any address past this one, to leave a loop
+    Return(usize), // back at this depth of the call stack
+    Out(usize),    // below this depth, outside of any call past the next RSUB
+    // the start of a source line, passing over code that has none such as literal
+    // pools, and the next instruction when there is no source
+    Line,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(address) => write!(f, "Breakpoint hit at {:06X}", address),
+            StopReason::Halted(address) => write!(f, "Halted at {:06X}", address),
+            StopReason::NoInstruction(address) => write!(f, "No instruction at {:06X}", address),
+            StopReason::Limit(count) => write!(f, "Stopped after {} instructions", count),
+            StopReason::Paused => write!(f, "Paused"),
+            StopReason::Reached(address) => write!(f, "Stopped at {:06X}", address),
+        }
+    }
+}
 
 pub struct Simulator {
     pub machine: Machine,
@@ -24,6 +92,25 @@ pub struct Simulator {
     pub running: bool,
     pub instructions: Vec<DisAssembledToken>,
     pub program_start: u32,
+    // bytes written by the last instruction, and since execution last stopped:
+    // over the whole of the last run and by the steps after it
+    pub changed: Vec<u32>,
+    pub changed_since_stop: HashSet<u32>,
+    // the memory operand of the last instruction, None when it had none
+    pub last_address: Option<u32>,
+    pub changed_registers: Vec<&'static str>,
+    // where each source line was assembled to, empty for an object program
+    pub lines: Vec<SourceLine>,
+    // instructions a run, until or finish may take, those taken by the current run
+    // and why the last one stopped
+    pub instruction_limit: usize,
+    pub executed: usize,
+    pub stop_reason: Option<StopReason>,
+    pub target: Option<RunTarget>,
+    // the JSUBs that have not returned yet, innermost last
+    pub call_stack: Vec<CallFrame>,
+    // where each executed instruction is logged, while a trace is on
+    pub trace: Option<Trace>,
 }
 
 impl Default for Simulator {
@@ -40,11 +127,24 @@ impl Simulator {
             running: false,
             program_start: 0,
             instructions: Vec::new(),
+            changed: Vec::new(),
+            changed_since_stop: HashSet::new(),
+            last_address: None,
+            changed_registers: Vec::new(),
+            lines: Vec::new(),
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+            executed: 0,
+            stop_reason: None,
+            target: None,
+            call_stack: Vec::new(),
+            trace: None,
         }
     }
 
     pub fn load_program(&mut self) {
         self.instructions = disassembler::disassemble();
+        self.machine.load_records(&OBJECTPROGRAM.lock().unwrap());
+        self.lines = LINEMAP.lock().unwrap().clone();
 
         if !self.instructions.is_empty() {
             self.program_start = self.instructions[0].locctr;
@@ -57,26 +157,87 @@ impl Simulator {
         log_info(&format!("Loaded {} instructions", self.instructions.len()));
     }
 
+    // runs until the program stops, a breakpoint or the instruction limit,
+    // the TUI runs in slices instead
     pub fn run(&mut self) {
+        self.start_run();
+        self.run_slice(usize::MAX);
+    }
+
+    pub fn start_run(&mut self) {
+        self.changed_since_stop.clear();
         self.running = true;
+        self.executed = 0;
+        self.stop_reason = None;
+        self.target = None;
         log_info("Starting program execution");
+    }
 
-        while self.running {
-            if self.breakpoints.contains(&self.machine.reg_pc) {
-                log_info(&format!(
-                    "Breakpoint hit at address: {:06X}",
-                    self.machine.reg_pc
-                ));
-                self.running = false;
-                break;
+    // a run that also stops once it reaches the target
+    pub fn run_to(&mut self, target: RunTarget) {
+        self.start_run();
+        self.target = Some(target);
+    }
+
+    // Goes on with a run for at most `budget` instructions, so the TUI can draw in
+    // between, and returns how many it took. A run started on a breakpoint leaves it.
+    pub fn run_slice(&mut self, budget: usize) -> usize {
+        let mut count = 0;
+        while self.running && count < budget {
+            let pc = self.machine.reg_pc;
+            if self.executed > 0 && self.breakpoints.contains(&pc) {
+                self.stop(StopReason::Breakpoint(pc));
+            } else if self.executed >= self.instruction_limit {
+                self.stop(StopReason::Limit(self.executed));
+            } else if !self.fetch_decode_execute() {
+                self.stop(StopReason::NoInstruction(pc));
+            } else {
+                self.executed += 1;
+                count += 1;
+                let now = self.machine.reg_pc;
+                if now == pc {
+                    self.stop(StopReason::Halted(pc));
+                } else if self.reached(pc) && !self.breakpoints.contains(&now) {
+                    // a breakpoint on the target is reported as the breakpoint
+                    self.stop(StopReason::Reached(now));
+                }
             }
+        }
+        count
+    }
 
-            if !self.fetch_decode_execute() {
-                self.running = false;
+    // whether the instruction just run from `pc` took the run to its target
+    fn reached(&self, pc: u32) -> bool {
+        let depth = self.call_stack.len();
+        match self.target {
+            None => false,
+            Some(RunTarget::Address(address)) => self.machine.reg_pc == address,
+            Some(RunTarget::Past(from)) => self.machine.reg_pc > from,
+            Some(RunTarget::Line) => {
+                self.lines.is_empty() || self.source_line(self.machine.reg_pc).is_some()
+            }
+            Some(RunTarget::Return(to)) => depth <= to,
+            Some(RunTarget::Out(from)) => {
+                depth < from
+                    || (from == 0
+                        && self
+                            .find_instruction_at_pc(pc)
+                            .is_some_and(|token| mnemonic(token) == "RSUB"))
             }
         }
+    }
 
-        log_info("Program execution completed");
+    pub fn pause(&mut self) {
+        if self.running {
+            self.stop(StopReason::Paused);
+        }
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        log_info(&format!("{} after {} instructions", reason, self.executed));
+        self.running = false;
+        self.stop_reason = Some(reason);
+        self.target = None;
     }
 
     fn find_program_start_from_header(&mut self) {
@@ -93,6 +254,17 @@ impl Simulator {
         self.fetch_decode_execute()
     }
 
+    pub fn source_line(&self, address: u32) -> Option<&SourceLine> {
+        self.lines.iter().find(|line| line.address == address)
+    }
+
+    pub fn line_address(&self, origin: &Origin) -> Option<u32> {
+        self.lines
+            .iter()
+            .find(|line| line.origin == *origin)
+            .map(|line| line.address)
+    }
+
     pub fn fetch_decode_execute(&mut self) -> bool {
         if let Some(instr) = self.find_instruction_at_pc(self.machine.reg_pc).cloned() {
             // println!(
@@ -100,7 +272,22 @@ impl Simulator {
             //     self.machine.reg_pc,
             //     self.format_instruction(&instr)
             // );
+            self.machine.writes.clear();
+            self.last_address = None;
+            let before = self.machine.registers();
+            // read before it runs, in case it writes over itself
+            let bytes = match self.trace {
+                Some(_) => self.instruction_bytes(&instr),
+                None => Vec::new(),
+            };
             self.execute_instruction(&instr);
+            self.track_calls(&instr, before.pc, before.l);
+            self.changed_registers = self.machine.registers().changed(&before);
+            self.changed = std::mem::take(&mut self.machine.writes);
+            self.changed_since_stop.extend(self.changed.iter().copied());
+            if self.trace.is_some() {
+                self.record_trace(&instr, bytes, before);
+            }
             true
         } else {
             // println!("No instruction found at PC: {:06X}", self.machine.reg_pc);
@@ -108,6 +295,68 @@ impl Simulator {
         }
     }
 
+    fn instruction_bytes(&self, token: &DisAssembledToken) -> Vec<u8> {
+        let size = match &token.command {
+            Command::Instruction(instr) => instr.opcode.format as usize,
+            Command::Directive(_) => 1,
+        };
+        let start = token.locctr as usize;
+        let end = (start + size).min(self.machine.memory.len());
+        self.machine
+            .memory
+            .get(start..end)
+            .unwrap_or_default()
+            .to_vec()
+    }
+
+    fn record_trace(&mut self, token: &DisAssembledToken, bytes: Vec<u8>, before: Registers) {
+        let class = match &token.command {
+            Command::Instruction(instr) => {
+                self.byte_to_opcode(instr.opcode.code).map(OpcodeClass::of)
+            }
+            Command::Directive(_) => None,
+        };
+        // the bytes written, gathered into runs of neighbouring addresses
+        let mut addresses = self.changed.clone();
+        addresses.sort_unstable();
+        addresses.dedup();
+        let mut writes: Vec<(u32, Vec<u8>)> = Vec::new();
+        for address in addresses {
+            let Some(byte) = self.machine.memory.get(address as usize).copied() else {
+                continue;
+            };
+            match writes.last_mut() {
+                Some((start, run)) if *start + run.len() as u32 == address => run.push(byte),
+                _ => writes.push((address, vec![byte])),
+            }
+        }
+        let entry = TraceEntry {
+            pc: before.pc,
+            bytes,
+            instruction: self.format_instruction(token),
+            class,
+            address: self.last_address,
+            before,
+            after: self.machine.registers(),
+            writes,
+        };
+        if let Some(trace) = &mut self.trace
+            && let Err(e) = trace.record(&entry)
+        {
+            log_error(&format!("Trace stopped, it could not be written: {}", e));
+            self.trace = None;
+        }
+    }
+
+    // stops the trace, writing out what is left of it
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        let mut trace = self.trace.take()?;
+        if let Err(e) = trace.flush() {
+            log_error(&format!("Could not finish the trace: {}", e));
+        }
+        Some(trace)
+    }
+
     fn find_instruction_at_pc(&self, pc: u32) -> Option<&DisAssembledToken> {
         self.instructions.iter().find(|instr| instr.locctr == pc)
     }
@@ -123,26 +372,29 @@ impl Simulator {
                     match format {
                         1 => {
                             // Format 1: No operand
-                            opcode.execute(&mut self.machine, 0, AddressingMode::Direct);
                             self.machine.reg_pc += 1;
+                            opcode.execute(&mut self.machine, 0, AddressingMode::Direct);
                         }
                         2 => {
                             // Format 2: Register operations
                             let operand = self.get_format2_operand(token);
-                            opcode.execute(&mut self.machine, operand, AddressingMode::Direct);
                             self.machine.reg_pc += 2;
+                            opcode.execute(&mut self.machine, operand, AddressingMode::Direct);
                         }
                         3 => {
-                            // Format 3: Memory operations with 12-bit displacement
+                            // Format 3: Memory operations with 12-bit displacement,
+                            // PC-relative to the next instruction
+                            self.machine.reg_pc += 3;
                             let (operand, mode) = self.get_format3_operand(token);
+                            self.last_address = self.memory_address(operand, &mode);
                             opcode.execute(&mut self.machine, operand, mode);
-                            self.machine.reg_pc += 3;
                         }
                         4 => {
                             // Format 4: Extended format with 20-bit address
+                            self.machine.reg_pc += 4;
                             let (operand, mode) = self.get_format4_operand(token);
+                            self.last_address = self.memory_address(operand, &mode);
                             opcode.execute(&mut self.machine, operand, mode);
-                            self.machine.reg_pc += 4;
                         }
                         _ => {
                             log_error(&format!("Unknown instruction format: {}", format));
@@ -201,7 +453,8 @@ impl Simulator {
 
     fn calculate_effective_address(&self, displacement: u32, flags: &AddressFlags) -> u32 {
         if flags.p {
-            // PC-relative addressing
+            // PC-relative addressing, the displacement is 12-bit two's complement
+            let displacement = ((displacement << 20) as i32 >> 20) as u32;
             self.machine.reg_pc.wrapping_add(displacement)
         } else if flags.b {
             // Base-relative addressing
@@ -212,6 +465,20 @@ impl Simulator {
         }
     }
 
+    // the memory an operand refers to, None for an immediate value
+    fn memory_address(&self, operand: u32, mode: &AddressingMode) -> Option<u32> {
+        match mode {
+            AddressingMode::Immediate => None,
+            AddressingMode::Direct => Some(operand),
+            AddressingMode::Indexed => Some(operand.wrapping_add(self.machine.reg_x)),
+            AddressingMode::Indirect => {
+                let address = operand as usize;
+                let word = self.machine.memory.get(address..address + 3)?;
+                Some(u32::from_be_bytes([0, word[0], word[1], word[2]]))
+            }
+        }
+    }
+
     fn determine_addressing_mode(&self, flags: &AddressFlags) -> AddressingMode {
         if flags.i && !flags.n {
             AddressingMode::Immediate
@@ -262,6 +529,14 @@ impl Simulator {
     pub fn reset(&mut self) {
         self.machine = Machine::new();
         self.running = false;
+        self.executed = 0;
+        self.stop_reason = None;
+        self.target = None;
+        self.call_stack.clear();
+        self.changed.clear();
+        self.changed_since_stop.clear();
+        self.last_address = None;
+        self.changed_registers.clear();
     }
 
     pub fn add_breakpoint(&mut self, address: u32) {
@@ -271,6 +546,90 @@ impl Simulator {
         }
     }
 
+    // runs to the target without drawing in between, for the command line and tests
+    pub fn run_until(&mut self, target: RunTarget) -> String {
+        self.run_to(target);
+        self.run_slice(usize::MAX);
+        self.stop_reason
+            .as_ref()
+            .map_or(String::new(), |reason| reason.to_string())
+    }
+
+    // a JSUB runs until its subroutine has returned, anything else is one step
+    pub fn step_over_target(&self) -> RunTarget {
+        RunTarget::Return(self.call_stack.len())
+    }
+
+    // runs until the subroutine the PC is in returns, outside of any call until the
+    // next RSUB
+    pub fn step_out_target(&self) -> RunTarget {
+        RunTarget::Out(self.call_stack.len())
+    }
+
+    // Keeps the shadow call stack: JSUB pushes a frame and RSUB pops back to the one
+    // it returns to. L holding anything but the return address, without STL having
+    // saved it first, marks the frame clobbered.
+    fn track_calls(&mut self, token: &DisAssembledToken, pc: u32, l: u32) {
+        match mnemonic(token) {
+            "JSUB" => {
+                if let Some(caller) = self.call_stack.last_mut()
+                    && caller.saved_at.is_none()
+                {
+                    caller.clobbered = true;
+                }
+                self.call_stack.push(CallFrame {
+                    call_site: pc,
+                    entry: self.machine.reg_pc,
+                    return_to: self.machine.reg_l,
+                    saved_at: None,
+                    clobbered: false,
+                });
+            }
+            "RSUB" => {
+                let to = self.machine.reg_pc;
+                match self.call_stack.iter().rposition(|f| f.return_to == to) {
+                    Some(frame) => self.call_stack.truncate(frame),
+                    None => {
+                        if !self.call_stack.is_empty() {
+                            log_warning(&format!(
+                                "RSUB at {:06X} returns to {:06X}, which no JSUB called from",
+                                pc, to
+                            ));
+                        }
+                        self.call_stack.pop();
+                    }
+                }
+            }
+            "STL" => {
+                if let Some(frame) = self.call_stack.last_mut()
+                    && self.machine.reg_l == frame.return_to
+                {
+                    frame.saved_at = self.last_address;
+                }
+            }
+            _ => {
+                if let Some(frame) = self.call_stack.last_mut()
+                    && self.machine.reg_l != l
+                {
+                    frame.clobbered =
+                        self.machine.reg_l != frame.return_to && frame.saved_at.is_none();
+                }
+            }
+        }
+    }
+
+    // sets a breakpoint or clears the one there, returning whether one is set now
+    pub fn toggle_breakpoint(&mut self, address: u32) -> bool {
+        if let Some(pos) = self.breakpoints.iter().position(|&x| x == address) {
+            self.breakpoints.remove(pos);
+            log_info(&format!("Breakpoint removed from {:06X}", address));
+            false
+        } else {
+            self.add_breakpoint(address);
+            true
+        }
+    }
+
     // pub fn remove_breakpoint(&mut self, address: u32) {
     //     if let Some(pos) = self.breakpoints.iter().position(|&x| x == address) {
     //         self.breakpoints.remove(pos);
@@ -318,7 +677,12 @@ impl Simulator {
 //     sim.print_state();
 // }
 
-pub fn calling_tui() -> Result<(), Box<dyn std::error::Error>> {
+// `source` is the .asm the program was assembled from, shown next to the disassembly
+// `instruction_limit` stops a run that goes on for longer
+pub fn calling_tui(
+    source: Option<&Path>,
+    instruction_limit: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
     log_info("Starting TUI simulator");
 
     // Setup terminal
@@ -330,40 +694,31 @@ pub fn calling_tui() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create app state
     let mut tui = Tui::new();
+    tui.set_config(Config::load());
+    tui.set_layout(LayoutConfig::load());
     let mut sim = Simulator::new();
+    sim.instruction_limit = instruction_limit;
     sim.load_program();
+    let mut clock = RunClock::new();
 
-    // Load object program and symbol table from global state
-    let object_program = {
-        let obj_prog = OBJECTPROGRAM.lock().unwrap();
-        obj_prog.clone()
-    };
-    tui.update_object_program(object_program);
-
-    let symbol_table = SYMBOLTABLE.lock().unwrap().clone();
-    tui.update_symbol_table(symbol_table);
-
-    let literal_table = LITERALTABLE.lock().unwrap().clone();
-    tui.update_literal_table(literal_table);
-
-    // Auto-focus memory on the object code location
-    tui.auto_focus_memory();
+    show_program(&mut tui);
+    if let Some(path) = source {
+        tui.update_source(source_files(path, &sim.lines));
+    }
 
     // Main event loop
     loop {
         // Update TUI with current simulator state before drawing
-        tui.update_registers(
-            sim.machine.reg_a,
-            sim.machine.reg_x,
-            sim.machine.reg_l,
+        tui.update_registers(sim.machine.registers(), &sim.changed_registers);
+
+        tui.update_memory(
+            &sim.machine.memory,
+            &sim.changed,
+            &sim.changed_since_stop,
             sim.machine.reg_pc,
-            sim.machine.reg_sw,
+            sim.last_address,
         );
 
-        // Memory widget now reads directly from OBJECTPROGRAM, so we don't need to update it
-        // Just call update_memory with empty data to trigger render
-        tui.update_memory(0, &[]);
-
         // Update disassembly with current instructions
         let disassembly: Vec<(u32, String, String)> = sim
             .instructions
@@ -380,63 +735,233 @@ pub fn calling_tui() -> Result<(), Box<dyn std::error::Error>> {
             .collect();
         tui.update_disassembly(disassembly);
 
+        tui.update_breakpoints(&sim.breakpoints);
+        tui.update_source_position(
+            sim.source_line(sim.machine.reg_pc)
+                .map(|line| line.origin.clone()),
+            sim.breakpoints
+                .iter()
+                .filter_map(|&address| sim.source_line(address))
+                .map(|line| line.origin.clone())
+                .collect(),
+        );
+
+        let status = if sim.running {
+            format!(
+                "Running, {} instructions/s at speed {}",
+                clock.rate,
+                clock.speed()
+            )
+        } else {
+            match &sim.stop_reason {
+                Some(reason) => format!("{}, speed {}", reason, clock.speed()),
+                None => format!("Stopped, speed {}", clock.speed()),
+            }
+        };
+        tui.update_run_state(sim.running, status);
+        tui.update_call_stack(&sim.call_stack);
+
         // Draw UI
         terminal.draw(|f| tui.draw(f))?;
 
         // Handle events
-        if event::poll(std::time::Duration::from_millis(100))?
-            && let Event::Key(key) = event::read()?
-        {
-            match key.code {
-                KeyCode::Char('q') => break,
-                KeyCode::Left => {
-                    tui.move_focus_left();
-                }
-                KeyCode::Right => {
-                    tui.move_focus_right();
-                }
-                KeyCode::Up => {
-                    tui.scroll_memory_up();
-                }
-                KeyCode::Down => {
-                    tui.scroll_memory_down();
-                }
-                KeyCode::Tab => {
-                    tui.next_tab();
-                }
-                KeyCode::BackTab => {
-                    tui.previous_tab();
+        // while running, the UI keeps drawing between slices of the run
+        let timeout = if sim.running {
+            clock.run(&mut sim)
+        } else {
+            IDLE_POLL
+        };
+        if !event::poll(timeout)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            Event::Mouse(mouse) => {
+                // a click on a line of source or disassembly sets or clears its breakpoint
+                match tui.mouse(mouse) {
+                    MouseAction::Button(button) => {
+                        press_button(button, &mut sim, &mut tui, &mut clock)
+                    }
+                    MouseAction::Breakpoint(address) => {
+                        sim.toggle_breakpoint(address);
+                    }
+                    MouseAction::SourceLine(origin) => toggle_line_breakpoint(&mut sim, &origin),
+                    MouseAction::None => {}
                 }
-                KeyCode::Enter => {
-                    match tui.get_focused_button() {
-                        0 => {
-                            // Step button
-                            sim.step();
+                continue;
+            }
+            _ => continue,
+        };
+        if tui.prompt_open() {
+            if let Some(line) = tui.prompt_key(key.code) {
+                let result = parse_command(&line).and_then(|command| {
+                    // mem and search move the memory pane, the rest go to the simulator
+                    match &command {
+                        DebugCommand::Memory(address) => {
+                            tui.memory().scroll_to_address(*address);
+                            return Ok(vec![format!("Memory at {:06X}", address)]);
                         }
-                        1 => {
-                            // Run button
-                            sim.run();
+                        DebugCommand::Search(pattern) => {
+                            return search_memory(&sim, tui.memory(), pattern);
                         }
-                        2 => {
-                            // Reset button
-                            sim.reset();
-                            sim.load_program();
-                            tui.auto_focus_memory();
+                        DebugCommand::Pane { name, visible } => {
+                            return tui.set_pane_visible(name, *visible);
                         }
                         _ => {}
                     }
+                    // until, next and finish run in slices like r, so p can pause them
+                    if let Some(target) = sim.run_target(&command) {
+                        sim.pause();
+                        sim.run_to(target);
+                        clock.start();
+                        return Ok(vec![format!("Running from {:06X}", sim.machine.reg_pc)]);
+                    }
+                    let result = sim.execute_command(&command);
+                    if matches!(command, DebugCommand::Load(_)) {
+                        show_program(&mut tui);
+                        tui.update_source(Vec::new());
+                    }
+                    result
+                });
+                tui.show_command_result(result);
+            }
+            continue;
+        }
+        if tui.editing_registers() {
+            if let Some(edit) = tui.register_key(key.code)
+                && let Err(e) = sim.machine.set_register(edit.register, &edit.value)
+            {
+                log_error(&e);
+            }
+            continue;
+        }
+        match tui.action(&key) {
+            Some(Action::Quit) => {
+                tui.save_layout();
+                break;
+            }
+            Some(Action::PreviousButton) => {
+                tui.move_focus_left();
+            }
+            Some(Action::NextButton) => {
+                tui.move_focus_right();
+            }
+            Some(Action::ScrollUp) => {
+                tui.memory().scroll_up();
+            }
+            Some(Action::ScrollDown) => {
+                tui.memory().scroll_down();
+            }
+            Some(Action::PageUp) => {
+                tui.memory().page_up();
+            }
+            Some(Action::PageDown) => {
+                tui.memory().page_down();
+            }
+            Some(Action::WordView) => {
+                tui.memory().toggle_unit();
+            }
+            Some(Action::Charset) => {
+                tui.memory().toggle_charset();
+            }
+            Some(Action::Follow) => {
+                tui.memory().cycle_follow();
+            }
+            Some(Action::PinMemory) => {
+                tui.memory().pin();
+            }
+            Some(Action::CloseMemory) => {
+                tui.memory().close();
+            }
+            Some(Action::PreviousMemory) => {
+                tui.memory().previous_view();
+            }
+            Some(Action::NextMemory) => {
+                tui.memory().next_view();
+            }
+            Some(Action::Zoom) => {
+                tui.toggle_zoom();
+            }
+            Some(Action::Narrower) => {
+                tui.resize(Divider::Columns, -5);
+            }
+            Some(Action::Wider) => {
+                tui.resize(Divider::Columns, 5);
+            }
+            Some(Action::Shorter) => {
+                tui.resize(Divider::Rows, -5);
+            }
+            Some(Action::Taller) => {
+                tui.resize(Divider::Rows, 5);
+            }
+            Some(Action::Goto) => {
+                tui.open_prompt("mem ");
+            }
+            Some(Action::Search) => {
+                tui.open_prompt("search ");
+            }
+            Some(Action::NextTab) => {
+                tui.next_tab();
+            }
+            Some(Action::PreviousTab) => {
+                tui.previous_tab();
+            }
+            Some(Action::Press) => {
+                press_button(tui.get_focused_button(), &mut sim, &mut tui, &mut clock);
+            }
+            Some(Action::Step) => {
+                sim.pause();
+                sim.step();
+            }
+            Some(Action::Run) if !sim.running => {
+                sim.start_run();
+                clock.start();
+            }
+            Some(Action::Pause) => {
+                sim.pause();
+            }
+            Some(Action::Faster) => {
+                clock.faster();
+            }
+            Some(Action::Slower) => {
+                clock.slower();
+            }
+            Some(Action::Break) => {
+                sim.add_breakpoint(sim.machine.reg_pc);
+            }
+            Some(Action::NextLine) if !sim.running => {
+                sim.run_to(RunTarget::Line);
+                clock.start();
+            }
+            Some(Action::StepOver) if !sim.running => {
+                sim.run_to(sim.step_over_target());
+                clock.start();
+            }
+            Some(Action::StepOut) if !sim.running => {
+                sim.run_to(sim.step_out_target());
+                clock.start();
+            }
+            Some(Action::SourceDown) => {
+                tui.move_source_cursor(1);
+            }
+            Some(Action::SourceUp) => {
+                tui.move_source_cursor(-1);
+            }
+            Some(Action::ToggleLine) => {
+                if let Some(origin) = tui.selected_source_line() {
+                    toggle_line_breakpoint(&mut sim, &origin);
                 }
-                KeyCode::Char('s') => {
-                    sim.step();
-                }
-                KeyCode::Char('r') => {
-                    sim.run();
-                }
-                KeyCode::Char('b') => {
-                    sim.add_breakpoint(sim.machine.reg_pc);
-                }
-                _ => {}
             }
+            Some(Action::FollowPc) => {
+                tui.follow_pc();
+            }
+            Some(Action::EditRegisters) => {
+                tui.edit_registers();
+            }
+            Some(Action::Command) => {
+                tui.open_prompt("");
+            }
+            _ => {}
         }
     }
 
@@ -452,3 +977,185 @@ pub fn calling_tui() -> Result<(), Box<dyn std::error::Error>> {
     log_info("TUI simulator shut down successfully");
     Ok(())
 }
+
+// how long the event loop waits for a key when nothing runs, and for how long a run
+// at full speed goes on before the next frame is drawn
+const IDLE_POLL: Duration = Duration::from_millis(100);
+const FRAME: Duration = Duration::from_millis(30);
+
+// Paces a run in the TUI at the speed picked from RUN_SPEEDS, and measures how many
+// instructions per second it really takes
+struct RunClock {
+    speed: usize,
+    credit: f64, // instructions due at a limited speed
+    last: Instant,
+    window: Instant,
+    window_count: usize,
+    rate: usize,
+}
+
+impl RunClock {
+    fn new() -> Self {
+        Self {
+            speed: RUN_SPEEDS.len() - 1,
+            credit: 0.0,
+            last: Instant::now(),
+            window: Instant::now(),
+            window_count: 0,
+            rate: 0,
+        }
+    }
+
+    fn start(&mut self) {
+        // the first instruction goes at once, even at one a second
+        self.credit = 1.0;
+        self.last = Instant::now();
+        self.window = self.last;
+        self.window_count = 0;
+        self.rate = 0;
+    }
+
+    fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(RUN_SPEEDS.len() - 1);
+    }
+
+    fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    fn speed(&self) -> String {
+        match RUN_SPEEDS[self.speed] {
+            Some(per_second) => format!("{}/s", per_second),
+            None => "max".to_string(),
+        }
+    }
+
+    // runs the next slice and returns how long to wait for a key before the next one
+    fn run(&mut self, sim: &mut Simulator) -> Duration {
+        let now = Instant::now();
+        let (count, wait) = match RUN_SPEEDS[self.speed] {
+            None => {
+                let mut count = 0;
+                while sim.running && now.elapsed() < FRAME {
+                    count += sim.run_slice(10_000);
+                }
+                (count, Duration::ZERO)
+            }
+            Some(per_second) => {
+                let due = per_second as f64 * now.duration_since(self.last).as_secs_f64();
+                self.credit = (self.credit + due).min(per_second as f64);
+                let count = sim.run_slice(self.credit as usize);
+                self.credit -= count as f64;
+                (count, Duration::from_millis(16))
+            }
+        };
+        self.last = now;
+        self.window_count += count;
+        let window = now.duration_since(self.window);
+        if window >= Duration::from_secs(1) {
+            self.rate = (self.window_count as f64 / window.as_secs_f64()) as usize;
+            self.window = now;
+            self.window_count = 0;
+        }
+        wait
+    }
+}
+
+// Load object program and symbol table from global state
+fn show_program(tui: &mut Tui) {
+    let object_program = OBJECTPROGRAM.lock().unwrap().clone();
+    let boundaries = LINEMAP.lock().unwrap().iter().map(|l| l.address).collect();
+    tui.update_object_program(object_program, boundaries);
+
+    let symbol_table = SYMBOLTABLE.lock().unwrap().clone();
+    tui.update_symbol_table(symbol_table);
+
+    let literal_table = LITERALTABLE.lock().unwrap().clone();
+    tui.update_literal_table(literal_table);
+
+    // Auto-focus memory on the object code location
+    tui.auto_focus_memory();
+}
+
+// the Step, Run and Reset buttons, by keyboard or mouse
+fn press_button(button: usize, sim: &mut Simulator, tui: &mut Tui, clock: &mut RunClock) {
+    match button {
+        0 => {
+            // Step button
+            sim.pause();
+            sim.step();
+        }
+        1 => {
+            // Run button, Pause while running
+            if sim.running {
+                sim.pause();
+            } else {
+                sim.start_run();
+                clock.start();
+            }
+        }
+        2 => {
+            // Reset button
+            sim.reset();
+            sim.load_program();
+            tui.auto_focus_memory();
+        }
+        _ => {}
+    }
+}
+
+// the next match after the last one, or after the top of the active window
+fn search_memory(
+    sim: &Simulator,
+    memory: &mut MemoryWidget,
+    pattern: &[u8],
+) -> Result<Vec<String>, String> {
+    let address = sim
+        .search_memory(pattern, memory.search_start())
+        .ok_or_else(|| "Not found".to_string())?;
+    memory.show_found(address, pattern.len());
+    Ok(vec![format!("Found at {:06X}", address)])
+}
+
+fn toggle_line_breakpoint(sim: &mut Simulator, origin: &Origin) {
+    match sim.line_address(origin) {
+        Some(address) => {
+            sim.toggle_breakpoint(address);
+        }
+        None => log_info(&format!("Line {} has no code to break at", origin.line)),
+    }
+}
+
+// the main source and the files it includes, read back as they are on disk
+fn source_files(path: &Path, lines: &[SourceLine]) -> Vec<SourceFile> {
+    let mut files: Vec<Option<String>> = vec![None];
+    for line in lines {
+        if !files.contains(&line.origin.file) {
+            files.push(line.origin.file.clone());
+        }
+    }
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.clone().unwrap_or_else(|| path.display().to_string());
+            match std::fs::read_to_string(&name) {
+                Ok(text) => Some(SourceFile {
+                    file,
+                    name,
+                    lines: text.lines().map(str::to_string).collect(),
+                }),
+                Err(e) => {
+                    log_error(&format!("Cannot read source {}: {}", name, e));
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn mnemonic(token: &DisAssembledToken) -> &str {
+    match &token.command {
+        Command::Instruction(instr) => &instr.instr,
+        Command::Directive(directive) => directive,
+    }
+}
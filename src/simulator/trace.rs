@@ -0,0 +1,302 @@
+use super::command::address;
+use super::inistialize_machine::{REGISTER_NAMES, Registers};
+use super::opcode_implementation::Opcode;
+use serde_json::{Map, Value, json};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// A log of every instruction executed, one line each, to diff a run against another
+// one and find where they first part ways. Steps count every instruction since the
+// trace started, also those the filter leaves out, so they line up between runs.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Csv,
+    JsonLines,
+}
+
+impl TraceFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "csv" => Some(TraceFormat::Csv),
+            "jsonl" | "ndjson" | "json" => Some(TraceFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+// the sections of the opcode table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeClass {
+    Load,
+    Store,
+    Arithmetic,
+    Compare,
+    Register,
+    Jump,
+    Io,
+    Misc,
+}
+
+pub const CLASS_NAMES: [&str; 8] = [
+    "load",
+    "store",
+    "arithmetic",
+    "compare",
+    "register",
+    "jump",
+    "io",
+    "misc",
+];
+
+impl OpcodeClass {
+    pub fn of(opcode: Opcode) -> Self {
+        use Opcode::*;
+        match opcode {
+            LDA | LDX | LDL | LDB | LDS | LDT | LDF | LDCH => OpcodeClass::Load,
+            STA | STX | STL | STB | STS | STT | STF | STI | STCH | STSW => OpcodeClass::Store,
+            ADD | ADDF | SUB | SUBF | MUL | MULF | DIV | DIVF => OpcodeClass::Arithmetic,
+            COMP | COMPF | COMPR => OpcodeClass::Compare,
+            ADDR | SUBR | MULR | DIVR | RMO | CLEAR | TIXR | SHIFTL | SHIFTR => {
+                OpcodeClass::Register
+            }
+            J | JEQ | JGT | JLT | JSUB | RSUB | TIX => OpcodeClass::Jump,
+            RD | WD | TD | SIO | TIO | HIO => OpcodeClass::Io,
+            FIX | FLOAT | NORM | SSK | LPS | SVC => OpcodeClass::Misc,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        CLASS_NAMES[*self as usize]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        const CLASSES: [OpcodeClass; 8] = [
+            OpcodeClass::Load,
+            OpcodeClass::Store,
+            OpcodeClass::Arithmetic,
+            OpcodeClass::Compare,
+            OpcodeClass::Register,
+            OpcodeClass::Jump,
+            OpcodeClass::Io,
+            OpcodeClass::Misc,
+        ];
+        let name = name.to_lowercase();
+        CLASSES.into_iter().find(|class| class.name() == name)
+    }
+}
+
+// which instructions make it into the trace, everything when left empty
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFilter {
+    pub range: Option<(u32, u32)>, // of the PC, both ends included
+    pub classes: Vec<OpcodeClass>,
+}
+
+impl TraceFilter {
+    // START..END, labels work as anywhere an address is expected
+    pub fn parse_range(text: &str) -> Result<(u32, u32), String> {
+        let (start, end) = text
+            .split_once("..")
+            .ok_or_else(|| format!("Invalid range '{}', use START..END", text))?;
+        let (start, end) = (address(start)?, address(end)?);
+        if start > end {
+            return Err(format!("Range '{}' ends before it starts", text));
+        }
+        Ok((start, end))
+    }
+
+    // a comma separated list such as jump,io
+    pub fn parse_classes(text: &str) -> Result<Vec<OpcodeClass>, String> {
+        text.split(',')
+            .map(|name| {
+                OpcodeClass::from_name(name.trim()).ok_or_else(|| {
+                    format!(
+                        "Unknown opcode class '{}', use {}",
+                        name.trim(),
+                        CLASS_NAMES.join(", ")
+                    )
+                })
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, pc: u32, class: Option<OpcodeClass>) -> bool {
+        let in_range = self
+            .range
+            .is_none_or(|(start, end)| (start..=end).contains(&pc));
+        let in_class =
+            self.classes.is_empty() || class.is_some_and(|class| self.classes.contains(&class));
+        in_range && in_class
+    }
+}
+
+// one executed instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u32,
+    pub bytes: Vec<u8>,
+    pub instruction: String,
+    pub class: Option<OpcodeClass>, // None for a directive run as code
+    pub address: Option<u32>,       // the effective address of a memory operand
+    pub before: Registers,
+    pub after: Registers,
+    pub writes: Vec<(u32, Vec<u8>)>, // runs of bytes written, with their new values
+}
+
+impl TraceEntry {
+    // <, = or > from the condition code the instruction left
+    pub fn cc(&self) -> char {
+        match (self.after.sw >> 16) & 0b11 {
+            0b00 => '<',
+            0b01 => '=',
+            _ => '>',
+        }
+    }
+
+    fn writes_text(&self) -> Vec<String> {
+        self.writes
+            .iter()
+            .map(|(address, bytes)| format!("{:06X}:{}", address, hex::encode_upper(bytes)))
+            .collect()
+    }
+
+    fn csv(&self, step: u64) -> String {
+        let mut fields = vec![
+            step.to_string(),
+            format!("{:06X}", self.pc),
+            hex::encode_upper(&self.bytes),
+            csv_field(&self.instruction),
+            self.class.map_or("", |class| class.name()).to_string(),
+            self.address.map_or(String::new(), |a| format!("{:06X}", a)),
+            self.cc().to_string(),
+            self.writes_text().join(" "),
+        ];
+        for registers in [&self.before, &self.after] {
+            fields.extend(REGISTER_NAMES.iter().map(|name| register(registers, name)));
+        }
+        fields.join(",")
+    }
+
+    fn json(&self, step: u64) -> String {
+        let registers = |registers: &Registers| {
+            let map: Map<String, Value> = REGISTER_NAMES
+                .iter()
+                .map(|name| (name.to_string(), Value::from(register(registers, name))))
+                .collect();
+            Value::Object(map)
+        };
+        json!({
+            "step": step,
+            "pc": format!("{:06X}", self.pc),
+            "bytes": hex::encode_upper(&self.bytes),
+            "instruction": self.instruction,
+            "class": self.class.map(|class| class.name()),
+            "ea": self.address.map(|a| format!("{:06X}", a)),
+            "cc": self.cc().to_string(),
+            "before": registers(&self.before),
+            "after": registers(&self.after),
+            "writes": self.writes_text(),
+        })
+        .to_string()
+    }
+}
+
+// hex for the 24-bit registers so two traces compare as text, F as a number
+fn register(registers: &Registers, name: &str) -> String {
+    match registers.get(name) {
+        Some(value) => format!("{:06X}", value),
+        None => registers.f.to_string(),
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn csv_header() -> String {
+    let mut columns: Vec<String> = [
+        "step",
+        "pc",
+        "bytes",
+        "instruction",
+        "class",
+        "ea",
+        "cc",
+        "writes",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect();
+    for when in ["before", "after"] {
+        columns.extend(
+            REGISTER_NAMES
+                .iter()
+                .map(|name| format!("{}_{}", name.to_lowercase(), when)),
+        );
+    }
+    columns.join(",")
+}
+
+pub struct Trace {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    pub filter: TraceFilter,
+    pub steps: u64,   // instructions executed since the trace started
+    pub written: u64, // of those, the ones the filter let through
+}
+
+impl Trace {
+    pub fn new(
+        mut writer: Box<dyn Write>,
+        format: TraceFormat,
+        filter: TraceFilter,
+    ) -> io::Result<Trace> {
+        if format == TraceFormat::Csv {
+            writeln!(writer, "{}", csv_header())?;
+        }
+        Ok(Trace {
+            writer,
+            format,
+            filter,
+            steps: 0,
+            written: 0,
+        })
+    }
+
+    // a trace into a file, in the format its extension names
+    pub fn create(path: &Path, filter: TraceFilter) -> Result<Trace, String> {
+        let format = TraceFormat::from_path(path).ok_or_else(|| {
+            format!(
+                "Cannot tell the trace format of {}, use .csv or .jsonl",
+                path.display()
+            )
+        })?;
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Trace::new(Box::new(BufWriter::new(file)), format, filter)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        self.steps += 1;
+        if !self.filter.matches(entry.pc, entry.class) {
+            return Ok(());
+        }
+        let line = match self.format {
+            TraceFormat::Csv => entry.csv(self.steps),
+            TraceFormat::JsonLines => entry.json(self.steps),
+        };
+        self.written += 1;
+        writeln!(self.writer, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,100 @@
+use super::theme::Theme;
+use crate::predefined::common::{SymbolTable, SymbolType};
+use crate::simulator::sim::CallFrame;
+use ratatui::{
+    Frame,
+    style::Style,
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+};
+
+// The JSUBs that have not returned, innermost first, named after the labels they
+// call and are called from
+pub struct CallStackWidget {
+    pub frames: Vec<CallFrame>,
+    pub scroll: usize, // frames scrolled past, for deep recursion
+}
+
+impl Default for CallStackWidget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallStackWidget {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            scroll: 0,
+        }
+    }
+
+    pub fn scroll(&mut self, lines: isize) {
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(lines)
+            .min(self.frames.len().saturating_sub(1));
+    }
+
+    pub fn render(
+        &self,
+        f: &mut Frame,
+        area: ratatui::layout::Rect,
+        symbols: &[SymbolTable],
+        theme: &Theme,
+    ) {
+        let text = theme.text();
+        let lines: Vec<Line> = if self.frames.is_empty() {
+            vec![Line::from(Span::styled("not in a subroutine", theme.dim()))]
+        } else {
+            self.frames
+                .iter()
+                .rev()
+                .enumerate()
+                .skip(self.scroll)
+                .map(|(depth, frame)| {
+                    let mut spans = vec![
+                        Span::styled(
+                            format!("#{} {}", depth, symbolize(frame.entry, symbols)),
+                            theme.accent(),
+                        ),
+                        Span::styled(
+                            format!(" from {}", symbolize(frame.call_site, symbols)),
+                            text,
+                        ),
+                    ];
+                    if frame.clobbered {
+                        spans.push(Span::styled(", L clobbered", theme.changed()));
+                    } else if let Some(saved_at) = frame.saved_at {
+                        spans.push(Span::styled(
+                            format!(", L in {}", symbolize(saved_at, symbols)),
+                            Style::default().fg(theme.pc),
+                        ));
+                    }
+                    Line::from(spans)
+                })
+                .collect()
+        };
+
+        let paragraph = Paragraph::new(lines).block(
+            Block::default()
+                .title("Call Stack (o step over, f step out)")
+                .borders(Borders::ALL)
+                .border_style(theme.border()),
+        );
+        f.render_widget(paragraph, area);
+    }
+}
+
+// the label at an address, or the nearest one before it with an offset
+fn symbolize(address: u32, symbols: &[SymbolTable]) -> String {
+    symbols
+        .iter()
+        .filter(|s| s.symbol_type == SymbolType::Relative && s.address <= address)
+        .max_by_key(|s| s.address)
+        .map(|s| match address - s.address {
+            0 => s.label.clone(),
+            offset => format!("{}+{:X}", s.label, offset),
+        })
+        .unwrap_or_else(|| format!("{:06X}", address))
+}
@@ -0,0 +1,90 @@
+use super::keys::KeyMap;
+use super::layout::config_dir;
+use super::theme::{THEME_NAMES, Theme, ThemeColors};
+use crate::error::log_warning;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// config.toml in the config directory, next to layout.toml:
+//
+//   theme = "light"
+//
+//   [themes.mine]
+//   base = "dark"
+//   accent = "#ff8800"
+//
+//   [keys]
+//   step = "F10"
+//   faster = ["+", "="]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    theme: Option<String>,
+    themes: HashMap<String, ThemeColors>,
+    keys: HashMap<String, KeyList>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub theme: Theme,
+    pub keys: KeyMap,
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let theme = match &file.theme {
+            Some(name) => theme_named(name, &file.themes)?,
+            None => Theme::default(),
+        };
+        let bindings: HashMap<String, Vec<String>> = file
+            .keys
+            .into_iter()
+            .map(|(action, keys)| match keys {
+                KeyList::One(key) => (action, vec![key]),
+                KeyList::Many(keys) => (action, keys),
+            })
+            .collect();
+        Ok(Config {
+            theme,
+            keys: KeyMap::with_bindings(&bindings)?,
+        })
+    }
+
+    // the defaults when there is no config.toml, or it has a mistake
+    pub fn load() -> Config {
+        let Some(path) = config_dir().map(|dir| dir.join("config.toml")) else {
+            return Config::default();
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::from_toml(&text).unwrap_or_else(|e| {
+                log_warning(&format!("Ignoring {}: {}", path.display(), e));
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+}
+
+// a theme from [themes], built on its base, or one of the built-in ones
+fn theme_named(name: &str, themes: &HashMap<String, ThemeColors>) -> Result<Theme, String> {
+    if let Some(colors) = themes.get(name) {
+        let base = colors.base.as_deref().unwrap_or("dark");
+        let mut theme = Theme::builtin(base)
+            .ok_or_else(|| format!("Theme '{}' is based on unknown theme '{}'", name, base))?;
+        theme.apply(colors)?;
+        return Ok(theme);
+    }
+    Theme::builtin(name).ok_or_else(|| {
+        let mut names: Vec<&str> = THEME_NAMES.to_vec();
+        names.extend(themes.keys().map(String::as_str));
+        format!("No theme '{}', there are {}", name, names.join(", "))
+    })
+}
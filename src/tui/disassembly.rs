@@ -1,11 +1,20 @@
+use super::theme::Theme;
 use ratatui::{
     Frame,
-    style::{Color, Modifier, Style},
+    layout::Rect,
+    text::{Line, Span},
     widgets::{Block, Borders, List, ListItem},
 };
+use std::cell::Cell;
 
 pub struct DisassemblyWidget {
     pub instructions: Vec<(u32, String, String)>, // (address, opcode, instruction)
+    pub breakpoints: Vec<u32>,
+    // the first line shown once scrolled with the wheel, None follows the PC
+    pub offset: Option<usize>,
+    // where the last render put the lines and which one is at the top, for clicks
+    area: Cell<Rect>,
+    top: Cell<usize>,
 }
 
 impl Default for DisassemblyWidget {
@@ -18,35 +27,74 @@ impl DisassemblyWidget {
     pub fn new() -> Self {
         Self {
             instructions: Vec::new(),
+            breakpoints: Vec::new(),
+            offset: None,
+            area: Cell::new(Rect::default()),
+            top: Cell::new(0),
         }
     }
 
-    pub fn render(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+    pub fn scroll(&mut self, lines: isize) {
+        let last = self.instructions.len().saturating_sub(1);
+        self.offset = Some(self.top.get().saturating_add_signed(lines).min(last));
+    }
+
+    // the address of the instruction under a click, None when the click is elsewhere
+    pub fn click(&self, column: u16, row: u16) -> Option<u32> {
+        let area = self.area.get();
+        if !area.contains(ratatui::layout::Position::new(column, row)) {
+            return None;
+        }
+        let index = self.top.get() + (row - area.y) as usize;
+        self.instructions.get(index).map(|(addr, _, _)| *addr)
+    }
+
+    pub fn render(&self, f: &mut Frame, area: Rect, theme: &Theme) {
+        let block = Block::default()
+            .title("Disassembly (click to break)")
+            .borders(Borders::ALL)
+            .border_style(theme.border());
+        let inner = block.inner(area);
+        self.area.set(inner);
+
+        // the PC's line stays in view until the wheel takes over
+        let height = inner.height as usize;
+        let top = self.offset.unwrap_or_else(|| {
+            let current = self
+                .instructions
+                .iter()
+                .position(|(_, _, marker)| marker == ">")
+                .unwrap_or(0);
+            current
+                .saturating_sub(height / 2)
+                .min(self.instructions.len().saturating_sub(height))
+        });
+        self.top.set(top);
+
         let items: Vec<ListItem> = self
             .instructions
             .iter()
+            .skip(top)
+            .take(height)
             .map(|(addr, instr, marker)| {
-                let line = format!("{} {:06X}  {}", marker, addr, instr);
-                if marker == ">" {
-                    ListItem::new(line).style(
-                        Style::default()
-                            .fg(Color::Rgb(255, 200, 0))
-                            .add_modifier(Modifier::BOLD),
-                    )
+                let style = if marker == ">" {
+                    theme.bold_accent()
+                } else {
+                    theme.text()
+                };
+                let breakpoint = if self.breakpoints.contains(addr) {
+                    "●"
                 } else {
-                    ListItem::new(line).style(Style::default().fg(Color::Rgb(200, 200, 200)))
-                }
+                    " "
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(breakpoint, theme.changed()),
+                    Span::styled(format!("{} {:06X}  {}", marker, addr, instr), style),
+                ]))
             })
             .collect();
 
-        let list = List::new(items)
-            .block(
-                Block::default()
-                    .title("Disassembly")
-                    .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Cyan)),
-            )
-            .style(Style::default().fg(Color::White));
+        let list = List::new(items).block(block).style(theme.text());
 
         f.render_widget(list, area);
     }
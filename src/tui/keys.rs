@@ -0,0 +1,268 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+
+// What a key does in the debugger, bound to keys by name in the [keys] table of
+// config.toml
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Step,
+    Run,
+    Pause,
+    Faster,
+    Slower,
+    Break,
+    NextLine,
+    StepOver,
+    StepOut,
+    EditRegisters,
+    Command,
+    NextTab,
+    PreviousTab,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Goto,
+    Search,
+    WordView,
+    Charset,
+    Follow,
+    PinMemory,
+    CloseMemory,
+    PreviousMemory,
+    NextMemory,
+    Zoom,
+    Narrower,
+    Wider,
+    Shorter,
+    Taller,
+    SourceDown,
+    SourceUp,
+    ToggleLine,
+    FollowPc,
+    PreviousButton,
+    NextButton,
+    Press,
+}
+
+// each action with its name in config.toml and its keys out of the box
+const DEFAULTS: [(Action, &str, &[&str]); 39] = [
+    (Action::Quit, "quit", &["q"]),
+    (Action::Step, "step", &["s"]),
+    (Action::Run, "run", &["r"]),
+    (Action::Pause, "pause", &["p"]),
+    (Action::Faster, "faster", &["+", "="]),
+    (Action::Slower, "slower", &["-"]),
+    (Action::Break, "break", &["b"]),
+    (Action::NextLine, "next_line", &["n"]),
+    (Action::StepOver, "step_over", &["o"]),
+    (Action::StepOut, "step_out", &["f"]),
+    (Action::EditRegisters, "edit_registers", &["e"]),
+    (Action::Command, "command", &[":"]),
+    (Action::NextTab, "next_tab", &["Tab"]),
+    (Action::PreviousTab, "previous_tab", &["BackTab"]),
+    (Action::ScrollUp, "scroll_up", &["Up"]),
+    (Action::ScrollDown, "scroll_down", &["Down"]),
+    (Action::PageUp, "page_up", &["PageUp"]),
+    (Action::PageDown, "page_down", &["PageDown"]),
+    (Action::Goto, "goto", &["g"]),
+    (Action::Search, "search", &["/"]),
+    (Action::WordView, "word_view", &["w"]),
+    (Action::Charset, "charset", &["c"]),
+    (Action::Follow, "follow", &["F"]),
+    (Action::PinMemory, "pin_memory", &["m"]),
+    (Action::CloseMemory, "close_memory", &["M"]),
+    (Action::PreviousMemory, "previous_memory", &["<"]),
+    (Action::NextMemory, "next_memory", &[">"]),
+    (Action::Zoom, "zoom", &["z"]),
+    (Action::Narrower, "narrower", &["["]),
+    (Action::Wider, "wider", &["]"]),
+    (Action::Shorter, "shorter", &["{"]),
+    (Action::Taller, "taller", &["}"]),
+    (Action::SourceDown, "source_down", &["j"]),
+    (Action::SourceUp, "source_up", &["k"]),
+    (Action::ToggleLine, "toggle_line", &["Space"]),
+    (Action::FollowPc, "follow_pc", &["Esc"]),
+    (Action::PreviousButton, "previous_button", &["Left"]),
+    (Action::NextButton, "next_button", &["Right"]),
+    (Action::Press, "press", &["Enter"]),
+];
+
+// the status bar, in this order, with the actions each entry stands for
+const STATUS_BAR: [(&[Action], &str); 16] = [
+    (&[Action::Quit], "quit"),
+    (&[Action::Step], "step"),
+    (&[Action::Run], "run"),
+    (&[Action::Pause], "pause"),
+    (&[Action::Faster, Action::Slower], "speed"),
+    (&[Action::Break], "break"),
+    (&[Action::NextLine], "next line"),
+    (&[Action::StepOver], "step over"),
+    (&[Action::StepOut], "step out"),
+    (&[Action::EditRegisters], "edit registers"),
+    (&[Action::Command], "command"),
+    (&[Action::NextTab], "switch tabs"),
+    (&[Action::ScrollUp, Action::ScrollDown], "scroll"),
+    (&[Action::Goto, Action::Search], "goto, search"),
+    (&[Action::PinMemory, Action::CloseMemory], "memory windows"),
+    (&[Action::Zoom], "zoom"),
+];
+
+// a key with Ctrl held or not, the case of a letter stands for Shift
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub ctrl: bool,
+}
+
+impl Key {
+    // "s", "F", "Ctrl+n", "PageUp", "Space", "F5" and so on
+    pub fn parse(text: &str) -> Result<Key, String> {
+        let (ctrl, name) = match text.strip_prefix("Ctrl+").or(text.strip_prefix("ctrl+")) {
+            Some(name) => (true, name),
+            None => (false, text),
+        };
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name.to_ascii_lowercase().as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                function => match function
+                    .strip_prefix('f')
+                    .and_then(|n| n.parse::<u8>().ok())
+                {
+                    Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                    _ => return Err(format!("Unknown key '{}'", text)),
+                },
+            },
+        };
+        Ok(Key { code, ctrl })
+    }
+
+    fn from_event(event: &KeyEvent) -> Key {
+        Key {
+            code: event.code,
+            ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+        }
+    }
+
+    // how the status bar shows the key
+    pub fn label(&self) -> String {
+        let name = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Up => "↑".to_string(),
+            KeyCode::Down => "↓".to_string(),
+            KeyCode::Left => "←".to_string(),
+            KeyCode::Right => "→".to_string(),
+            KeyCode::PageUp => "PgUp".to_string(),
+            KeyCode::PageDown => "PgDn".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            code => format!("{:?}", code),
+        };
+        if self.ctrl {
+            format!("Ctrl+{}", name)
+        } else {
+            name
+        }
+    }
+}
+
+pub struct KeyMap {
+    actions: HashMap<Key, Action>,
+    keys: HashMap<Action, Vec<Key>>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut map = KeyMap {
+            actions: HashMap::new(),
+            keys: HashMap::new(),
+        };
+        for (action, _, keys) in DEFAULTS {
+            let keys: Vec<Key> = keys.iter().map(|k| Key::parse(k).unwrap()).collect();
+            map.bind(action, keys);
+        }
+        map
+    }
+}
+
+impl KeyMap {
+    // the defaults with the [keys] table over them, a key taken from another action
+    // leaves it
+    pub fn with_bindings(bindings: &HashMap<String, Vec<String>>) -> Result<KeyMap, String> {
+        let mut map = KeyMap::default();
+        let mut names: Vec<&String> = bindings.keys().collect();
+        names.sort();
+        for name in names {
+            let action =
+                action_named(name).ok_or_else(|| format!("Unknown action '{}' in [keys]", name))?;
+            let keys = bindings[name]
+                .iter()
+                .map(|k| Key::parse(k))
+                .collect::<Result<Vec<Key>, String>>()?;
+            map.bind(action, keys);
+        }
+        Ok(map)
+    }
+
+    fn bind(&mut self, action: Action, keys: Vec<Key>) {
+        for key in self.keys.remove(&action).unwrap_or_default() {
+            self.actions.remove(&key);
+        }
+        for key in keys.iter() {
+            if let Some(previous) = self.actions.insert(*key, action)
+                && let Some(taken) = self.keys.get_mut(&previous)
+            {
+                taken.retain(|k| k != key);
+            }
+        }
+        self.keys.insert(action, keys);
+    }
+
+    pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+        self.actions.get(&Key::from_event(event)).copied()
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.keys.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    // the status bar entries as (keys, description), left out when nothing is bound
+    pub fn status_bar(&self) -> Vec<(String, &'static str)> {
+        STATUS_BAR
+            .iter()
+            .filter_map(|(actions, description)| {
+                let labels: Vec<String> = actions
+                    .iter()
+                    .filter_map(|action| self.keys(*action).first())
+                    .map(Key::label)
+                    .collect();
+                (!labels.is_empty()).then(|| (labels.join(" "), *description))
+            })
+            .collect()
+    }
+}
+
+pub fn action_named(name: &str) -> Option<Action> {
+    DEFAULTS
+        .iter()
+        .find(|(_, n, _)| *n == name)
+        .map(|(action, _, _)| *action)
+}
@@ -0,0 +1,145 @@
+use crate::error::log_warning;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// The panes of the debugger that can be focused, zoomed and hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pane {
+    Registers,
+    Controls,
+    Disassembly,
+    CallStack,
+    Source,
+    Tables,
+    Memory,
+}
+
+impl Pane {
+    pub const ALL: [Pane; 7] = [
+        Pane::Registers,
+        Pane::Controls,
+        Pane::Disassembly,
+        Pane::CallStack,
+        Pane::Source,
+        Pane::Tables,
+        Pane::Memory,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pane::Registers => "registers",
+            Pane::Controls => "controls",
+            Pane::Disassembly => "disassembly",
+            Pane::CallStack => "callstack",
+            Pane::Source => "source",
+            Pane::Tables => "tables",
+            Pane::Memory => "memory",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pane> {
+        Pane::ALL
+            .into_iter()
+            .find(|pane| pane.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// How the screen is split, in percent, and which panes are left out. Saved in
+// layout.toml in the config directory when the debugger quits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    pub left_width: u16,   // of the screen, for registers, disassembly and call stack
+    pub top_height: u16,   // of the right column, for source and tables above memory
+    pub source_width: u16, // of the top right, for the source next to the tables
+    pub hidden: Vec<Pane>,
+}
+
+// a pane never shrinks below this share of what it is split from
+pub const MIN_PERCENT: u16 = 10;
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            left_width: 30,
+            top_height: 50,
+            source_width: 60,
+            hidden: Vec::new(),
+        }
+    }
+}
+
+impl LayoutConfig {
+    pub fn is_visible(&self, pane: Pane) -> bool {
+        !self.hidden.contains(&pane)
+    }
+
+    // the controls stay, without them the mouse could not run anything
+    pub fn set_visible(&mut self, pane: Pane, visible: bool) -> Result<(), String> {
+        if pane == Pane::Controls && !visible {
+            return Err("The controls cannot be hidden".to_string());
+        }
+        self.hidden.retain(|p| *p != pane);
+        if !visible {
+            self.hidden.push(pane);
+        }
+        Ok(())
+    }
+
+    pub fn clamp(percent: i32) -> u16 {
+        percent.clamp(MIN_PERCENT as i32, 100 - MIN_PERCENT as i32) as u16
+    }
+
+    pub fn from_toml(text: &str) -> Result<LayoutConfig, String> {
+        let mut layout: LayoutConfig = toml::from_str(text).map_err(|e| e.to_string())?;
+        layout.left_width = Self::clamp(layout.left_width as i32);
+        layout.top_height = Self::clamp(layout.top_height as i32);
+        layout.source_width = Self::clamp(layout.source_width as i32);
+        Ok(layout)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    // the saved layout, the default one when there is none or it cannot be read
+    pub fn load() -> LayoutConfig {
+        let Some(path) = layout_path() else {
+            return LayoutConfig::default();
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::from_toml(&text).unwrap_or_else(|e| {
+                log_warning(&format!("Ignoring {}: {}", path.display(), e));
+                LayoutConfig::default()
+            }),
+            Err(_) => LayoutConfig::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = layout_path() else {
+            return;
+        };
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, self.to_toml()));
+        if let Err(e) = written {
+            log_warning(&format!("Could not save {}: {}", path.display(), e));
+        }
+    }
+}
+
+// $XDG_CONFIG_HOME/hexe, else ~/.config/hexe
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("hexe"))
+}
+
+fn layout_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("layout.toml"))
+}
@@ -1,109 +1,356 @@
+use super::theme::Theme;
 use crate::predefined::common::{OBJECTPROGRAM, ObjectRecord};
 use ratatui::{
     Frame,
-    style::{Color, Style},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
     text::{Line, Span},
     widgets::{Block, Borders, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState},
 };
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+// what a memory window keeps in view by itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Follow {
+    Off,
+    Pc,
+    EffectiveAddress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Byte,
+    Word, // 24-bit
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    Ascii,
+    Ebcdic,
+}
+
+// one memory window: the address at its top and how it shows what is there
+#[derive(Debug, Clone)]
+pub struct MemoryView {
+    pub top: u32,
+    pub unit: Unit,
+    pub charset: Charset,
+    pub follow: Follow,
+}
+
+impl MemoryView {
+    fn new() -> Self {
+        Self {
+            top: 0,
+            unit: Unit::Byte,
+            charset: Charset::Ascii,
+            follow: Follow::Off,
+        }
+    }
+
+    // 16 bytes, or 6 words so that no word is split over two lines
+    pub fn line_bytes(&self) -> u32 {
+        match self.unit {
+            Unit::Byte => 16,
+            Unit::Word => 18,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let unit = match self.unit {
+            Unit::Byte => "bytes",
+            Unit::Word => "words",
+        };
+        let charset = match self.charset {
+            Charset::Ascii => "ASCII",
+            Charset::Ebcdic => "EBCDIC",
+        };
+        match self.follow {
+            Follow::Off => format!("{}, {}", unit, charset),
+            Follow::Pc => format!("{}, {}, follows PC", unit, charset),
+            Follow::EffectiveAddress => format!("{}, {}, follows EA", unit, charset),
+        }
+    }
+}
 
-#[allow(dead_code)]
+// the simulator's memory in one or more windows, only what they show is copied in
+// before each frame
 pub struct MemoryWidget {
-    pub memory: Vec<u8>,
-    pub start_address: usize,
-    pub scroll_offset: u16,
+    memory_size: u32,
+    shown: Vec<(u32, Vec<u8>)>, // the bytes each window shows, from its top
     pub max_address: u32,
+    pub changed: Vec<u32>,                // by the last step
+    pub changed_since_stop: HashSet<u32>, // since execution last stopped, of those shown
+    pub pc: u32,
+    pub last_address: Option<u32>, // effective address of the last instruction
+    pub views: Vec<MemoryView>,
+    pub active: usize,
+    pub found: Option<(u32, usize)>, // address and length of the last search match
+    // lines each window showed at the last render, for paging and following
+    visible_lines: Cell<u32>,
+    // where each window was drawn, for the mouse
+    areas: RefCell<Vec<Rect>>,
+    // rows of the screen at the last render, no window shows more lines than that
+    screen_rows: Cell<u32>,
 }
 
+// windows pinned at once, more would leave too few lines in each
+pub const MAX_VIEWS: usize = 4;
+
 impl MemoryWidget {
     pub fn new(memory_size: usize) -> Self {
         Self {
-            memory: vec![0; memory_size],
-            start_address: 0,
-            scroll_offset: 0,
+            memory_size: memory_size as u32,
+            shown: Vec::new(),
             max_address: 0,
+            changed: Vec::new(),
+            changed_since_stop: HashSet::new(),
+            pc: 0,
+            last_address: None,
+            views: vec![MemoryView::new()],
+            active: 0,
+            found: None,
+            visible_lines: Cell::new(10),
+            areas: RefCell::new(Vec::new()),
+            screen_rows: Cell::new(100),
         }
     }
 
+    pub fn view(&mut self) -> &mut MemoryView {
+        &mut self.views[self.active]
+    }
+
+    // Everything up to 64K past the program can be scrolled to
+    fn end_address(&self) -> u32 {
+        (self.max_address + 0x10000)
+            .max(0x10000)
+            .min(self.memory_size)
+    }
+
+    // moves the active window by whole lines, back for a negative count
+    pub fn scroll(&mut self, lines: i64) {
+        let end = self.end_address();
+        let visible = self.visible_lines.get();
+        let view = self.view();
+        let line = view.line_bytes() as i64;
+        let last_top = (end as i64 - visible as i64 * line).max(0);
+        view.top = (view.top as i64 + lines * line).clamp(0, last_top) as u32;
+    }
+
     pub fn scroll_up(&mut self) {
-        self.scroll_offset = self.scroll_offset.saturating_sub(1);
+        self.scroll(-1);
     }
 
     pub fn scroll_down(&mut self) {
-        let max_scroll = (self.max_address / 16).saturating_sub(10);
-        if (self.scroll_offset as u32) < max_scroll {
-            self.scroll_offset += 1;
-        }
+        self.scroll(1);
     }
 
-    pub fn scroll_to_address(&mut self, addr: u32) {
-        // Scroll to show the address in the middle of the view
-        let line_number = (addr / 16).saturating_sub(5);
-        self.scroll_offset = line_number as u16;
+    pub fn page_up(&mut self) {
+        self.scroll(-(self.visible_lines.get() as i64));
     }
 
-    pub fn render(&self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let mut lines = Vec::new();
+    pub fn page_down(&mut self) {
+        self.scroll(self.visible_lines.get() as i64);
+    }
+
+    pub fn scroll_to_address(&mut self, addr: u32) {
+        let visible = self.visible_lines.get();
+        Self::show(self.view(), addr, visible);
+    }
 
-        // Build complete memory map from OBJECTPROGRAM
-        let end_addr = (self.max_address + 0x10000).max(0x10000);
-        let mut memory_bytes: Vec<u8> = vec![0; end_addr as usize];
+    // puts the address a few lines down from the top of the window
+    fn show(view: &mut MemoryView, address: u32, visible: u32) {
+        let line = view.line_bytes();
+        let above = (visible / 3).min(4);
+        view.top = (address / line).saturating_sub(above) * line;
+    }
 
-        if let Ok(obj_prog) = OBJECTPROGRAM.lock() {
-            for record in obj_prog.iter() {
-                if let ObjectRecord::Text {
-                    start, objcodes, ..
-                } = record
-                {
-                    let mut current_addr = *start;
-
-                    for code in objcodes {
-                        // Convert hex string to bytes
-                        for i in (0..code.len()).step_by(2) {
-                            if i + 1 < code.len() {
-                                if let Ok(byte) = u8::from_str_radix(&code[i..i + 2], 16) {
-                                    if (current_addr as usize) < memory_bytes.len() {
-                                        memory_bytes[current_addr as usize] = byte;
-                                    }
-                                    current_addr += 1;
-                                }
-                            } else if i < code.len() {
-                                // Handle odd-length hex strings
-                                if let Ok(byte) = u8::from_str_radix(&code[i..i + 1], 16) {
-                                    if (current_addr as usize) < memory_bytes.len() {
-                                        memory_bytes[current_addr as usize] = byte;
-                                    }
-                                    current_addr += 1;
-                                }
-                            }
-                        }
-                    }
-                }
+    // windows that follow the PC or EA scroll when it leaves them
+    pub fn follow(&mut self) {
+        let visible = self.visible_lines.get();
+        for view in self.views.iter_mut() {
+            let target = match view.follow {
+                Follow::Off => None,
+                Follow::Pc => Some(self.pc),
+                Follow::EffectiveAddress => self.last_address,
+            };
+            if let Some(address) = target
+                && (address < view.top || address >= view.top + visible * view.line_bytes())
+            {
+                Self::show(view, address, visible);
             }
         }
+    }
 
-        // Generate memory dump lines from 0x000000 to end_addr
-        let end_addr_aligned = end_addr & !0xF; // Align to 16
+    pub fn toggle_unit(&mut self) {
+        let view = self.view();
+        view.unit = match view.unit {
+            Unit::Byte => Unit::Word,
+            Unit::Word => Unit::Byte,
+        };
+        // realigned so the first address stays in view
+        view.top -= view.top % view.line_bytes();
+    }
 
-        for addr in (0..=end_addr_aligned).step_by(16) {
-            let line = self.format_memory_line_colored(addr, &memory_bytes);
-            lines.push(line);
+    pub fn toggle_charset(&mut self) {
+        let view = self.view();
+        view.charset = match view.charset {
+            Charset::Ascii => Charset::Ebcdic,
+            Charset::Ebcdic => Charset::Ascii,
+        };
+    }
+
+    pub fn cycle_follow(&mut self) {
+        let view = self.view();
+        view.follow = match view.follow {
+            Follow::Off => Follow::Pc,
+            Follow::Pc => Follow::EffectiveAddress,
+            Follow::EffectiveAddress => Follow::Off,
+        };
+        self.follow();
+    }
+
+    // a new window showing what the active one shows, which stays where it is
+    pub fn pin(&mut self) {
+        if self.views.len() < MAX_VIEWS {
+            let view = self.views[self.active].clone();
+            self.views.insert(self.active + 1, view);
+            self.active += 1;
+        }
+    }
+
+    pub fn close(&mut self) {
+        if self.views.len() > 1 {
+            self.views.remove(self.active);
+            self.active = self.active.min(self.views.len() - 1);
+        }
+    }
+
+    // the window under the mouse, which becomes the active one
+    pub fn click(&mut self, column: u16, row: u16) -> Option<usize> {
+        let index = self
+            .areas
+            .borrow()
+            .iter()
+            .position(|area| area.contains(ratatui::layout::Position::new(column, row)))?;
+        self.active = index.min(self.views.len() - 1);
+        Some(self.active)
+    }
+
+    pub fn next_view(&mut self) {
+        self.active = (self.active + 1) % self.views.len();
+    }
+
+    pub fn previous_view(&mut self) {
+        self.active = (self.active + self.views.len() - 1) % self.views.len();
+    }
+
+    // the match is highlighted and the active window moves to it
+    pub fn show_found(&mut self, address: u32, length: usize) {
+        self.found = Some((address, length));
+        self.scroll_to_address(address);
+    }
+
+    // where a search goes on from: past the last match, else the top of the window
+    pub fn search_start(&self) -> u32 {
+        match self.found {
+            Some((address, _)) => address + 1,
+            None => self.views[self.active].top,
         }
+    }
 
-        // Apply scroll offset
-        let visible_lines: Vec<Line> = lines
-            .into_iter()
-            .skip(self.scroll_offset as usize)
-            .take(area.height.saturating_sub(2) as usize)
+    // copies in the bytes the windows show and the marks on them, after they followed
+    // the PC or EA
+    pub fn update(&mut self, memory: &[u8], changed_since_stop: &HashSet<u32>) {
+        self.memory_size = memory.len() as u32;
+        self.update_max_address();
+        self.follow();
+        let rows = self.screen_rows.get();
+        self.shown = self
+            .views
+            .iter()
+            .map(|view| {
+                let start = (view.top as usize).min(memory.len());
+                let end = (start + (rows * view.line_bytes()) as usize).min(memory.len());
+                (view.top, memory[start..end].to_vec())
+            })
+            .collect();
+        self.changed_since_stop = self
+            .shown
+            .iter()
+            .flat_map(|(top, bytes)| *top..*top + bytes.len() as u32)
+            .filter(|address| changed_since_stop.contains(address))
             .collect();
+    }
 
+    pub fn render(&self, f: &mut Frame, area: Rect, theme: &Theme) {
+        self.screen_rows.set(f.area().height as u32);
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Ratio(1, self.views.len() as u32);
+                self.views.len()
+            ])
+            .split(area);
+        self.visible_lines
+            .set(areas[0].height.saturating_sub(2).max(1) as u32);
+        self.areas.replace(areas.to_vec());
+        for (i, (view, area)) in self.views.iter().zip(areas.iter()).enumerate() {
+            self.render_view(f, *area, view, i, theme);
+        }
+    }
+
+    fn render_view(
+        &self,
+        f: &mut Frame,
+        area: Rect,
+        view: &MemoryView,
+        index: usize,
+        theme: &Theme,
+    ) {
+        let line_bytes = view.line_bytes();
+        let end = self.end_address();
+        let height = area.height.saturating_sub(2) as u32;
+
+        // Only the lines on screen are built
+        let visible_lines: Vec<Line> = (0..height)
+            .map(|line| view.top + line * line_bytes)
+            .take_while(|addr| *addr < end)
+            .map(|addr| self.format_memory_line_colored(addr, view, theme))
+            .collect();
+
+        let active = index == self.active;
+        let mut title = vec![Span::raw(format!(
+            "Memory {} ({}) ",
+            index + 1,
+            view.describe()
+        ))];
+        if active {
+            let legend = |text: &'static str, style: Style| Span::styled(text, style);
+            title.extend([
+                legend(" PC ", theme.highlight(theme.pc)),
+                Span::raw(" "),
+                legend(" EA ", theme.highlight(theme.address)),
+                Span::raw(" "),
+                legend("step", changed_style(theme)),
+                Span::raw(" "),
+                legend("since stop", Style::default().fg(theme.changed_since_stop)),
+            ]);
+        }
+        let border = if active && self.views.len() > 1 {
+            theme.accent
+        } else {
+            theme.border
+        };
         let paragraph = Paragraph::new(visible_lines)
             .block(
                 Block::default()
-                    .title("Memory Dump (↑↓ to scroll)")
+                    .title(Line::from(title))
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Cyan)),
+                    .border_style(Style::default().fg(border)),
             )
-            .style(Style::default().fg(Color::Rgb(200, 200, 200)));
+            .style(theme.text());
 
         f.render_widget(paragraph, area);
 
@@ -113,12 +360,11 @@ impl MemoryWidget {
             .begin_symbol(Some("↑"))
             .end_symbol(Some("↓"));
 
-        let total_lines = (end_addr_aligned / 16) as usize;
-        let visible_height = area.height.saturating_sub(2) as usize;
-        let max_scroll = total_lines.saturating_sub(visible_height);
+        let total_lines = (end / line_bytes) as usize;
+        let max_scroll = total_lines.saturating_sub(height as usize);
 
         let mut scrollbar_state =
-            ScrollbarState::new(max_scroll).position(self.scroll_offset as usize);
+            ScrollbarState::new(max_scroll).position((view.top / line_bytes) as usize);
 
         f.render_stateful_widget(
             scrollbar,
@@ -130,58 +376,76 @@ impl MemoryWidget {
         );
     }
 
-    fn format_memory_line_colored<'a>(&'a self, addr: u32, memory: &'a [u8]) -> Line<'a> {
+    fn format_memory_line_colored(
+        &self,
+        addr: u32,
+        view: &MemoryView,
+        theme: &Theme,
+    ) -> Line<'static> {
         let mut spans = Vec::new();
 
         // Address in golden yellow
-        spans.push(Span::styled(
-            format!("{:07x}", addr),
-            Style::default().fg(Color::Rgb(255, 200, 0)),
-        ));
-
-        // Memory bytes in light gray
-        let mut bytes_str = String::new();
-        for i in 0..16 {
-            if i % 2 == 0 {
-                bytes_str.push(' ');
-            }
-
-            let byte_addr = (addr + i) as usize;
-            if byte_addr < memory.len() {
-                bytes_str.push_str(&format!("{:02x}", memory[byte_addr]));
-            } else {
-                bytes_str.push_str("00");
+        spans.push(Span::styled(format!("{:07x}", addr), theme.accent()));
+
+        // Memory bytes in light gray, unless they are marked, in pairs or words
+        let group = match view.unit {
+            Unit::Byte => 2,
+            Unit::Word => 3,
+        };
+        let line = addr..addr + view.line_bytes();
+        for byte_addr in line.clone() {
+            if (byte_addr - addr).is_multiple_of(group) {
+                spans.push(Span::raw(" "));
             }
+            spans.push(Span::styled(
+                format!("{:02x}", self.byte(byte_addr)),
+                self.byte_style(byte_addr, theme),
+            ));
         }
 
-        spans.push(Span::styled(
-            bytes_str,
-            Style::default().fg(Color::Rgb(200, 200, 200)),
-        ));
+        spans.push(Span::raw("  "));
+        let text: String = line
+            .map(|byte_addr| {
+                let byte = self.byte(byte_addr);
+                match view.charset {
+                    Charset::Ascii if byte.is_ascii_graphic() || byte == b' ' => byte as char,
+                    Charset::Ascii => '.',
+                    Charset::Ebcdic => ebcdic(byte).unwrap_or('.'),
+                }
+            })
+            .collect();
+        spans.push(Span::styled(text, theme.dim()));
 
         Line::from(spans)
     }
 
-    // Keep the old method for compatibility if needed elsewhere
-    // fn format_memory_line(&self, addr: u32, memory: &[u8]) -> String {
-    //     let mut result = format!("{:07x}", addr);
-
-    //     // Add 16 bytes in pairs
-    //     for i in 0..16 {
-    //         if i % 2 == 0 {
-    //             result.push(' ');
-    //         }
-
-    //         let byte_addr = (addr + i) as usize;
-    //         if byte_addr < memory.len() {
-    //             result.push_str(&format!("{:02x}", memory[byte_addr]));
-    //         } else {
-    //             result.push_str("00");
-    //         }
-    //     }
+    fn byte(&self, address: u32) -> u8 {
+        self.shown
+            .iter()
+            .find_map(|(top, bytes)| bytes.get(address.checked_sub(*top)? as usize))
+            .copied()
+            .unwrap_or(0)
+    }
 
-    //     result
-    // }
+    // PC and the effective address win over the search match and change highlights
+    fn byte_style(&self, address: u32, theme: &Theme) -> Style {
+        let found = self
+            .found
+            .is_some_and(|(start, length)| address >= start && address < start + length as u32);
+        if address == self.pc {
+            theme.highlight(theme.pc)
+        } else if self.last_address == Some(address) {
+            theme.highlight(theme.address)
+        } else if found {
+            theme.highlight(theme.found)
+        } else if self.changed.contains(&address) {
+            changed_style(theme)
+        } else if self.changed_since_stop.contains(&address) {
+            Style::default().fg(theme.changed_since_stop)
+        } else {
+            theme.text()
+        }
+    }
 
     pub fn update_max_address(&mut self) {
         // Calculate max address from OBJECTPROGRAM
@@ -189,15 +453,8 @@ impl MemoryWidget {
 
         if let Ok(obj_prog) = OBJECTPROGRAM.lock() {
             for record in obj_prog.iter() {
-                if let ObjectRecord::Text {
-                    start, objcodes, ..
-                } = record
-                {
-                    let mut current_addr = *start;
-                    for code in objcodes {
-                        current_addr += (code.len() / 2) as u32;
-                    }
-                    max_addr = max_addr.max(current_addr);
+                if let ObjectRecord::Text { start, bytes, .. } = record {
+                    max_addr = max_addr.max(start + bytes.len() as u32);
                 }
             }
         }
@@ -217,3 +474,55 @@ impl MemoryWidget {
         }
     }
 }
+
+fn changed_style(theme: &Theme) -> Style {
+    theme.changed().add_modifier(Modifier::BOLD)
+}
+
+// the printable characters of EBCDIC (code page 037)
+pub fn ebcdic(byte: u8) -> Option<char> {
+    let letter = |base: u8, first: char| Some((first as u8 + (byte - base)) as char);
+    match byte {
+        0x40 => Some(' '),
+        0x81..=0x89 => letter(0x81, 'a'),
+        0x91..=0x99 => letter(0x91, 'j'),
+        0xA2..=0xA9 => letter(0xA2, 's'),
+        0xC1..=0xC9 => letter(0xC1, 'A'),
+        0xD1..=0xD9 => letter(0xD1, 'J'),
+        0xE2..=0xE9 => letter(0xE2, 'S'),
+        0xF0..=0xF9 => letter(0xF0, '0'),
+        _ => {
+            const PUNCTUATION: [(u8, char); 25] = [
+                (0x4B, '.'),
+                (0x4C, '<'),
+                (0x4D, '('),
+                (0x4E, '+'),
+                (0x4F, '|'),
+                (0x50, '&'),
+                (0x5A, '!'),
+                (0x5B, '$'),
+                (0x5C, '*'),
+                (0x5D, ')'),
+                (0x5E, ';'),
+                (0x60, '-'),
+                (0x61, '/'),
+                (0x6B, ','),
+                (0x6C, '%'),
+                (0x6D, '_'),
+                (0x6E, '>'),
+                (0x6F, '?'),
+                (0x7A, ':'),
+                (0x7B, '#'),
+                (0x7C, '@'),
+                (0x7D, '\''),
+                (0x7E, '='),
+                (0x7F, '"'),
+                (0xA1, '~'),
+            ];
+            PUNCTUATION
+                .iter()
+                .find(|(code, _)| *code == byte)
+                .map(|(_, c)| *c)
+        }
+    }
+}
@@ -1,9 +1,16 @@
 #![allow(clippy::module_name_repetitions)]
 #![allow(clippy::module_inception)]
+pub mod callstack;
+pub mod config;
 pub mod disassembly;
+pub mod keys;
+pub mod layout;
 pub mod memory;
+pub mod prompt;
 pub mod registers;
+pub mod source;
 pub mod tabs;
+pub mod theme;
 pub mod tui;
 
 pub use tui::Tui;
@@ -0,0 +1,113 @@
+use super::theme::Theme;
+use crossterm::event::KeyCode;
+use ratatui::text::{Line, Span};
+
+// The `:` command line: what is being typed, earlier commands for ↑↓ and the lines
+// printed by the last one
+pub struct PromptWidget {
+    pub input: Option<String>,
+    pub output: Vec<String>,
+    pub error: bool, // the output is an error message
+    history: Vec<String>,
+    history_index: usize,
+}
+
+// output lines shown at once, a longer dump keeps its first lines
+pub const MAX_OUTPUT_LINES: usize = 8;
+
+impl Default for PromptWidget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PromptWidget {
+    pub fn new() -> Self {
+        Self {
+            input: None,
+            output: Vec::new(),
+            error: false,
+            history: Vec::new(),
+            history_index: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.input.is_some()
+    }
+
+    // `initial` is typed in already, for keys that start a command
+    pub fn open(&mut self, initial: &str) {
+        self.input = Some(initial.to_string());
+        self.history_index = self.history.len();
+    }
+
+    // Enter hands back the typed line, Esc closes the prompt
+    pub fn handle_key(&mut self, code: KeyCode) -> Option<String> {
+        let input = self.input.as_mut()?;
+        match code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace if input.is_empty() => self.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Up if self.history_index > 0 => {
+                self.history_index -= 1;
+                *input = self.history[self.history_index].clone();
+            }
+            KeyCode::Down if self.history_index < self.history.len() => {
+                self.history_index += 1;
+                *input = self
+                    .history
+                    .get(self.history_index)
+                    .cloned()
+                    .unwrap_or_default();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Enter => {
+                let line = self.input.take()?.trim().to_string();
+                if line.is_empty() {
+                    return None;
+                }
+                if self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                }
+                return Some(line);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    pub fn show(&mut self, result: Result<Vec<String>, String>) {
+        (self.output, self.error) = match result {
+            Ok(lines) => (lines, false),
+            Err(message) => (vec![message], true),
+        };
+    }
+
+    pub fn output_height(&self) -> u16 {
+        self.output.len().min(MAX_OUTPUT_LINES) as u16
+    }
+
+    pub fn lines(&self, theme: &Theme) -> Vec<Line<'static>> {
+        let style = if self.error {
+            theme.changed()
+        } else {
+            theme.text()
+        };
+        let mut lines: Vec<Line> = self
+            .output
+            .iter()
+            .take(MAX_OUTPUT_LINES)
+            .map(|line| Line::from(Span::styled(line.clone(), style)))
+            .collect();
+        if let Some(input) = &self.input {
+            lines.push(Line::from(vec![
+                Span::styled(":", theme.accent()),
+                Span::raw(format!("{}_", input)),
+            ]));
+        }
+        lines
+    }
+}
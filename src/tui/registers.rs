@@ -1,16 +1,26 @@
+use super::theme::Theme;
+use crate::simulator::inistialize_machine::{REGISTER_NAMES, Registers};
+use crossterm::event::KeyCode;
 use ratatui::{
     Frame,
-    layout::Constraint,
-    style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Row, Table},
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Row, Table},
 };
 
 pub struct RegistersWidget {
-    pub a: u32,
-    pub x: u32,
-    pub l: u32,
-    pub pc: u32,
-    pub sw: u32,
+    pub registers: Registers,
+    pub changed: Vec<&'static str>, // by the last step
+    // the row picked for editing, and the value typed into it so far
+    pub selected: Option<usize>,
+    pub input: Option<String>,
+}
+
+// a value typed into a register row, for the simulator to set
+pub struct RegisterEdit {
+    pub register: &'static str,
+    pub value: String,
 }
 
 impl Default for RegistersWidget {
@@ -22,45 +32,167 @@ impl Default for RegistersWidget {
 impl RegistersWidget {
     pub fn new() -> Self {
         Self {
-            a: 0,
-            x: 0,
-            l: 0,
-            pc: 0,
-            sw: 0,
+            registers: Registers::default(),
+            changed: Vec::new(),
+            selected: None,
+            input: None,
         }
     }
 
-    pub fn render(&self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let rows = vec![
-            Row::new(vec!["A".to_string(), format!("{:06X}", self.a)])
-                .style(Style::default().fg(Color::Rgb(200, 200, 200))),
-            Row::new(vec!["X".to_string(), format!("{:06X}", self.x)])
-                .style(Style::default().fg(Color::Rgb(200, 200, 200))),
-            Row::new(vec!["L".to_string(), format!("{:06X}", self.l)])
-                .style(Style::default().fg(Color::Rgb(200, 200, 200))),
-            Row::new(vec!["PC".to_string(), format!("{:06X}", self.pc)]).style(
-                Style::default()
-                    .fg(Color::Rgb(100, 255, 100))
-                    .add_modifier(Modifier::BOLD),
-            ),
-            Row::new(vec!["SW".to_string(), format!("{:06X}", self.sw)])
-                .style(Style::default().fg(Color::Rgb(200, 200, 200))),
-        ];
+    pub fn is_editing(&self) -> bool {
+        self.selected.is_some()
+    }
+
+    pub fn start_editing(&mut self) {
+        self.selected = Some(0);
+        self.input = None;
+    }
+
+    // ↑↓ pick a register, Enter starts typing and Enter again sets it, Esc leaves
+    pub fn handle_key(&mut self, code: KeyCode) -> Option<RegisterEdit> {
+        let selected = self.selected?;
+        match (&mut self.input, code) {
+            (None, KeyCode::Up) => self.selected = Some(selected.saturating_sub(1)),
+            (None, KeyCode::Down) => {
+                self.selected = Some((selected + 1).min(REGISTER_NAMES.len() - 1))
+            }
+            (None, KeyCode::Enter) => self.input = Some(String::new()),
+            (None, KeyCode::Esc) => self.selected = None,
+            (Some(input), KeyCode::Char(c)) => input.push(c),
+            (Some(input), KeyCode::Backspace) => {
+                input.pop();
+            }
+            (Some(_), KeyCode::Esc) => self.input = None,
+            (Some(_), KeyCode::Enter) => {
+                let value = self.input.take().unwrap_or_default();
+                return Some(RegisterEdit {
+                    register: REGISTER_NAMES[selected],
+                    value,
+                });
+            }
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render(&self, f: &mut Frame, area: ratatui::layout::Rect, theme: &Theme) {
+        let block = Block::default()
+            .title(if self.is_editing() {
+                "CPU Registers (editing, Esc to leave)"
+            } else {
+                "CPU Registers (e to edit)"
+            })
+            .borders(Borders::ALL)
+            .border_style(theme.border());
+        let inner = block.inner(area);
+        f.render_widget(block, area);
 
-        let widths = vec![Constraint::Length(4), Constraint::Length(8)];
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(2)])
+            .split(inner);
 
+        let rows: Vec<Row> = REGISTER_NAMES
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let editing = self.selected == Some(i);
+                let mut cells = match (editing, &self.input) {
+                    (true, Some(input)) => {
+                        vec![name.to_string(), format!("{}_", input), String::new()]
+                    }
+                    _ => self.cells(name),
+                };
+                cells.insert(0, if editing { ">" } else { " " }.to_string());
+                Row::new(cells).style(self.row_style(name, editing, theme))
+            })
+            .collect();
+
+        let widths = vec![
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Length(8),
+            Constraint::Length(13),
+            Constraint::Length(5),
+        ];
         let register_table = Table::new(rows, widths)
-            .block(
-                Block::default()
-                    .title("CPU Registers")
-                    .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Cyan)),
-            )
-            .header(
-                Row::new(vec!["Reg", "Value"]).style(Style::default().fg(Color::Rgb(255, 200, 0))),
-            )
-            .style(Style::default().fg(Color::Cyan));
+            .header(Row::new(vec!["", "Reg", "Hex", "Dec", "ASCII"]).style(theme.accent()))
+            .style(theme.border());
+        f.render_widget(register_table, layout[0]);
+
+        f.render_widget(Paragraph::new(self.status_word(theme)), layout[1]);
+    }
+
+    fn cells(&self, name: &str) -> Vec<String> {
+        let Some(value) = self.registers.get(name) else {
+            // F is a floating point number, not a 24-bit word
+            return vec![
+                name.to_string(),
+                String::new(),
+                format!("{}", self.registers.f),
+            ];
+        };
+        // 24-bit two's complement
+        let signed = ((value << 8) as i32) >> 8;
+        let ascii: String = value
+            .to_be_bytes()
+            .iter()
+            .skip(1)
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        vec![
+            name.to_string(),
+            format!("{:06X}", value),
+            signed.to_string(),
+            ascii,
+        ]
+    }
+
+    fn row_style(&self, name: &str, editing: bool, theme: &Theme) -> Style {
+        if editing {
+            theme.highlight(theme.accent).add_modifier(Modifier::BOLD)
+        } else if self.changed.contains(&name) {
+            theme.changed().add_modifier(Modifier::BOLD)
+        } else if name == "PC" {
+            Style::default().fg(theme.pc).add_modifier(Modifier::BOLD)
+        } else {
+            theme.text()
+        }
+    }
 
-        f.render_widget(register_table, area);
+    // SW bits from the left: MODE 0, IDLE 1, ID 2-5, CC 6-7, MASK 8-11, ICODE 16-23
+    fn status_word(&self, theme: &Theme) -> Vec<Line<'static>> {
+        let sw = self.registers.sw;
+        let cc = match (sw >> 16) & 0b11 {
+            0b00 => "<",
+            0b01 => "=",
+            0b10 => ">",
+            _ => "?",
+        };
+        let field = |label: &'static str, value: String| {
+            vec![
+                Span::styled(label, theme.accent()),
+                Span::styled(value, theme.text()),
+            ]
+        };
+        let mut first = field("CC ", format!("{}  ", cc));
+        first.extend(field(
+            "MODE ",
+            format!("{}  ", if sw & 0x800000 != 0 { "sup" } else { "user" }),
+        ));
+        first.extend(field(
+            "IDLE ",
+            (if sw & 0x400000 != 0 { "idle" } else { "run" }).to_string(),
+        ));
+        let mut second = field("MASK ", format!("{:X}  ", (sw >> 12) & 0xF));
+        second.extend(field("KEY ", format!("{:X}  ", (sw >> 18) & 0xF)));
+        second.extend(field("ICODE ", format!("{:02X}", sw & 0xFF)));
+        vec![Line::from(first), Line::from(second)]
     }
 }
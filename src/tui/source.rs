@@ -0,0 +1,177 @@
+use super::theme::Theme;
+use crate::predefined::common::Origin;
+use ratatui::{
+    Frame,
+    layout::Rect,
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+};
+use std::cell::Cell;
+use std::path::Path;
+
+// a file the program was assembled from, `file` as in the origins of its lines
+pub struct SourceFile {
+    pub file: Option<String>,
+    pub name: String,
+    pub lines: Vec<String>,
+}
+
+// The .asm the program was assembled from, as written, following the PC
+pub struct SourceWidget {
+    pub files: Vec<SourceFile>,
+    pub current: Option<Origin>, // the line the PC is on
+    pub breakpoints: Vec<Origin>,
+    pub cursor: Option<Origin>, // the line picked with j/k or a click
+    // where the last render put the text and which line is at its top, for clicks
+    area: Cell<Rect>,
+    top: Cell<usize>,
+}
+
+const TAB_WIDTH: usize = 8;
+
+impl Default for SourceWidget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SourceWidget {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            current: None,
+            breakpoints: Vec::new(),
+            cursor: None,
+            area: Cell::new(Rect::default()),
+            top: Cell::new(0),
+        }
+    }
+
+    // the file of the picked line, else of the PC's line, else the main source
+    fn shown(&self) -> Option<&SourceFile> {
+        let file = self
+            .cursor
+            .as_ref()
+            .or(self.current.as_ref())
+            .map(|origin| &origin.file);
+        self.files
+            .iter()
+            .find(|f| Some(&f.file) == file)
+            .or(self.files.first())
+    }
+
+    pub fn move_cursor(&mut self, delta: isize) {
+        let Some(shown) = self.shown() else {
+            return;
+        };
+        let file = shown.file.clone();
+        let count = shown.lines.len().max(1);
+        let line = match (&self.cursor, &self.current) {
+            (Some(cursor), _) => cursor.line.saturating_add_signed(delta),
+            (None, Some(current)) if current.file == file => current.line,
+            _ => 1,
+        };
+        self.cursor = Some(Origin {
+            file,
+            line: line.clamp(1, count),
+        });
+    }
+
+    // picks the line under a click, None when the click is elsewhere
+    pub fn click(&mut self, column: u16, row: u16) -> Option<Origin> {
+        let area = self.area.get();
+        if column < area.x
+            || column >= area.x + area.width
+            || row < area.y
+            || row >= area.y + area.height
+        {
+            return None;
+        }
+        let shown = self.shown()?;
+        let line = self.top.get() + (row - area.y) as usize + 1;
+        if line > shown.lines.len() {
+            return None;
+        }
+        let origin = Origin {
+            file: shown.file.clone(),
+            line,
+        };
+        self.cursor = Some(origin.clone());
+        Some(origin)
+    }
+
+    pub fn render(&self, f: &mut Frame, area: Rect, theme: &Theme) {
+        let Some(shown) = self.shown() else {
+            return;
+        };
+        let name = Path::new(&shown.name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&shown.name);
+        let block = Block::default()
+            .title(format!("Source: {} (j k pick a line, Space break)", name))
+            .borders(Borders::ALL)
+            .border_style(theme.border());
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+        self.area.set(inner);
+
+        // keep the picked line, or the PC's, in the middle
+        let height = inner.height as usize;
+        let focus = self
+            .cursor
+            .as_ref()
+            .or(self.current.as_ref())
+            .filter(|origin| origin.file == shown.file)
+            .map_or(1, |origin| origin.line);
+        let top = focus
+            .saturating_sub(height / 2 + 1)
+            .min(shown.lines.len().saturating_sub(height));
+        self.top.set(top);
+
+        let at = |origins: Option<&Origin>, line: usize| {
+            origins.is_some_and(|o| o.file == shown.file && o.line == line)
+        };
+        let lines: Vec<Line> = shown
+            .lines
+            .iter()
+            .enumerate()
+            .skip(top)
+            .take(height)
+            .map(|(index, text)| {
+                let line = index + 1;
+                let current = at(self.current.as_ref(), line);
+                let breakpoint = self.breakpoints.iter().any(|b| at(Some(b), line));
+                let mut style = if current {
+                    theme.bold_accent()
+                } else {
+                    theme.text()
+                };
+                if at(self.cursor.as_ref(), line) {
+                    style = style.bg(theme.selection);
+                }
+                Line::from(vec![
+                    Span::raw(if current { ">" } else { " " }),
+                    Span::styled(if breakpoint { "●" } else { " " }, theme.changed()),
+                    Span::styled(format!("{:>4} ", line), theme.dim()),
+                    Span::styled(expand_tabs(text), style),
+                ])
+            })
+            .collect();
+        f.render_widget(Paragraph::new(lines), inner);
+    }
+}
+
+// tabs to the columns they stand for, so the layout is the one in the editor
+fn expand_tabs(text: &str) -> String {
+    let mut expanded = String::new();
+    for c in text.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - expanded.chars().count() % TAB_WIDTH;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
@@ -1,16 +1,20 @@
+use super::theme::Theme;
+use crate::disassembler::disassembler::split_record;
 use crate::predefined::common::{LiteralTable, ObjectRecord, SymbolTable};
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
     widgets::{Block, Borders, List, ListItem, Row, Table, Tabs},
 };
+use std::collections::BTreeSet;
 
 pub struct TabsWidget {
     pub selected_tab: usize,
     pub object_program: Vec<ObjectRecord>,
+    pub boundaries: BTreeSet<u32>, // where the source put instructions and constants
     pub symbol_table: Vec<SymbolTable>,
     pub literal_table: Vec<LiteralTable>,
+    pub scroll: usize, // rows of the selected table scrolled past
 }
 
 impl Default for TabsWidget {
@@ -24,13 +28,16 @@ impl TabsWidget {
         Self {
             selected_tab: 0,
             object_program: Vec::new(),
+            boundaries: BTreeSet::new(),
             symbol_table: Vec::new(),
             literal_table: Vec::new(),
+            scroll: 0,
         }
     }
 
     pub fn next_tab(&mut self) {
         self.selected_tab = (self.selected_tab + 1) % 3;
+        self.scroll = 0;
     }
 
     pub fn previous_tab(&mut self) {
@@ -39,9 +46,22 @@ impl TabsWidget {
         } else {
             self.selected_tab - 1
         };
+        self.scroll = 0;
     }
 
-    pub fn render(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+    pub fn scroll(&mut self, lines: isize) {
+        let rows = match self.selected_tab {
+            0 => self.object_program.len(),
+            1 => self.symbol_table.len(),
+            _ => self.literal_table.len(),
+        };
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(lines)
+            .min(rows.saturating_sub(1));
+    }
+
+    pub fn render(&self, f: &mut Frame, area: ratatui::layout::Rect, theme: &Theme) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Length(3), Constraint::Min(0)])
@@ -54,30 +74,27 @@ impl TabsWidget {
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Tables")
-                    .border_style(Style::default().fg(Color::Cyan)),
+                    .border_style(theme.border()),
             )
             .select(self.selected_tab)
-            .style(Style::default().fg(Color::Rgb(200, 200, 200)))
-            .highlight_style(
-                Style::default()
-                    .fg(Color::Rgb(255, 200, 0))
-                    .add_modifier(Modifier::BOLD),
-            );
+            .style(theme.text())
+            .highlight_style(theme.bold_accent());
         f.render_widget(tabs, chunks[0]);
 
         // Render content based on selected tab
         match self.selected_tab {
-            0 => self.render_object_program(f, chunks[1]),
-            1 => self.render_symbol_table(f, chunks[1]),
-            2 => self.render_literal_table(f, chunks[1]),
+            0 => self.render_object_program(f, chunks[1], theme),
+            1 => self.render_symbol_table(f, chunks[1], theme),
+            2 => self.render_literal_table(f, chunks[1], theme),
             _ => {}
         }
     }
 
-    fn render_object_program(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+    fn render_object_program(&self, f: &mut Frame, area: ratatui::layout::Rect, theme: &Theme) {
         let items: Vec<ListItem> = self
             .object_program
             .iter()
+            .skip(self.scroll)
             .map(|record| {
                 let text = match record {
                     ObjectRecord::Header {
@@ -90,9 +107,13 @@ impl TabsWidget {
                     ObjectRecord::Text {
                         start,
                         length,
-                        objcodes,
+                        bytes,
                     } => {
-                        let codes = objcodes.join(" ");
+                        let codes: Vec<String> = split_record(*start, bytes, &self.boundaries)
+                            .into_iter()
+                            .map(hex::encode_upper)
+                            .collect();
+                        let codes = codes.join(" ");
                         format!("T  {:06X}  {:02X}  {}", start, length, codes)
                     }
                     ObjectRecord::End { start } => {
@@ -116,55 +137,58 @@ impl TabsWidget {
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Object Program Records")
-                    .border_style(Color::Cyan),
+                    .border_style(theme.border()),
             )
-            .style(Style::default().fg(Color::White));
+            .style(theme.text());
 
         f.render_widget(list, area);
     }
 
-    fn render_symbol_table(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+    fn render_symbol_table(&self, f: &mut Frame, area: ratatui::layout::Rect, theme: &Theme) {
         let mut rows: Vec<Row> = Vec::new();
 
         // Add header row
-        rows.push(
-            Row::new(vec!["Label", "Address"]).style(Style::default().fg(Color::Rgb(255, 200, 0))),
-        );
+        rows.push(Row::new(vec!["Label", "Address", "Type"]).style(theme.accent()));
 
-        for symbol in &self.symbol_table {
+        for symbol in self.symbol_table.iter().skip(self.scroll) {
             rows.push(
                 Row::new(vec![
                     symbol.label.clone(),
                     format!("{:06X}", symbol.address),
+                    symbol.symbol_type.to_string(),
                 ])
-                .style(Style::default().fg(Color::White)),
+                .style(theme.text()),
             );
         }
 
-        let widths = &[Constraint::Length(15), Constraint::Length(10)];
+        let widths = &[
+            Constraint::Length(15),
+            Constraint::Length(10),
+            Constraint::Length(6),
+        ];
 
         let table = Table::new(rows, widths)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Symbol Table")
-                    .border_style(Style::default().fg(Color::Cyan)),
+                    .border_style(theme.border()),
             )
-            .style(Style::default().fg(Color::White));
+            .style(theme.text());
 
         f.render_widget(table, area);
     }
 
-    fn render_literal_table(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+    fn render_literal_table(&self, f: &mut Frame, area: ratatui::layout::Rect, theme: &Theme) {
         let mut rows: Vec<Row> = Vec::new();
 
         // Add header row
         rows.push(
-            Row::new(vec!["Literal", "Value", "Length", "Address"])
-                .style(Style::default().fg(Color::Rgb(255, 200, 0))),
+            Row::new(vec!["Literal", "Value", "Length", "Address", "Used by"])
+                .style(theme.accent()),
         );
 
-        for literal in &self.literal_table {
+        for literal in self.literal_table.iter().skip(self.scroll) {
             rows.push(
                 Row::new(vec![
                     literal.literal.clone(),
@@ -175,8 +199,14 @@ impl TabsWidget {
                     } else {
                         "---".to_string()
                     },
+                    literal
+                        .references
+                        .iter()
+                        .map(|addr| format!("{:06X}", addr))
+                        .collect::<Vec<_>>()
+                        .join(" "),
                 ])
-                .style(Style::default().fg(Color::White)),
+                .style(theme.text()),
             );
         }
 
@@ -185,6 +215,7 @@ impl TabsWidget {
             Constraint::Length(20),
             Constraint::Length(8),
             Constraint::Length(10),
+            Constraint::Min(10),
         ];
 
         let table = Table::new(rows, widths)
@@ -192,9 +223,9 @@ impl TabsWidget {
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Literal Table")
-                    .border_style(Style::default().fg(Color::Cyan)),
+                    .border_style(theme.border()),
             )
-            .style(Style::default().fg(Color::White));
+            .style(theme.text());
 
         f.render_widget(table, area);
     }
@@ -0,0 +1,167 @@
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+// The colors of the debugger by what they mean rather than where they are used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub background: Color,
+    pub text: Color,
+    pub dim: Color,          // line numbers, hints, the text column of memory
+    pub border: Color,       // of panes that are not focused
+    pub accent: Color,       // titles, labels, the current line, the focused pane
+    pub on_highlight: Color, // text on the accent, PC, EA and search backgrounds
+    pub selection: Color,    // background of the picked line and the buttons
+    pub changed: Color,      // written by the last step, breakpoints and errors
+    pub changed_since_stop: Color,
+    pub pc: Color,      // background of the PC in memory, and saved return addresses
+    pub address: Color, // background of the effective address in memory
+    pub found: Color,   // background of a search match in memory
+}
+
+pub const THEME_NAMES: [&str; 3] = ["dark", "light", "high-contrast"];
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            background: Color::Rgb(25, 25, 35),
+            text: Color::Rgb(200, 200, 200),
+            dim: Color::Rgb(100, 100, 120),
+            border: Color::Cyan,
+            accent: Color::Rgb(255, 200, 0),
+            on_highlight: Color::Black,
+            selection: Color::Rgb(50, 50, 60),
+            changed: Color::Rgb(255, 90, 90),
+            changed_since_stop: Color::Rgb(170, 100, 90),
+            pc: Color::Rgb(100, 255, 100),
+            address: Color::Rgb(120, 170, 255),
+            found: Color::Rgb(255, 150, 255),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            background: Color::Rgb(250, 250, 245),
+            text: Color::Rgb(30, 30, 40),
+            dim: Color::Rgb(120, 120, 130),
+            border: Color::Rgb(0, 90, 170),
+            accent: Color::Rgb(170, 90, 0),
+            on_highlight: Color::Rgb(255, 255, 255),
+            selection: Color::Rgb(220, 220, 230),
+            changed: Color::Rgb(200, 30, 30),
+            changed_since_stop: Color::Rgb(160, 100, 90),
+            pc: Color::Rgb(30, 140, 50),
+            address: Color::Rgb(40, 100, 210),
+            found: Color::Rgb(170, 60, 170),
+        }
+    }
+
+    // the Okabe-Ito colors on black, told apart with any kind of color blindness
+    pub fn high_contrast() -> Self {
+        Self {
+            background: Color::Black,
+            text: Color::White,
+            dim: Color::Rgb(170, 170, 170),
+            border: Color::Rgb(86, 180, 233),
+            accent: Color::Rgb(240, 228, 66),
+            on_highlight: Color::Black,
+            selection: Color::Rgb(70, 70, 70),
+            changed: Color::Rgb(213, 94, 0),
+            changed_since_stop: Color::Rgb(230, 159, 0),
+            pc: Color::Rgb(0, 158, 115),
+            address: Color::Rgb(86, 180, 233),
+            found: Color::Rgb(204, 121, 167),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self) -> Style {
+        Style::default().fg(self.text)
+    }
+
+    pub fn dim(&self) -> Style {
+        Style::default().fg(self.dim)
+    }
+
+    pub fn accent(&self) -> Style {
+        Style::default().fg(self.accent)
+    }
+
+    pub fn border(&self) -> Style {
+        Style::default().fg(self.border)
+    }
+
+    pub fn changed(&self) -> Style {
+        Style::default().fg(self.changed)
+    }
+
+    pub fn bold_accent(&self) -> Style {
+        self.accent().add_modifier(Modifier::BOLD)
+    }
+
+    pub fn highlight(&self, background: Color) -> Style {
+        Style::default().fg(self.on_highlight).bg(background)
+    }
+
+    // applies the colors a [themes.NAME] table sets over this theme
+    pub fn apply(&mut self, colors: &ThemeColors) -> Result<(), String> {
+        let fields = [
+            (&colors.background, &mut self.background),
+            (&colors.text, &mut self.text),
+            (&colors.dim, &mut self.dim),
+            (&colors.border, &mut self.border),
+            (&colors.accent, &mut self.accent),
+            (&colors.on_highlight, &mut self.on_highlight),
+            (&colors.selection, &mut self.selection),
+            (&colors.changed, &mut self.changed),
+            (&colors.changed_since_stop, &mut self.changed_since_stop),
+            (&colors.pc, &mut self.pc),
+            (&colors.address, &mut self.address),
+            (&colors.found, &mut self.found),
+        ];
+        for (value, color) in fields {
+            if let Some(value) = value {
+                *color = parse_color(value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// a theme in config.toml: the one it starts from and the colors it changes
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeColors {
+    pub base: Option<String>,
+    pub background: Option<String>,
+    pub text: Option<String>,
+    pub dim: Option<String>,
+    pub border: Option<String>,
+    pub accent: Option<String>,
+    pub on_highlight: Option<String>,
+    pub selection: Option<String>,
+    pub changed: Option<String>,
+    pub changed_since_stop: Option<String>,
+    pub pc: Option<String>,
+    pub address: Option<String>,
+    pub found: Option<String>,
+}
+
+// "#rrggbb" or a terminal color name such as "cyan" or "lightblue"
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    text.parse::<Color>()
+        .map_err(|_| format!("Invalid color '{}', use #rrggbb or a name like cyan", text))
+}
@@ -1,24 +1,139 @@
+pub use super::callstack;
+pub use super::config;
 pub use super::disassembly;
+pub use super::keys;
+pub use super::layout;
 pub use super::memory;
+pub use super::prompt;
 pub use super::registers;
+pub use super::source;
 pub use super::tabs;
-
-use crate::predefined::common::{ObjectRecord, SymbolTable};
+pub use super::theme;
+
+use crate::predefined::common::{ObjectRecord, Origin, SymbolTable};
+use crate::simulator::inistialize_machine::{MEMORY_SIZE, Registers};
+use crate::simulator::sim::CallFrame;
+use config::Config;
+use crossterm::event::KeyEvent;
+use crossterm::event::{KeyCode, MouseButton, MouseEvent, MouseEventKind};
+use keys::{Action, KeyMap};
+use layout::{LayoutConfig, Pane};
 use ratatui::{
     Frame,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
+    layout::{Constraint, Direction, Layout, Position, Rect},
+    style::{Modifier, Style},
     text::{Line, Span},
     widgets::{Block, Borders, Paragraph},
 };
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use theme::Theme;
 
 pub struct Tui {
     registers: registers::RegistersWidget,
     disassembly: disassembly::DisassemblyWidget,
+    call_stack: callstack::CallStackWidget,
     memory: memory::MemoryWidget,
+    source: source::SourceWidget,
     tabs: tabs::TabsWidget,
+    prompt: prompt::PromptWidget,
     buttons: Vec<&'static str>,
     focused_button: usize,
+    // whether a run is going on, and what it is doing or why it stopped
+    running: bool,
+    run_status: String,
+    // how the panes are arranged, and the arrangement last loaded or saved
+    layout: LayoutConfig,
+    saved_layout: LayoutConfig,
+    focused: Pane,
+    zoomed: bool,
+    dragging: Option<Divider>,
+    // where the last draw put each pane and each divider, for the mouse
+    areas: RefCell<Vec<(Pane, Rect)>>,
+    splits: RefCell<Vec<Split>>,
+    theme: Theme,
+    keys: KeyMap,
+}
+
+// the lines between panes that can be dragged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Divider {
+    Columns, // left column and right column
+    Rows,    // source and tables above memory
+    Source,  // source and tables
+}
+
+// a divider, the area it splits and where, the first column or row after it
+#[derive(Debug, Clone, Copy)]
+pub struct Split {
+    divider: Divider,
+    parent: Rect,
+    at: u16,
+}
+
+impl Split {
+    fn new(divider: Divider, parent: Rect, percent: u16) -> Self {
+        // widened, a wide terminal times the percent overflows u16
+        let share = |length: u16| (length as u32 * percent as u32 / 100) as u16;
+        let at = match divider {
+            Divider::Rows => parent.y + share(parent.height),
+            _ => parent.x + share(parent.width),
+        };
+        Self {
+            divider,
+            parent,
+            at,
+        }
+    }
+
+    // the borders on both sides of the divider can be grabbed
+    fn hit(&self, column: u16, row: u16) -> bool {
+        if !self.parent.contains(Position::new(column, row)) {
+            return false;
+        }
+        let position = match self.divider {
+            Divider::Rows => row,
+            _ => column,
+        };
+        position + 1 == self.at || position == self.at
+    }
+
+    // the share of the parent up to a column or row
+    fn percent(&self, column: u16, row: u16) -> u16 {
+        let (position, start, length) = match self.divider {
+            Divider::Rows => (row, self.parent.y, self.parent.height),
+            _ => (column, self.parent.x, self.parent.width),
+        };
+        let offset = position.saturating_sub(start) as i32;
+        LayoutConfig::clamp(offset * 100 / (length as i32).max(1))
+    }
+}
+
+// what a click asks the simulator to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum MouseAction {
+    None,
+    Button(usize),
+    Breakpoint(u32),
+    SourceLine(Origin),
+}
+
+// the focused pane's border, titles keep their own colors
+fn highlight_border(f: &mut Frame, area: Rect, theme: &Theme) {
+    let buffer = f.buffer_mut();
+    let area = area.intersection(buffer.area);
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            let on_border = x == area.left()
+                || x + 1 == area.right()
+                || y == area.top()
+                || y + 1 == area.bottom();
+            let cell = &mut buffer[(x, y)];
+            if on_border && "─│┌┐└┘".contains(cell.symbol()) {
+                cell.set_fg(theme.accent);
+            }
+        }
+    }
 }
 
 impl Default for Tui {
@@ -32,98 +147,206 @@ impl Tui {
         Self {
             registers: registers::RegistersWidget::new(),
             disassembly: disassembly::DisassemblyWidget::new(),
-            memory: memory::MemoryWidget::new(65536), // 64K memory
+            call_stack: callstack::CallStackWidget::new(),
+            memory: memory::MemoryWidget::new(MEMORY_SIZE),
+            source: source::SourceWidget::new(),
             tabs: tabs::TabsWidget::new(),
+            prompt: prompt::PromptWidget::new(),
             buttons: vec!["Step", "Run", "Reset"],
             focused_button: 0,
+            running: false,
+            run_status: String::new(),
+            layout: LayoutConfig::default(),
+            saved_layout: LayoutConfig::default(),
+            focused: Pane::Memory,
+            zoomed: false,
+            dragging: None,
+            areas: RefCell::new(Vec::new()),
+            splits: RefCell::new(Vec::new()),
+            theme: Theme::default(),
+            keys: KeyMap::default(),
         }
     }
 
     pub fn draw(&self, f: &mut Frame) {
+        let theme = &self.theme;
         // Set background color
         f.render_widget(
-            Block::default().style(Style::default().bg(Color::Rgb(25, 25, 35))),
+            Block::default().style(Style::default().bg(theme.background)),
             f.area(),
         );
 
         let main_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Min(0), Constraint::Length(3)])
-            .split(f.area());
-
-        let content_layout = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
-            .split(main_layout[0]);
-
-        let left_layout = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
-                Constraint::Length(10),
-                Constraint::Length(3),
                 Constraint::Min(0),
+                Constraint::Length(3 + self.prompt.output_height()),
             ])
-            .split(content_layout[0]);
-
-        let right_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(content_layout[1]);
+            .split(f.area());
 
-        // Render components
-        self.registers.render(f, left_layout[0]);
-        self.render_controls(f, left_layout[1]);
-        self.disassembly.render(f, left_layout[2]);
-        self.tabs.render(f, right_layout[0]);
-        self.memory.render(f, right_layout[1]);
+        let mut splits = Vec::new();
+        let panes = if self.zoomed && self.is_shown(self.focused) {
+            vec![(self.focused, main_layout[0])]
+        } else {
+            self.split(main_layout[0], &mut splits)
+        };
+        for (pane, area) in panes.iter() {
+            self.render_pane(f, *pane, *area);
+            if *pane == self.focused {
+                highlight_border(f, *area, &self.theme);
+            }
+        }
+        self.areas.replace(panes);
+        self.splits.replace(splits);
 
         // Render status bar at bottom
         self.render_status_bar(f, main_layout[1]);
     }
 
-    fn render_status_bar(&self, f: &mut Frame, area: ratatui::layout::Rect) {
-        let shortcuts = [
-            ("q", "quit"),
-            ("s", "step"),
-            ("r", "run"),
-            ("b", "break"),
-            ("Tab", "switch tabs"),
-            ("↑↓", "scroll"),
-        ];
+    // the source pane is only there when the program was assembled from one
+    fn is_shown(&self, pane: Pane) -> bool {
+        self.layout.is_visible(pane) && (pane != Pane::Source || !self.source.files.is_empty())
+    }
 
-        let mut spans = Vec::new();
+    // registers, controls, disassembly and call stack on the left, source and tables
+    // above memory on the right, leaving out the hidden ones
+    fn split(&self, area: Rect, splits: &mut Vec<Split>) -> Vec<(Pane, Rect)> {
+        let shown = |panes: &[Pane]| -> Vec<Pane> {
+            panes
+                .iter()
+                .copied()
+                .filter(|p| self.is_shown(*p))
+                .collect()
+        };
+        let left = shown(&[
+            Pane::Registers,
+            Pane::Controls,
+            Pane::Disassembly,
+            Pane::CallStack,
+        ]);
+        let top = shown(&[Pane::Source, Pane::Tables]);
+        let memory = self.is_shown(Pane::Memory);
+
+        let columns = if top.is_empty() && !memory {
+            vec![area]
+        } else {
+            let width = self.layout.left_width;
+            splits.push(Split::new(Divider::Columns, area, width));
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Percentage(width),
+                    Constraint::Percentage(100 - width),
+                ])
+                .split(area)
+                .to_vec()
+        };
+
+        let constraints: Vec<Constraint> = left
+            .iter()
+            .map(|pane| match pane {
+                Pane::Registers => Constraint::Length(15),
+                Pane::Controls => Constraint::Length(3),
+                Pane::CallStack if left.contains(&Pane::Disassembly) => Constraint::Length(6),
+                _ => Constraint::Min(0),
+            })
+            .collect();
+        let left_areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(constraints)
+            .split(columns[0]);
+        let mut panes: Vec<(Pane, Rect)> =
+            left.into_iter().zip(left_areas.iter().copied()).collect();
+
+        if let Some(right) = columns.get(1).copied() {
+            let (top_area, memory_area) = match (top.is_empty(), memory) {
+                (false, true) => {
+                    let height = self.layout.top_height;
+                    splits.push(Split::new(Divider::Rows, right, height));
+                    let rows = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([
+                            Constraint::Percentage(height),
+                            Constraint::Percentage(100 - height),
+                        ])
+                        .split(right);
+                    (Some(rows[0]), Some(rows[1]))
+                }
+                (false, false) => (Some(right), None),
+                (true, _) => (None, Some(right)),
+            };
+            if let Some(top_area) = top_area {
+                if top.len() == 2 {
+                    let width = self.layout.source_width;
+                    splits.push(Split::new(Divider::Source, top_area, width));
+                    let halves = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .constraints([
+                            Constraint::Percentage(width),
+                            Constraint::Percentage(100 - width),
+                        ])
+                        .split(top_area);
+                    panes.push((Pane::Source, halves[0]));
+                    panes.push((Pane::Tables, halves[1]));
+                } else {
+                    panes.push((top[0], top_area));
+                }
+            }
+            if let Some(memory_area) = memory_area {
+                panes.push((Pane::Memory, memory_area));
+            }
+        }
+        panes
+    }
+
+    fn render_pane(&self, f: &mut Frame, pane: Pane, area: Rect) {
+        let theme = &self.theme;
+        match pane {
+            Pane::Registers => self.registers.render(f, area, theme),
+            Pane::Controls => self.render_controls(f, area),
+            Pane::Disassembly => self.disassembly.render(f, area, theme),
+            Pane::CallStack => self
+                .call_stack
+                .render(f, area, &self.tabs.symbol_table, theme),
+            Pane::Source => self.source.render(f, area, theme),
+            Pane::Tables => self.tabs.render(f, area, theme),
+            Pane::Memory => self.memory.render(f, area, theme),
+        }
+    }
 
-        for (i, (key, desc)) in shortcuts.iter().enumerate() {
+    fn render_status_bar(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.theme;
+        // the shortcuts as they are bound, so the bar never lies about a remapped key
+        let mut spans = Vec::new();
+        for (i, (keys, desc)) in self.keys.status_bar().into_iter().enumerate() {
             if i > 0 {
                 spans.push(Span::raw(", "));
             }
-            spans.push(Span::styled(
-                *key,
-                Style::default()
-                    .fg(Color::Rgb(255, 200, 0))
-                    .add_modifier(Modifier::BOLD),
-            ));
+            spans.push(Span::styled(keys, theme.bold_accent()));
             spans.push(Span::raw(" "));
-            spans.push(Span::styled(
-                *desc,
-                Style::default().fg(Color::Rgb(200, 200, 200)),
-            ));
+            spans.push(Span::styled(desc, theme.text()));
         }
 
-        let status_text = Line::from(spans);
+        // the command output above the prompt, or above the shortcuts when it is closed
+        let mut status_text = self.prompt.lines(theme);
+        if !self.prompt.is_open() {
+            status_text.push(Line::from(spans));
+        }
 
         let paragraph = Paragraph::new(status_text)
             .block(
                 Block::default()
+                    .title(self.run_status.as_str())
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Cyan)),
+                    .border_style(theme.border()),
             )
-            .style(Style::default().bg(Color::Rgb(25, 25, 35)));
+            .style(Style::default().bg(theme.background));
 
         f.render_widget(paragraph, area);
     }
 
     fn render_controls(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let theme = &self.theme;
         let button_layout = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([
@@ -134,22 +357,22 @@ impl Tui {
             .split(area);
 
         for (i, &button_text) in self.buttons.iter().enumerate() {
+            let button_text = if i == 1 && self.running {
+                "Pause"
+            } else {
+                button_text
+            };
             let style = if i == self.focused_button {
-                Style::default()
-                    .fg(Color::Black)
-                    .bg(Color::Rgb(255, 200, 0))
-                    .add_modifier(Modifier::BOLD)
+                theme.highlight(theme.accent).add_modifier(Modifier::BOLD)
             } else {
-                Style::default()
-                    .fg(Color::Rgb(200, 200, 200))
-                    .bg(Color::Rgb(50, 50, 60))
+                theme.text().bg(theme.selection)
             };
 
             let button = Paragraph::new(Span::styled(button_text, style))
                 .block(
                     Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Cyan)),
+                        .border_style(theme.border()),
                 )
                 .alignment(ratatui::layout::Alignment::Center);
 
@@ -158,38 +381,115 @@ impl Tui {
     }
 
     // Add methods to update component states
-    pub fn update_registers(&mut self, a: u32, x: u32, l: u32, pc: u32, sw: u32) {
-        self.registers.a = a;
-        self.registers.x = x;
-        self.registers.l = l;
-        self.registers.pc = pc;
-        self.registers.sw = sw;
+    pub fn update_registers(&mut self, registers: Registers, changed: &[&'static str]) {
+        self.registers.registers = registers;
+        self.registers.changed.clear();
+        self.registers.changed.extend_from_slice(changed);
+    }
+
+    pub fn editing_registers(&self) -> bool {
+        self.registers.is_editing()
+    }
+
+    pub fn edit_registers(&mut self) {
+        self.registers.start_editing();
     }
 
-    pub fn update_memory(&mut self, start_address: usize, _data: &[u8]) {
-        // Update max address and auto-focus on first load
-        self.memory.update_max_address();
-        self.memory.start_address = start_address;
+    // keys go to the registers pane while a register is being edited
+    pub fn register_key(&mut self, code: KeyCode) -> Option<registers::RegisterEdit> {
+        self.registers.handle_key(code)
     }
 
-    pub fn scroll_memory_up(&mut self) {
-        self.memory.scroll_up();
+    pub fn prompt_open(&self) -> bool {
+        self.prompt.is_open()
     }
 
-    pub fn scroll_memory_down(&mut self) {
-        self.memory.scroll_down();
+    pub fn open_prompt(&mut self, initial: &str) {
+        self.prompt.open(initial);
+    }
+
+    // keys go to the `:` prompt while it is open, Enter gives back the command
+    pub fn prompt_key(&mut self, code: KeyCode) -> Option<String> {
+        self.prompt.handle_key(code)
+    }
+
+    pub fn show_command_result(&mut self, result: Result<Vec<String>, String>) {
+        self.prompt.show(result);
+    }
+
+    pub fn update_call_stack(&mut self, frames: &[CallFrame]) {
+        self.call_stack.frames.clear();
+        self.call_stack.frames.extend_from_slice(frames);
+    }
+
+    pub fn update_run_state(&mut self, running: bool, status: String) {
+        self.running = running;
+        self.run_status = status;
+    }
+
+    pub fn update_source(&mut self, files: Vec<source::SourceFile>) {
+        self.source.files = files;
+        self.source.cursor = None;
+    }
+
+    pub fn update_source_position(&mut self, current: Option<Origin>, breakpoints: Vec<Origin>) {
+        self.source.current = current;
+        self.source.breakpoints = breakpoints;
+    }
+
+    pub fn move_source_cursor(&mut self, delta: isize) {
+        self.source.move_cursor(delta);
+    }
+
+    // the source and disassembly go back to showing where the PC is
+    pub fn follow_pc(&mut self) {
+        self.source.cursor = None;
+        self.disassembly.offset = None;
+    }
+
+    pub fn selected_source_line(&self) -> Option<Origin> {
+        self.source.cursor.clone()
+    }
+
+    pub fn update_memory(
+        &mut self,
+        memory: &[u8],
+        changed: &[u32],
+        changed_since_stop: &HashSet<u32>,
+        pc: u32,
+        last_address: Option<u32>,
+    ) {
+        self.memory.changed.clear();
+        self.memory.changed.extend_from_slice(changed);
+        self.memory.pc = pc;
+        self.memory.last_address = last_address;
+        self.memory.update(memory, changed_since_stop);
     }
 
     pub fn auto_focus_memory(&mut self) {
         self.memory.auto_focus();
     }
 
+    pub fn memory(&mut self) -> &mut memory::MemoryWidget {
+        &mut self.memory
+    }
+
+    pub fn update_breakpoints(&mut self, breakpoints: &[u32]) {
+        self.disassembly.breakpoints.clear();
+        self.disassembly.breakpoints.extend_from_slice(breakpoints);
+    }
+
     pub fn update_disassembly(&mut self, instructions: Vec<(u32, String, String)>) {
         self.disassembly.instructions = instructions;
     }
 
-    pub fn update_object_program(&mut self, object_program: Vec<ObjectRecord>) {
+    pub fn update_object_program(
+        &mut self,
+        object_program: Vec<ObjectRecord>,
+        boundaries: BTreeSet<u32>,
+    ) {
         self.tabs.object_program = object_program;
+        self.tabs.boundaries = boundaries;
     }
 
     pub fn update_symbol_table(&mut self, symbol_table: Vec<SymbolTable>) {
@@ -226,4 +526,151 @@ impl Tui {
     pub fn get_focused_button(&self) -> usize {
         self.focused_button
     }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.theme = config.theme;
+        self.keys = config.keys;
+    }
+
+    // what a key is bound to
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.keys.action(key)
+    }
+
+    pub fn set_layout(&mut self, layout: LayoutConfig) {
+        self.saved_layout = layout.clone();
+        self.layout = layout;
+    }
+
+    // writes the layout back only when it was changed
+    pub fn save_layout(&mut self) {
+        if self.layout != self.saved_layout {
+            self.layout.save();
+            self.saved_layout = self.layout.clone();
+        }
+    }
+
+    // the focused pane fills the screen, or goes back to its place
+    pub fn toggle_zoom(&mut self) {
+        self.zoomed = !self.zoomed;
+    }
+
+    pub fn resize(&mut self, divider: Divider, delta: i32) {
+        let percent = match divider {
+            Divider::Columns => &mut self.layout.left_width,
+            Divider::Rows => &mut self.layout.top_height,
+            Divider::Source => &mut self.layout.source_width,
+        };
+        *percent = LayoutConfig::clamp(*percent as i32 + delta);
+    }
+
+    pub fn set_pane_visible(&mut self, name: &str, visible: bool) -> Result<Vec<String>, String> {
+        let pane = Pane::from_name(name).ok_or_else(|| {
+            let names: Vec<&str> = Pane::ALL.iter().map(|p| p.name()).collect();
+            format!("No pane '{}', there are {}", name, names.join(", "))
+        })?;
+        self.layout.set_visible(pane, visible)?;
+        if !visible && self.focused == pane {
+            self.focused = Pane::Controls;
+        }
+        let state = if visible { "Showing" } else { "Hiding" };
+        Ok(vec![format!("{} {}", state, pane.name())])
+    }
+
+    fn pane_at(&self, column: u16, row: u16) -> Option<(Pane, Rect)> {
+        self.areas
+            .borrow()
+            .iter()
+            .find(|(_, area)| area.contains(Position::new(column, row)))
+            .copied()
+    }
+
+    // a click focuses the pane under it, dragging a divider resizes the panes
+    // next to it and the wheel scrolls whichever pane it is over
+    pub fn mouse(&mut self, event: MouseEvent) -> MouseAction {
+        let (column, row) = (event.column, event.row);
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let split = self
+                    .splits
+                    .borrow()
+                    .iter()
+                    .find(|split| split.hit(column, row))
+                    .copied();
+                if let Some(split) = split {
+                    self.dragging = Some(split.divider);
+                    return MouseAction::None;
+                }
+                let Some((pane, area)) = self.pane_at(column, row) else {
+                    return MouseAction::None;
+                };
+                self.focused = pane;
+                match pane {
+                    Pane::Controls => {
+                        let offset = (column - area.x) as usize;
+                        let button = (offset * self.buttons.len() / area.width.max(1) as usize)
+                            .min(self.buttons.len() - 1);
+                        self.focused_button = button;
+                        MouseAction::Button(button)
+                    }
+                    Pane::Disassembly => self
+                        .disassembly
+                        .click(column, row)
+                        .map_or(MouseAction::None, MouseAction::Breakpoint),
+                    Pane::Source => self
+                        .source
+                        .click(column, row)
+                        .map_or(MouseAction::None, MouseAction::SourceLine),
+                    Pane::Memory => {
+                        self.memory.click(column, row);
+                        MouseAction::None
+                    }
+                    _ => MouseAction::None,
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let split = self
+                    .splits
+                    .borrow()
+                    .iter()
+                    .find(|split| Some(split.divider) == self.dragging)
+                    .copied();
+                if let Some(split) = split {
+                    let percent = split.percent(column, row);
+                    let current = match split.divider {
+                        Divider::Columns => self.layout.left_width,
+                        Divider::Rows => self.layout.top_height,
+                        Divider::Source => self.layout.source_width,
+                    };
+                    self.resize(split.divider, percent as i32 - current as i32);
+                }
+                MouseAction::None
+            }
+            MouseEventKind::Up(MouseButton::Left) => {
+                self.dragging = None;
+                MouseAction::None
+            }
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let lines = if event.kind == MouseEventKind::ScrollUp {
+                    -3
+                } else {
+                    3
+                };
+                match self.pane_at(column, row).map(|(pane, _)| pane) {
+                    Some(Pane::Disassembly) => self.disassembly.scroll(lines),
+                    Some(Pane::CallStack) => self.call_stack.scroll(lines),
+                    Some(Pane::Source) => self.source.move_cursor(lines),
+                    Some(Pane::Tables) => self.tabs.scroll(lines),
+                    Some(Pane::Memory) => {
+                        self.memory.click(column, row);
+                        self.memory.scroll(lines as i64);
+                    }
+                    // registers and controls always fit
+                    _ => {}
+                }
+                MouseAction::None
+            }
+            _ => MouseAction::None,
+        }
+    }
 }
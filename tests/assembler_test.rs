@@ -0,0 +1,945 @@
+use hexe::assembler::constant::{parse_bytes, parse_number};
+use hexe::assembler::cst::{self, CstKind};
+use hexe::assembler::expression::evaluate;
+use hexe::assembler::format::format_source;
+use hexe::assembler::lexer::{LexError, lex, tokenize};
+use hexe::assembler::lint::lint;
+use hexe::assembler::parser::parser;
+use hexe::assembler::pass2asm::pass2asm;
+use hexe::predefined::common::{
+    ASSEMBLER_LOCK, AssemblerOptions, Command, DIAGNOSTICS, Diagnostic, LITERALTABLE, LiteralTable,
+    ObjectRecord, SYMBOLTABLE, Severity, Span, SymbolTable, SymbolType,
+};
+
+fn assemble(source: &str) -> Vec<ObjectRecord> {
+    let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    pass2asm(source, &AssemblerOptions::default())
+}
+
+// assembles and also returns the symbol table and diagnostics it left behind
+fn assemble_with_tables(source: &str) -> (Vec<ObjectRecord>, Vec<SymbolTable>, Vec<Diagnostic>) {
+    let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let records = pass2asm(source, &AssemblerOptions::default());
+    let symbols = SYMBOLTABLE.lock().unwrap().clone();
+    let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+    (records, symbols, diagnostics)
+}
+
+fn symbol_address(symbols: &[SymbolTable], label: &str) -> Option<u32> {
+    symbols.iter().find(|s| s.label == label).map(|s| s.address)
+}
+
+fn text_bytes(records: &[ObjectRecord]) -> Vec<(u32, String)> {
+    records
+        .iter()
+        .filter_map(|r| match r {
+            ObjectRecord::Text { start, bytes, .. } => Some((*start, hex::encode_upper(bytes))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn modification_addresses(records: &[ObjectRecord]) -> Vec<u32> {
+    records
+        .iter()
+        .filter_map(|r| match r {
+            ObjectRecord::Modification { address, .. } => Some(*address),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod assembler_tests {
+    use super::*;
+
+    #[test]
+    fn test_expression_operands() {
+        let source = "\
+PROG    START   0
+FIRST   LDA     BUFFER+3
+        LDX     #BUFFER-LENGTH
+LENGTH  WORD    LENGTH-1
+BUFFER  RESB    2*3
+        END     FIRST";
+        let result = assemble(source);
+        let text = text_bytes(&result);
+
+        // LDA BUFFER+3: PC-relative, target 0009+3 = 000C, PC = 0003 -> disp 009
+        // LDX #BUFFER-LENGTH: absolute 3, immediate value kept in the 12-bit field
+        // LENGTH WORD LENGTH-1: 0006 - 1 = 0005
+        assert_eq!(text[0], (0x0000, "032009050003000005".to_string()));
+
+        // only the relative WORD needs relocating
+        assert_eq!(modification_addresses(&result), vec![0x0006]);
+        assert!(result.iter().any(|r| matches!(
+            r,
+            ObjectRecord::Modification { length: 6, variable, .. } if variable == "PROG"
+        )));
+    }
+
+    #[test]
+    fn test_location_counter_and_constants() {
+        let source = "\
+PROG    START   0
+        LDA     #C'A'
+HERE    J       *
+        WORD    X'10'+1
+        END     HERE";
+        let result = assemble(source);
+        let text = text_bytes(&result);
+
+        assert_eq!(text[0].1, "0100413F2FFD000011");
+        assert!(matches!(
+            result.last(),
+            Some(ObjectRecord::End { start: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_format4_modification_only_for_relocatable_operands() {
+        let source = "\
+PROG    START   0
+        +LDT    #4096
+        +JSUB   ROUTIN
+ROUTIN  RSUB
+        END     PROG";
+        let result = assemble(source);
+        let text = text_bytes(&result);
+
+        assert_eq!(text[0].1, "75101000 4B100008 4F0000".replace(' ', ""));
+        assert_eq!(
+            modification_addresses(&result),
+            vec![0x0005],
+            "only +JSUB ROUTIN should be relocated"
+        );
+    }
+
+    #[test]
+    fn test_absolute_equ_is_not_relocated() {
+        let source = "\
+PROG    START   0
+        +LDT    #MAXLEN
+        +JSUB   RDREC
+BUFFER  RESB    16
+BUFEND  EQU     *
+MAXLEN  EQU     BUFEND-BUFFER
+        EXTREF  RDREC
+        END     PROG";
+        let result = assemble(source);
+        let text = text_bytes(&result);
+
+        assert_eq!(text[0].1, "751000104B100000");
+        let modifications: Vec<_> = result
+            .iter()
+            .filter_map(|r| match r {
+                ObjectRecord::Modification {
+                    address, variable, ..
+                } => Some((*address, variable.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            modifications,
+            vec![(0x0005, "RDREC".to_string())],
+            "absolute MAXLEN needs no M record, external RDREC does"
+        );
+    }
+
+    #[test]
+    fn test_expression_types() {
+        let symbols = vec![
+            SymbolTable {
+                label: "FIRST".to_string(),
+                address: 0x1000,
+                symbol_type: SymbolType::Relative,
+            },
+            SymbolTable {
+                label: "LAST".to_string(),
+                address: 0x1030,
+                symbol_type: SymbolType::Relative,
+            },
+            SymbolTable {
+                label: "SIZE".to_string(),
+                address: 0x10,
+                symbol_type: SymbolType::Absolute,
+            },
+            SymbolTable {
+                label: "OTHER".to_string(),
+                address: 0,
+                symbol_type: SymbolType::External,
+            },
+        ];
+
+        let diff = evaluate("LAST-FIRST", &symbols, 0).unwrap();
+        assert_eq!((diff.value, diff.symbol_type), (0x30, SymbolType::Absolute));
+
+        let offset = evaluate("FIRST+SIZE*2", &symbols, 0).unwrap();
+        assert_eq!(
+            (offset.value, offset.symbol_type),
+            (0x1020, SymbolType::Relative)
+        );
+
+        let external = evaluate("OTHER+4", &symbols, 0).unwrap();
+        assert_eq!(external.symbol_type, SymbolType::External);
+        assert_eq!(external.external.as_deref(), Some("OTHER"));
+
+        assert!(evaluate("FIRST+LAST", &symbols, 0).is_err());
+        assert!(evaluate("SIZE-FIRST", &symbols, 0).is_err());
+        assert!(evaluate("FIRST*2", &symbols, 0).is_err());
+        assert!(evaluate("OTHER+FIRST", &symbols, 0).is_err());
+
+        let lowest = evaluate("(-2147483647-1)/1", &symbols, 0).unwrap();
+        assert_eq!(lowest.value, i32::MIN);
+        assert!(evaluate("(-2147483647-1)/-1", &symbols, 0).is_err());
+    }
+
+    #[test]
+    fn test_org_with_and_without_operand() {
+        let source = "\
+PROG    START   0
+TABLE   RESB    6
+        ORG     TABLE
+FIELD1  RESB    2
+FIELD2  RESB    4
+        ORG
+NEXT    WORD    5
+        END     PROG";
+        let (result, symbols, diagnostics) = assemble_with_tables(source);
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(symbol_address(&symbols, "FIELD1"), Some(0x0000));
+        assert_eq!(symbol_address(&symbols, "FIELD2"), Some(0x0002));
+        assert_eq!(symbol_address(&symbols, "NEXT"), Some(0x0006));
+        assert_eq!(text_bytes(&result), vec![(0x0006, "000005".to_string())]);
+        assert!(matches!(
+            result.first(),
+            Some(ObjectRecord::Header { length: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn test_forward_referenced_equ() {
+        let source = "\
+PROG    START   0
+        LDA     #SIZE
+SIZE    EQU     HALF*2
+HALF    EQU     BUFEND-BUFFER
+BUFFER  RESB    8
+BUFEND  EQU     *
+        END     PROG";
+        let (result, symbols, diagnostics) = assemble_with_tables(source);
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(symbol_address(&symbols, "HALF"), Some(8));
+        assert_eq!(symbol_address(&symbols, "SIZE"), Some(16));
+        assert_eq!(text_bytes(&result)[0].1, "010010");
+    }
+
+    #[test]
+    fn test_reservation_overflow_is_reported() {
+        let source = "\
+PROG    START   0
+BIG     RESW    2000000000
+NEXT    RESB    1
+        END     PROG";
+        let (_, symbols, diagnostics) = assemble_with_tables(source);
+
+        // the location counter stays put after a reservation that does not fit
+        assert_eq!(symbol_address(&symbols, "NEXT"), Some(0));
+        let messages: Vec<(usize, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![(
+                2,
+                "Reserving 2000000000 times 3 bytes overflows the address"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_operand_errors_are_reported() {
+        let source = "\
+PROG    START   0
+        WORD    NOSUCH
+        RESB    LATER
+        BASE    NOWHERE
+        CLEAR   Q
+        SVC     MISSING
+        RSUB
+LATER   EQU     4
+        END     ELSEWHERE";
+        let (result, _, diagnostics) = assemble_with_tables(source);
+
+        let messages: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(messages, vec![3, 2, 4, 5, 6, 9], "{:?}", diagnostics);
+        assert!(
+            diagnostics[0]
+                .message
+                .starts_with("Failed to evaluate reservation size")
+        );
+        assert!(
+            diagnostics[1]
+                .message
+                .starts_with("Invalid WORD operand 'NOSUCH'")
+        );
+        assert_eq!(diagnostics[3].message, "Unknown register 'Q'");
+        // the bad word keeps its three bytes
+        assert_eq!(
+            text_bytes(&result)[0],
+            (0, "000000B400B0004F0000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_circular_equ_is_reported() {
+        let source = "\
+PROG    START   0
+FIRST   EQU     SECOND+1
+SECOND  EQU     FIRST-1
+OTHER   EQU     MISSING
+        RSUB
+        END     PROG";
+        let (_, symbols, diagnostics) = assemble_with_tables(source);
+
+        assert_eq!(symbol_address(&symbols, "FIRST"), None);
+        let messages: Vec<(usize, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (2, "Circular EQU definition: FIRST -> SECOND -> FIRST"),
+                (3, "Circular EQU definition: SECOND -> FIRST -> SECOND"),
+                (4, "EQU 'OTHER' refers to undefined symbol 'MISSING'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unresolved_operand_is_reported() {
+        let source = "\
+PROG    START   1000
+FIRST   LDA     NOSUCH
+        +LDA    NOSUCH
+        J       FIRST
+        END     FIRST";
+        let (result, _, diagnostics) = assemble_with_tables(source);
+
+        let messages: Vec<(usize, Severity)> =
+            diagnostics.iter().map(|d| (d.line, d.severity)).collect();
+        assert_eq!(messages, vec![(2, Severity::Error), (3, Severity::Error)]);
+        // the instructions keep their place with a zero operand
+        assert_eq!(
+            text_bytes(&result),
+            vec![(0x1000, "030000031000003F2FF6".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_start_radix() {
+        let source = "\
+PROG    START   1000
+FIRST   RSUB
+        END     FIRST";
+        let hex = assemble(source);
+        assert!(matches!(
+            hex.first(),
+            Some(ObjectRecord::Header { start: 0x1000, .. })
+        ));
+
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let decimal = pass2asm(
+            source,
+            &AssemblerOptions {
+                start_radix: 10,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            decimal.first(),
+            Some(ObjectRecord::Header { start: 1000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_byte_and_word_constants() {
+        let source = "\
+PROG    START   0
+ONE     BYTE    X'F1'
+ODD     BYTE    X'ABC'
+TEXT    BYTE    C'A\\n'
+SMALL   BYTE    -1
+NEG     WORD    -5
+DIFF    WORD    -(NEG-ONE)
+        END     PROG";
+        let (result, symbols, diagnostics) = assemble_with_tables(source);
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(symbol_address(&symbols, "ODD"), Some(0x0001));
+        assert_eq!(symbol_address(&symbols, "TEXT"), Some(0x0003));
+        assert_eq!(symbol_address(&symbols, "NEG"), Some(0x0006));
+        assert_eq!(
+            text_bytes(&result)[0].1,
+            "F1 0ABC 410A FF FFFFFB FFFFFA".replace(' ', "")
+        );
+    }
+
+    #[test]
+    fn test_quote_escapes_in_operands() {
+        // a blank, comma or lower case after an escaped quote is still inside it
+        let source = "\
+PROG    start   0
+QUOTE   byte    C'it''s a, b'
+SLASH   byte    C'\\' x,y'
+WORDS   word    C'a''b'+1
+        end     PROG";
+        let options = AssemblerOptions {
+            fold_case: true,
+            ..AssemblerOptions::default()
+        };
+        let (result, diagnostics) = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let records = pass2asm(source, &options);
+            (records, DIAGNOSTICS.lock().unwrap().clone())
+        };
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(
+            text_bytes(&result)[0].1,
+            format!(
+                "{}{}612763",
+                hex::encode_upper("it's a, b"),
+                hex::encode_upper("' x,y")
+            )
+        );
+    }
+
+    #[test]
+    fn test_constant_parser() {
+        assert_eq!(parse_bytes("C'EOF'"), Ok(b"EOF".to_vec()));
+        assert_eq!(parse_bytes("c'It''s'"), Ok(b"It's".to_vec()));
+        assert_eq!(parse_bytes("C'\\x41\\t\\''"), Ok(vec![0x41, b'\t', b'\'']));
+        assert_eq!(parse_bytes("X'F'"), Ok(vec![0x0F]));
+        assert_eq!(parse_bytes("x'05f1'"), Ok(vec![0x05, 0xF1]));
+        assert_eq!(parse_bytes("255"), Ok(vec![0xFF]));
+        assert!(parse_bytes("256").is_err());
+        assert!(parse_bytes("X'G1'").is_err());
+        assert!(parse_bytes("C'\\q'").is_err());
+
+        assert_eq!(parse_number("1000", 16), Ok(0x1000));
+        assert_eq!(parse_number("1000", 10), Ok(1000));
+        assert_eq!(parse_number("X'2A'", 10), Ok(0x2A));
+        assert_eq!(parse_number("$FF", 10), Ok(0xFF));
+        assert!(parse_number("12AB", 10).is_err());
+    }
+
+    #[test]
+    fn test_literal_pool() {
+        let source = "\
+PROG    START   0
+        LDA     =C'A'
+        LDB     =X'41'
+        LDS     =W'5'
+        LDT     =5
+        LDX     =*
+        COMPR   A,=X'01'
+        LTORG
+        END     PROG";
+        let (result, literals, diagnostics) = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let records = pass2asm(source, &AssemblerOptions::default());
+            let literals: Vec<LiteralTable> = LITERALTABLE.lock().unwrap().clone();
+            let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+            (records, literals, diagnostics)
+        };
+
+        // =C'A' and =X'41' share an entry, so do =W'5' and =5
+        let pool: Vec<(&str, Option<u32>, Vec<u32>)> = literals
+            .iter()
+            .map(|l| (l.value.as_str(), l.address, l.references.clone()))
+            .collect();
+        assert_eq!(
+            pool,
+            vec![
+                ("41", Some(0x0011), vec![0x0000, 0x0003]),
+                ("000005", Some(0x0012), vec![0x0006, 0x0009]),
+                ("00000C", Some(0x0015), vec![0x000C]),
+            ]
+        );
+
+        let text = text_bytes(&result);
+        assert_eq!(
+            text[0].1,
+            "03200E 6B200B 6F2009 772006 072006 A000 41 000005 00000C".replace(' ', "")
+        );
+
+        // =* holds an address, so its pool word is relocated
+        assert_eq!(modification_addresses(&result), vec![0x0015]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 7);
+        assert!(diagnostics[0].message.contains("format 2"));
+    }
+
+    #[test]
+    fn test_relaxation_widens_out_of_range_instructions() {
+        let source = "\
+PROG    START   0
+FIRST   LDA     FAR
+        STA     FAR2
+        J       FIRST
+BUF     RESB    4000
+FAR     WORD    1
+FAR2    WORD    2
+        END     FIRST";
+
+        // without relaxation the instructions are reported, not silently widened
+        let (_, _, diagnostics) = assemble_with_tables(source);
+        let errors: Vec<usize> = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| d.line)
+            .collect();
+        assert_eq!(errors, vec![2, 3]);
+
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let options = AssemblerOptions {
+            relax: true,
+            ..Default::default()
+        };
+        let result = pass2asm(source, &options);
+        let symbols = SYMBOLTABLE.lock().unwrap().clone();
+        let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+
+        // both widened instructions take 4 bytes, moving FAR and FAR2 along
+        assert_eq!(symbol_address(&symbols, "FAR"), Some(0x0FAB));
+        assert_eq!(text_bytes(&result)[0].1, "03100FAB0F100FAE3F2FF5");
+        assert_eq!(modification_addresses(&result), vec![0x0001, 0x0005]);
+
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Note));
+        let widened: Vec<usize> = diagnostics
+            .iter()
+            .filter(|d| d.message.contains("widened to format 4"))
+            .map(|d| d.line)
+            .collect();
+        assert_eq!(widened, vec![2, 3]);
+        assert!(
+            diagnostics
+                .iter()
+                .any(|d| d.message.starts_with("LDB #FAR and BASE FAR"))
+        );
+    }
+
+    #[test]
+    fn test_free_form_source() {
+        let source = "\
+. full line comment
+SUBR\tRSUB\t\treturn to caller
+COMP    COMP    ZERO    . label named like a mnemonic
+        RSUB
+DOTS    BYTE    C'A.B C'   trailing words are a comment
+        COMPR   A, X
+ZERO    WORD    0";
+        let lines = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            parser(source, &AssemblerOptions::default())
+        };
+        // line, label, mnemonic, operand1, operand2
+        type Statement<'a> = (
+            usize,
+            Option<&'a str>,
+            String,
+            Option<&'a str>,
+            Option<&'a str>,
+        );
+        let summary: Vec<Statement> = lines
+            .iter()
+            .map(|l| {
+                let command = match &l.command {
+                    Command::Instruction(instr) => instr.instr.clone(),
+                    Command::Directive(directive) => directive.clone(),
+                };
+                (
+                    l.line,
+                    l.label.as_deref(),
+                    command,
+                    l.operand1.as_deref(),
+                    l.operand2.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (2, Some("SUBR"), "RSUB".to_string(), None, None),
+                (3, Some("COMP"), "COMP".to_string(), Some("ZERO"), None),
+                (4, None, "RSUB".to_string(), None, None),
+                (5, Some("DOTS"), "BYTE".to_string(), Some("C'A.B C'"), None),
+                (6, None, "COMPR".to_string(), Some("A"), Some("X")),
+                (7, Some("ZERO"), "WORD".to_string(), Some("0"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_spans_and_case_folding() {
+        let options = AssemblerOptions {
+            fold_case: true,
+            ..Default::default()
+        };
+        let tokens = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            tokenize("loop    lda     c'ab',x", &options)
+        };
+        let spans: Vec<(String, Span)> = tokens[0]
+            .iter()
+            .map(|t| (format!("{:?}", t.token), t.span))
+            .collect();
+        let span = |start, end| Span {
+            line: 1,
+            start,
+            end,
+        };
+        assert_eq!(spans[0], ("Label(\"LOOP\")".to_string(), span(0, 4)));
+        assert_eq!(spans[1].1, span(8, 11));
+        assert!(spans[1].0.contains("instr: \"LDA\""));
+        assert_eq!(spans[2], ("Operand1(\"C'ab'\")".to_string(), span(16, 21)));
+        assert_eq!(spans[3], ("Operand2(\"X\")".to_string(), span(22, 23)));
+    }
+
+    #[test]
+    fn test_fixed_column_source() {
+        let options = AssemblerOptions {
+            fixed_columns: true,
+            ..Default::default()
+        };
+        // columns 1-8 label, 10-15 mnemonic, 17-35 operand, the rest is a comment
+        let source = "\
+PROG     START  0
+FIRST    LDA    BUF+3              load the buffer
+\tRSUB\t\treturn
+BUF      RESB   6
+         END    FIRST";
+        let (result, symbols, diagnostics) = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let records = pass2asm(source, &options);
+            let symbols = SYMBOLTABLE.lock().unwrap().clone();
+            let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+            (records, symbols, diagnostics)
+        };
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(symbol_address(&symbols, "BUF"), Some(0x0006));
+        assert_eq!(text_bytes(&result)[0].1, "0320064F0000");
+    }
+
+    #[test]
+    fn test_cst_is_lossless() {
+        let source = "COPY\tSTART\t1000   . program\r\n\r\n  . indented comment\n\
+FIRST   LDA  BUFFER , X  trailing words\n        USE\n        RSUB";
+        // the tree leaves the assembler's diagnostics alone
+        let (tree, before, after) = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let before = DIAGNOSTICS.lock().unwrap().clone();
+            let tree = cst::parse(source, &AssemblerOptions::default());
+            (tree, before, DIAGNOSTICS.lock().unwrap().clone())
+        };
+        assert_eq!(before, after);
+        assert_eq!(tree.to_string(), source);
+        let (_, errors) = lex(source, &AssemblerOptions::default());
+        assert_eq!(
+            errors,
+            vec![LexError {
+                line: 5,
+                message: "Unknown mnemonic 'USE'".to_string()
+            }]
+        );
+
+        let kinds: Vec<CstKind> = tree.lines[3].elements.iter().map(|e| e.kind).collect();
+        use CstKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Label, Whitespace, Mnemonic, Whitespace, Operand, Whitespace, Comma, Whitespace,
+                Operand, Whitespace, Comment
+            ]
+        );
+        // USE is no statement the lexer knows, so it is not mistaken for a comment
+        assert_eq!(
+            tree.lines[4].find(Error).map(|e| e.text.as_str()),
+            Some("USE")
+        );
+    }
+
+    #[test]
+    fn test_lint_suppression_in_fixed_columns() {
+        let options = AssemblerOptions {
+            fixed_columns: true,
+            ..Default::default()
+        };
+        // the comment starts right after a full operand field, read free-form it is
+        // part of the operand
+        let source = "\
+PROG     START  0
+FIRST    RSUB
+FULL     RESB   1+1+1+1+1+1+1+1+1+1nolint(L001)
+TABBED\tRESB\t3\tnolint(unused-label)
+UNUSED   RESB   3
+         END    FIRST";
+        let (warnings, tree) = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            (lint(source, &options), cst::parse(source, &options))
+        };
+        let found: Vec<(usize, &str)> = warnings.iter().map(|w| (w.line, w.code.code())).collect();
+        assert_eq!(found, vec![(5, "L001")]);
+
+        assert_eq!(tree.to_string(), source);
+        let texts: Vec<(CstKind, &str)> = tree.lines[3]
+            .elements
+            .iter()
+            .map(|e| (e.kind, e.text.as_str()))
+            .collect();
+        use CstKind::*;
+        assert_eq!(
+            texts,
+            vec![
+                (Label, "TABBED"),
+                (Whitespace, "\t"),
+                (Mnemonic, "RESB"),
+                (Whitespace, "\t"),
+                (Operand, "3"),
+                (Whitespace, "\t"),
+                (Comment, "nolint(unused-label)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_formatter() {
+        let source = "\
+. header comment
+copy start 1000
+first\tlda   buffer , x    . load
+\t\trsub
+  +jsub  rdrec
+        USE    what
+LONGLABEL  compr a,s
+";
+        let formatted = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            format_source(source)
+        };
+        assert_eq!(
+            formatted,
+            "\
+. header comment
+copy    START   1000
+first   LDA     buffer,X                . load
+        RSUB
+        +JSUB   rdrec
+        USE    what
+LONGLABEL COMPR A,S
+"
+        );
+
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        assert_eq!(format_source(&formatted), formatted, "formatting is stable");
+    }
+
+    #[test]
+    fn test_lint_warnings() {
+        let source = "\
+PROG    START   0
+FIRST   JSUB    OUTER
+        STA     FIRST
+        J       FIRST
+        LDA     COUNT
+COUNT   WORD    0
+OUTER   STL     SAVE
+        JSUB    INNER
+        LDCH    COUNT
+        LDA     FAR
+        STA     FAR             . nolint(missing-base)
+        RSUB
+INNER   RSUB
+SAVE    RESW    1
+UNUSED  RESB    3000            . nolint(L003)
+FAR     WORD    1               . nolint
+        END     FIRST";
+        let warnings = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            lint(source, &AssemblerOptions::default())
+        };
+        let found: Vec<(usize, &str)> = warnings.iter().map(|w| (w.line, w.code.code())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "L003"),
+                (5, "L002"),
+                (6, "L006"),
+                (9, "L007"),
+                (10, "L005"),
+                (12, "L004"),
+                (15, "L001"),
+            ]
+        );
+        assert_eq!(
+            warnings[6].to_string(),
+            "line 15: warning[L001/unused-label]: label 'UNUSED' is never used"
+        );
+
+        // only an LDL from where STL saved the return address brings it back
+        let source = "\
+PROG    START   0
+FIRST   JSUB    RELOAD
+        JSUB    SAVED
+HALT    J       HALT
+RELOAD  JSUB    INNER
+        LDL     OTHER
+        RSUB
+SAVED   STL     SAVE
+        JSUB    INNER
+        LDL     SAVE
+        RSUB
+INNER   RSUB
+SAVE    RESW    1
+OTHER   WORD    0
+        END     FIRST";
+        let warnings = {
+            let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            lint(source, &AssemblerOptions::default())
+        };
+        let found: Vec<(usize, &str)> = warnings.iter().map(|w| (w.line, w.code.code())).collect();
+        assert_eq!(found, vec![(7, "L004")]);
+    }
+
+    #[test]
+    fn test_include_files() {
+        let dir = std::env::temp_dir().join(format!("hexe-include-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(
+            lib.join("io.asm"),
+            "BUFSZ   EQU     16\nIOBUF   RESB    BUFSZ\n        FOO\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a.asm"), "        INCLUDE 'b.asm'\n").unwrap();
+        std::fs::write(dir.join("b.asm"), "        INCLUDE 'a.asm'\n").unwrap();
+
+        let source = "\
+COPY    START   0
+        LDA     #BUFSZ
+        INCLUDE 'io.asm'
+        INCLUDE 'a.asm'
+        INCLUDE 'missing.asm'
+        END     COPY";
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let options = AssemblerOptions {
+            include_paths: vec![dir.clone(), lib.clone()],
+            ..Default::default()
+        };
+        let result = pass2asm(source, &options);
+        let symbols = SYMBOLTABLE.lock().unwrap().clone();
+        let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(symbol_address(&symbols, "IOBUF"), Some(0x0003));
+        assert_eq!(text_bytes(&result)[0].1, "010010");
+        let reported: Vec<(Option<String>, usize)> = diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.file
+                        .as_deref()
+                        .map(|f| f.rsplit('/').next().unwrap().to_string()),
+                    d.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                (Some("b.asm".to_string()), 1),
+                (None, 5),
+                (Some("io.asm".to_string()), 3),
+            ]
+        );
+        assert!(
+            diagnostics[2]
+                .to_string()
+                .ends_with("io.asm: error: Unknown mnemonic 'FOO'")
+        );
+        assert!(diagnostics[0].message.starts_with("Circular INCLUDE: "));
+        assert!(diagnostics[0].message.ends_with("a.asm"));
+    }
+
+    #[test]
+    fn test_include_of_the_main_source() {
+        let dir = std::env::temp_dir().join(format!("hexe-include-main-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.asm");
+        let source = "\
+COPY    START   0
+FIRST   LDA     #0
+        INCLUDE 'b.asm'
+        END     FIRST
+";
+        std::fs::write(&main, source).unwrap();
+        std::fs::write(dir.join("b.asm"), "        INCLUDE 'main.asm'\n").unwrap();
+
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let options = AssemblerOptions {
+            include_paths: vec![dir.clone()],
+            source: Some(main),
+            ..Default::default()
+        };
+        pass2asm(source, &options);
+        let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the cycle is caught before main.asm is read again, so FIRST is defined once
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert!(diagnostics[0].message.starts_with("Circular INCLUDE: "));
+        assert!(diagnostics[0].message.ends_with("main.asm"));
+    }
+
+    #[test]
+    fn test_conditional_assembly() {
+        let source = "\
+COPY    START   0
+DEBUG   EQU     1
+        IF      LEVEL-1
+        LDA     #2
+        ELSE
+        IFDEF   DEBUG
+        LDA     #1
+        ENDIF
+        ENDIF
+        IFNDEF  DEBUG
+        BOGUS
+        ENDIF
+        ENDIF
+        END     COPY";
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut options = AssemblerOptions::default();
+        let mut assemble_with = |level: u32| {
+            options.defines = vec![("LEVEL".to_string(), level)];
+            let result = pass2asm(source, &options);
+            let diagnostics = DIAGNOSTICS.lock().unwrap().clone();
+            (text_bytes(&result)[0].1.clone(), diagnostics)
+        };
+
+        let (code, diagnostics) = assemble_with(1);
+        assert_eq!(code, "010001");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 13);
+        assert_eq!(diagnostics[0].message, "ENDIF without IF");
+
+        let (code, _) = assemble_with(2);
+        assert_eq!(code, "010002");
+    }
+}
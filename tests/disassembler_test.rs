@@ -1,5 +1,9 @@
-use hexe::disassembler::disassembler::disassemble;
-use hexe::predefined::common::{Command, OBJECTPROGRAM, ObjectRecord};
+use hexe::assembler::pass2asm::pass2asm;
+use hexe::disassembler::disassembler::{disassemble, split_record};
+use hexe::predefined::common::{
+    ASSEMBLER_LOCK, AssemblerOptions, Command, LINEMAP, OBJECTPROGRAM, ObjectRecord,
+};
+use std::collections::BTreeSet;
 
 #[cfg(test)]
 mod disassembler_tests {
@@ -7,6 +11,9 @@ mod disassembler_tests {
 
     #[test]
     fn test_format3_instruction() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // no source, the record bytes alone say where instructions start
+        LINEMAP.lock().unwrap().clear();
         // Clear and set up
         {
             let mut program = OBJECTPROGRAM.lock().unwrap();
@@ -20,7 +27,7 @@ mod disassembler_tests {
                 ObjectRecord::Text {
                     start: 0x1000,
                     length: 3,
-                    objcodes: vec!["4B1000".to_string()],
+                    bytes: vec![0x4B, 0x10, 0x00],
                 },
                 ObjectRecord::End { start: 0x1000 },
             ];
@@ -61,6 +68,9 @@ mod disassembler_tests {
 
     #[test]
     fn test_format4_instruction() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // no source, the record bytes alone say where instructions start
+        LINEMAP.lock().unwrap().clear();
         {
             let mut program = OBJECTPROGRAM.lock().unwrap();
             program.clear();
@@ -73,7 +83,7 @@ mod disassembler_tests {
                 ObjectRecord::Text {
                     start: 0x1000,
                     length: 4,
-                    objcodes: vec!["03100000".to_string()],
+                    bytes: vec![0x03, 0x10, 0x00, 0x00],
                 },
                 ObjectRecord::End { start: 0x1000 },
             ];
@@ -108,6 +118,9 @@ mod disassembler_tests {
 
     #[test]
     fn test_format2_instruction() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // no source, the record bytes alone say where instructions start
+        LINEMAP.lock().unwrap().clear();
         {
             let mut program = OBJECTPROGRAM.lock().unwrap();
             program.clear();
@@ -120,7 +133,7 @@ mod disassembler_tests {
                 ObjectRecord::Text {
                     start: 0x1000,
                     length: 2,
-                    objcodes: vec!["B410".to_string()],
+                    bytes: vec![0xB4, 0x10],
                 },
                 ObjectRecord::End { start: 0x1000 },
             ];
@@ -155,6 +168,9 @@ mod disassembler_tests {
 
     #[test]
     fn test_multiple_instructions() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // no source, the record bytes alone say where instructions start
+        LINEMAP.lock().unwrap().clear();
         {
             let mut program = OBJECTPROGRAM.lock().unwrap();
             program.clear();
@@ -167,7 +183,7 @@ mod disassembler_tests {
                 ObjectRecord::Text {
                     start: 0x1000,
                     length: 6,
-                    objcodes: vec!["4B1000".to_string(), "4B1003".to_string()],
+                    bytes: vec![0x4B, 0x10, 0x00, 0x4B, 0x10, 0x03],
                 },
                 ObjectRecord::End { start: 0x1000 },
             ];
@@ -184,4 +200,43 @@ mod disassembler_tests {
         assert_eq!(result[0].locctr, 0x1000, "First instruction at 0x1000");
         assert_eq!(result[1].locctr, 0x1003, "Second instruction at 0x1003");
     }
+
+    #[test]
+    fn test_data_between_instructions() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // 00 looks like LDA and would swallow the STA after it
+        let source = "PROG    START   1000
+FIRST   LDA     #1
+        +JSUB   NEXT
+FLAG    BYTE    X'00'
+NEXT    STA     RES
+        J       *
+RES     RESW    1
+        END     FIRST
+";
+        pass2asm(source, &AssemblerOptions::default());
+        let result = disassemble();
+        let addresses: Vec<u32> = result.iter().map(|token| token.locctr).collect();
+        assert_eq!(addresses, vec![0x1000, 0x1003, 0x1007, 0x1008, 0x100B]);
+        match &result[3].command {
+            Command::Instruction(instr) => assert_eq!(instr.instr, "STA"),
+            _ => panic!("Expected instruction, got directive"),
+        }
+
+        // the same bytes without the source guess the data into the STA
+        let bytes = [
+            0x01, 0x00, 0x01, 0x4B, 0x10, 0x10, 0x08, 0x00, 0x0F, 0x20, 0x03,
+        ];
+        let boundaries = BTreeSet::from([0x1000, 0x1003, 0x1007, 0x1008]);
+        let pieces: Vec<usize> = split_record(0x1000, &bytes, &boundaries)
+            .iter()
+            .map(|piece| piece.len())
+            .collect();
+        assert_eq!(pieces, vec![3, 4, 1, 3]);
+        let pieces: Vec<usize> = split_record(0x1000, &bytes, &BTreeSet::new())
+            .iter()
+            .map(|piece| piece.len())
+            .collect();
+        assert_eq!(pieces, vec![3, 4, 3, 1]);
+    }
 }
@@ -1,5 +1,9 @@
-use hexe::loader::loader::loader;
+use hexe::disassembler::disassembler::split_instructions;
+use hexe::loader::image::{ImageFormat, export, image, import, object_program};
+use hexe::loader::loader::{loader, relocate};
+use hexe::loader::validate::validate;
 use hexe::predefined::common::{OBJECTPROGRAM, ObjectRecord};
+use hexe::simulator::inistialize_machine::Machine;
 
 #[cfg(test)]
 mod loader_tests {
@@ -67,23 +71,16 @@ mod loader_tests {
         if let Some(ObjectRecord::Text {
             start,
             length,
-            objcodes,
+            bytes,
         }) = text_record
         {
             assert_eq!(*start, 0x1000, "Text start should be 0x1000");
             assert_eq!(*length, 0x0C, "Text length should be 0x0C (12 bytes)");
-            assert!(!objcodes.is_empty(), "Object codes should not be empty");
-            assert_eq!(objcodes.len(), 2, "Should have 2 object codes");
-            assert_eq!(
-                objcodes[0].len(),
-                6,
-                "First objcode should be 6 chars (format 3)"
-            );
-            assert_eq!(
-                objcodes[1].len(),
-                6,
-                "Second objcode should be 6 chars (format 3)"
-            );
+            assert_eq!(bytes, &[0x4B, 0x10, 0x00, 0x4B, 0x10, 0x03]);
+            let instructions = split_instructions(bytes);
+            assert_eq!(instructions.len(), 2, "Should have 2 instructions");
+            assert_eq!(instructions[0].len(), 3, "First should be format 3");
+            assert_eq!(instructions[1].len(), 3, "Second should be format 3");
         }
     }
 
@@ -216,20 +213,14 @@ mod loader_tests {
 
         assert_eq!(text_records.len(), 2, "Should have 2 text records");
 
-        if let ObjectRecord::Text {
-            start, objcodes, ..
-        } = text_records[0]
-        {
+        if let ObjectRecord::Text { start, bytes, .. } = text_records[0] {
             assert_eq!(*start, 0x1000, "First text at 0x1000");
-            assert!(!objcodes.is_empty(), "First text should have object codes");
+            assert!(!bytes.is_empty(), "First text should have bytes");
         }
 
-        if let ObjectRecord::Text {
-            start, objcodes, ..
-        } = text_records[1]
-        {
+        if let ObjectRecord::Text { start, bytes, .. } = text_records[1] {
             assert_eq!(*start, 0x1010, "Second text at 0x1010");
-            assert!(!objcodes.is_empty(), "Second text should have object codes");
+            assert!(!bytes.is_empty(), "Second text should have bytes");
         }
     }
 
@@ -254,10 +245,10 @@ mod loader_tests {
             .find(|r| matches!(r, ObjectRecord::Text { .. }));
         assert!(text_record.is_some(), "Should have text record");
 
-        if let Some(ObjectRecord::Text { objcodes, .. }) = text_record {
-            assert!(!objcodes.is_empty(), "Should have object codes");
-            assert_eq!(objcodes[0].len(), 4, "Format 2 should be 4 hex chars");
-            assert_eq!(objcodes[0], "B410", "Should be B410 (CLEAR A)");
+        if let Some(ObjectRecord::Text { bytes, .. }) = text_record {
+            let instructions = split_instructions(bytes);
+            assert_eq!(instructions[0].len(), 2, "Format 2 should be 2 bytes");
+            assert_eq!(instructions[0], &[0xB4, 0x10], "Should be B410 (CLEAR A)");
         }
     }
 
@@ -282,10 +273,10 @@ mod loader_tests {
             .find(|r| matches!(r, ObjectRecord::Text { .. }));
         assert!(text_record.is_some(), "Should have text record");
 
-        if let Some(ObjectRecord::Text { objcodes, .. }) = text_record {
-            assert!(!objcodes.is_empty(), "Should have object codes");
-            assert_eq!(objcodes[0].len(), 2, "Format 1 should be 2 hex chars");
-            assert_eq!(objcodes[0], "C4", "Should be C4 (FIX)");
+        if let Some(ObjectRecord::Text { bytes, .. }) = text_record {
+            let instructions = split_instructions(bytes);
+            assert_eq!(instructions[0].len(), 1, "Format 1 should be 1 byte");
+            assert_eq!(instructions[0], &[0xC4], "Should be C4 (FIX)");
         }
     }
 
@@ -310,11 +301,12 @@ mod loader_tests {
             .find(|r| matches!(r, ObjectRecord::Text { .. }));
         assert!(text_record.is_some(), "Should have text record");
 
-        if let Some(ObjectRecord::Text { objcodes, .. }) = text_record {
-            assert_eq!(objcodes.len(), 3, "Should have 3 instructions");
-            assert_eq!(objcodes[0].len(), 2, "First should be format 1");
-            assert_eq!(objcodes[1].len(), 4, "Second should be format 2");
-            assert_eq!(objcodes[2].len(), 6, "Third should be format 3");
+        if let Some(ObjectRecord::Text { bytes, .. }) = text_record {
+            let instructions = split_instructions(bytes);
+            assert_eq!(instructions.len(), 3, "Should have 3 instructions");
+            assert_eq!(instructions[0].len(), 1, "First should be format 1");
+            assert_eq!(instructions[1].len(), 2, "Second should be format 2");
+            assert_eq!(instructions[2].len(), 3, "Third should be format 3");
         }
     }
 
@@ -344,4 +336,305 @@ mod loader_tests {
             _ => panic!("Expected header record"),
         }
     }
+
+    #[test]
+    fn test_relocation_applies_modification_records() {
+        // +LDA DATA, DATA WORD DATA, a field for another program, PROG assembled at 0
+        let input = "HPROG  000000000007\nT0000000703100004000004\nM00000105+PROG\nM00000406+PROG\nM00000105+OTHER\nE000000";
+        let loaded = loader(input.to_string());
+        assert!(loaded.iter().any(|r| matches!(
+            r,
+            ObjectRecord::Modification { address: 4, length: 6, sign: true, variable } if variable == "PROG"
+        )));
+
+        let mut records = vec![
+            ObjectRecord::Header {
+                name: "PROG".to_string(),
+                start: 0,
+                length: 7,
+            },
+            ObjectRecord::Text {
+                start: 0,
+                length: 7,
+                bytes: vec![0x03, 0x10, 0x00, 0x04, 0x00, 0x00, 0x04],
+            },
+            ObjectRecord::Modification {
+                address: 1,
+                length: 5,
+                sign: true,
+                variable: "PROG".to_string(),
+            },
+            ObjectRecord::Modification {
+                address: 4,
+                length: 6,
+                sign: true,
+                variable: "PROG".to_string(),
+            },
+            ObjectRecord::Modification {
+                address: 1,
+                length: 5,
+                sign: true,
+                variable: "OTHER".to_string(),
+            },
+            ObjectRecord::End { start: 0 },
+        ];
+        relocate(&mut records, 0x2000);
+
+        let ObjectRecord::Text { start, bytes, .. } = &records[1] else {
+            panic!("expected a text record");
+        };
+        assert_eq!(*start, 0x2000);
+        assert_eq!(hex::encode_upper(bytes), "03102004002004");
+        assert!(matches!(
+            records[0],
+            ObjectRecord::Header { start: 0x2000, .. }
+        ));
+        assert!(matches!(
+            records[2],
+            ObjectRecord::Modification {
+                address: 0x2001,
+                ..
+            }
+        ));
+        assert!(matches!(records[5], ObjectRecord::End { start: 0x2000 }));
+
+        // a field longer than a word is left alone instead of overflowing the patch
+        for length in [8, 9, 0xFF] {
+            let mut records = vec![
+                ObjectRecord::Text {
+                    start: 0,
+                    length: 6,
+                    bytes: vec![0x00, 0x10, 0x00, 0x00, 0x00, 0x00],
+                },
+                ObjectRecord::Modification {
+                    address: 0,
+                    length,
+                    sign: true,
+                    variable: "PROG".to_string(),
+                },
+            ];
+            relocate(&mut records, 0x2000);
+            let ObjectRecord::Text { bytes, .. } = &records[0] else {
+                panic!("expected a text record");
+            };
+            assert_eq!(hex::encode_upper(bytes), "001000000000");
+        }
+    }
+
+    #[test]
+    fn test_images_round_trip() {
+        // two runs, the second one past 64K so Intel HEX needs an extended address
+        let records = vec![
+            ObjectRecord::Header {
+                name: "COPY".to_string(),
+                start: 0x1000,
+                length: 0xF003,
+            },
+            ObjectRecord::Text {
+                start: 0x1000,
+                length: 6,
+                bytes: vec![0x03, 0x10, 0x00, 0x4F, 0x00, 0x00],
+            },
+            ObjectRecord::Text {
+                start: 0x10000,
+                length: 3,
+                bytes: vec![0x45, 0x4F, 0x46],
+            },
+            ObjectRecord::End { start: 0x1003 },
+        ];
+        let mut machine = Machine::new();
+        machine.load_records(&records);
+        let exported = image(&records, &machine.memory);
+        assert_eq!(
+            exported.segments,
+            vec![
+                (0x1000, vec![0x03, 0x10, 0x00, 0x4F, 0x00, 0x00]),
+                (0x10000, vec![0x45, 0x4F, 0x46]),
+            ]
+        );
+
+        let hex = export(&exported, ImageFormat::IntelHex);
+        assert!(String::from_utf8_lossy(&hex).starts_with(":061000000310004F000088\n"));
+        assert_eq!(
+            import(&hex, ImageFormat::IntelHex, 0).unwrap().segments,
+            exported.segments
+        );
+        assert_eq!(
+            import(&hex, ImageFormat::IntelHex, 0).unwrap().entry,
+            Some(0x1003)
+        );
+
+        let srec = import(
+            &export(&exported, ImageFormat::SRecord),
+            ImageFormat::SRecord,
+            0,
+        )
+        .unwrap();
+        assert_eq!(srec, exported);
+
+        // a raw binary is one run, zero-filled, placed where it is loaded
+        let bin = export(&exported, ImageFormat::Binary);
+        assert_eq!(bin.len(), 0xF003);
+        let raw = import(&bin, ImageFormat::Binary, 0x1000).unwrap();
+        assert_eq!(raw.segments[0].0, 0x1000);
+        assert_eq!(
+            &raw.segments[0].1[..6],
+            &[0x03, 0x10, 0x00, 0x4F, 0x00, 0x00]
+        );
+
+        // loading an image again makes T records of at most 30 bytes
+        let program = object_program(&raw);
+        assert!(program.iter().all(|r| match r {
+            ObjectRecord::Text { length, .. } => *length <= 0x1E,
+            _ => true,
+        }));
+        assert!(matches!(
+            program.last(),
+            Some(ObjectRecord::End { start: 0x1000 })
+        ));
+    }
+
+    #[test]
+    fn test_image_checksum_errors() {
+        let hex = b":03100000031000DC\n:00000001FF\n";
+        assert_eq!(
+            import(hex, ImageFormat::IntelHex, 0),
+            Err("line 1: bad checksum".to_string())
+        );
+        let srec = b"S006000048455814\nS1061000031000C5\nS9031000EC\n";
+        assert_eq!(
+            import(srec, ImageFormat::SRecord, 0),
+            Err("line 2: bad checksum".to_string())
+        );
+    }
+
+    #[test]
+    fn test_image_addresses_past_24_bits() {
+        // an object program writes addresses in six hex digits, the last byte fits
+        let top = import(b"S30600FFFFFF41BB\n", ImageFormat::SRecord, 0).unwrap();
+        assert_eq!(top.segments, vec![(0xFFFFFF, vec![0x41])]);
+        assert_eq!(
+            import(b"S3060100000041B7\n", ImageFormat::SRecord, 0),
+            Err("line 1: data at 1000000 runs past address FFFFFF".to_string())
+        );
+        assert_eq!(
+            import(b"S70501000000F9\n", ImageFormat::SRecord, 0),
+            Err("line 1: entry point 1000000 is past address FFFFFF".to_string())
+        );
+        assert_eq!(
+            import(
+                b":020000040100F9\n:0100000041BE\n",
+                ImageFormat::IntelHex,
+                0
+            ),
+            Err("line 2: data at 1000000 runs past address FFFFFF".to_string())
+        );
+        // the end of this one does not even fit in 32 bits
+        assert_eq!(
+            import(
+                b":02000004FFFFFC\n:02FFFF0041427D\n",
+                ImageFormat::IntelHex,
+                0
+            ),
+            Err("line 2: data at FFFFFFFF runs past address FFFFFF".to_string())
+        );
+        assert!(import(&[0, 0], ImageFormat::Binary, 0xFFFFFF).is_err());
+    }
+
+    #[test]
+    fn test_validate_accepts_a_well_formed_program() {
+        let input = "H^COPY  ^001000^00000A\nT^001000^06^031006^4F0000\nT^001006^04^00000003\nM^001001^05^+COPY\nE^001000\n";
+        assert!(validate(input).is_empty(), "{:?}", validate(input));
+    }
+
+    #[test]
+    fn test_validate_reports_each_bad_record() {
+        let input = "\
+T00100003031006
+H1COPY 00100000000A
+T0010000603100G4F0000
+T00100004031006
+T00100803031006
+M00101005+COPY
+E002000
+T00100003031006
+";
+        let errors: Vec<(usize, String)> = validate(input)
+            .into_iter()
+            .map(|e| (e.line, e.message))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (1, "T record before the H record".to_string()),
+                (2, "Illegal program name '1COPY'".to_string()),
+                (
+                    3,
+                    "T record object code '03100G4F0000' is not hex bytes".to_string()
+                ),
+                (4, "T record length 04 but it holds 03 bytes".to_string()),
+                (
+                    5,
+                    "T record 001008-00100B is outside program 1COPY (001000-00100A)".to_string()
+                ),
+                (
+                    6,
+                    "M record field 001010, 5 half-bytes, is outside program 1COPY".to_string()
+                ),
+                (
+                    7,
+                    "Entry point 002000 is outside program 1COPY (001000-00100A)".to_string()
+                ),
+                (8, "T record after the E record".to_string()),
+            ]
+        );
+
+        let overlapping = "HCOPY  00100000000A\nT00100003031006\nT00100203031006\nE001000";
+        assert_eq!(
+            validate(overlapping)[0].message,
+            "T record overlaps the T record on line 2"
+        );
+        assert_eq!(
+            validate("HCOPY  00100000000A\nT00100003031006")[0].message,
+            "Program COPY has no E record"
+        );
+        assert_eq!(
+            validate("HCOPY  00100000000A\nT00100003031006\nM00100008+COPY\nE001000")[0].message,
+            "M record length 8 is over 6 half-bytes"
+        );
+    }
+
+    #[test]
+    fn test_text_record_keeps_exact_bytes() {
+        {
+            let mut program = OBJECTPROGRAM.lock().unwrap();
+            program.clear();
+        }
+
+        // +JSUB, then BYTE C'EOF' and a lone BYTE X'C4' that looks like FIX
+        let input = "HTEST  001000000008
+T001000084B101000454F46C4
+E001000";
+        let result = loader(input.to_string());
+        {
+            let mut program = OBJECTPROGRAM.lock().unwrap();
+            program.clear();
+        }
+
+        let Some(ObjectRecord::Text { bytes, .. }) = result
+            .iter()
+            .find(|r| matches!(r, ObjectRecord::Text { .. }))
+        else {
+            panic!("expected a text record");
+        };
+        assert_eq!(hex::encode_upper(bytes), "4B101000454F46C4");
+        assert_eq!(split_instructions(bytes)[0], &[0x4B, 0x10, 0x10, 0x00]);
+        assert_eq!(
+            result
+                .iter()
+                .find(|r| matches!(r, ObjectRecord::Text { .. }))
+                .map(|r| r.to_string()),
+            Some("T001000084B101000454F46C4".to_string())
+        );
+    }
 }
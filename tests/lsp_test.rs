@@ -0,0 +1,221 @@
+use hexe::lsp::analysis::{Document, completions};
+use hexe::lsp::server::{file_path, serve};
+use hexe::predefined::common::AssemblerOptions;
+use lsp_server::{Connection, Message, Notification, Request, RequestId};
+use lsp_types::{DiagnosticSeverity, HoverContents, NumberOrString, Position, Range, Uri};
+use serde_json::json;
+use std::path::PathBuf;
+
+const PROGRAM: &str = "\
+COPY    START   1000
+FIRST   LDA     LENGTH
+        STA     BUFFER,X
+        J       FIRST
+LENGTH  WORD    3
+BUFFER  RESB    10
+UNUSED  RESW    1
+        END     FIRST
+";
+
+fn document(source: &str) -> Document {
+    Document::new(source.to_string(), &AssemblerOptions::default())
+}
+
+fn range(line: u32, start: u32, end: u32) -> Range {
+    Range {
+        start: Position::new(line, start),
+        end: Position::new(line, end),
+    }
+}
+
+fn hover_text(document: &Document, position: Position) -> Option<String> {
+    document.hover(position).map(|hover| match hover.contents {
+        HoverContents::Markup(markup) => markup.value,
+        other => panic!("unexpected hover contents {:?}", other),
+    })
+}
+
+#[cfg(test)]
+mod lsp_tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics_include_errors_and_lint_warnings() {
+        let document = document("COPY START 1000\n FOO A\nUNUSED RESW 1\n END COPY\n");
+
+        let error = document
+            .diagnostics
+            .iter()
+            .find(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+            .expect("unknown mnemonic should be an error");
+        assert_eq!(error.range, range(1, 0, 6));
+        assert!(error.message.contains("FOO"));
+
+        let warning = document
+            .diagnostics
+            .iter()
+            .find(|d| d.code == Some(NumberOrString::String("L001/unused-label".to_string())))
+            .expect("unused label should be linted");
+        assert_eq!(warning.range.start.line, 2);
+    }
+
+    #[test]
+    fn test_assembler_panic_is_a_diagnostic() {
+        // the location counter runs past u32 here
+        let crashed = document("P START FFFFFFFF\n LDA #1\n LDA #1\n END\n");
+        assert_eq!(crashed.diagnostics.len(), 1);
+        assert_eq!(
+            crashed.diagnostics[0].severity,
+            Some(DiagnosticSeverity::ERROR)
+        );
+        assert!(
+            crashed.diagnostics[0]
+                .message
+                .starts_with("The assembler failed on this file")
+        );
+
+        // the tables are usable again for the next document
+        let document = document(PROGRAM);
+        assert!(!document.symbols.is_empty());
+    }
+
+    #[test]
+    fn test_definition_and_references() {
+        let document = document(PROGRAM);
+
+        // `BUFFER` in `BUFFER,X` on line 3
+        let position = Position::new(2, 18);
+        assert_eq!(document.definition(position), Some(range(5, 0, 6)));
+        assert_eq!(
+            document.references(position, true),
+            vec![range(2, 16, 22), range(5, 0, 6)]
+        );
+        // from the definition, the uses of FIRST, without the label itself
+        assert_eq!(
+            document.references(Position::new(1, 2), false),
+            vec![range(3, 16, 21), range(7, 16, 21)]
+        );
+        // registers are not symbols
+        assert_eq!(document.definition(Position::new(2, 23)), None);
+    }
+
+    #[test]
+    fn test_hover() {
+        let document = document(PROGRAM);
+        assert_eq!(
+            hover_text(&document, Position::new(1, 9)).as_deref(),
+            Some("**LDA**: opcode 0x00, format 3")
+        );
+        assert_eq!(
+            hover_text(&document, Position::new(4, 9)).as_deref(),
+            Some("**WORD**: assembler directive")
+        );
+        assert_eq!(
+            hover_text(&document, Position::new(1, 17)).as_deref(),
+            Some("**LENGTH**: 001009 (relative)")
+        );
+    }
+
+    #[test]
+    fn test_completions_and_semantic_tokens() {
+        let items = completions();
+        let lda = items.iter().find(|i| i.label == "+LDA").unwrap();
+        assert_eq!(lda.detail.as_deref(), Some("opcode 0x00, format 4"));
+        assert!(items.iter().any(|i| i.label == "LTORG"));
+
+        let document = document("FIRST LDA #0FFF . load\n");
+        let tokens: Vec<(u32, u32, u32)> = document
+            .semantic_tokens()
+            .iter()
+            .map(|t| (t.delta_start, t.length, t.token_type))
+            .collect();
+        // label, instruction, number, comment
+        assert_eq!(tokens, vec![(0, 5, 0), (6, 3, 1), (4, 5, 3), (6, 6, 5)]);
+    }
+
+    #[test]
+    fn test_file_uri_to_path() {
+        let path = |uri: &str| file_path(&uri.parse::<Uri>().unwrap());
+        assert_eq!(
+            path("file:///home/me/my%20code/a.asm"),
+            Some(PathBuf::from("/home/me/my code/a.asm"))
+        );
+        assert_eq!(
+            path("file:///C:/src/a.asm"),
+            Some(PathBuf::from("C:/src/a.asm"))
+        );
+        assert_eq!(path("untitled:Untitled-1"), None);
+    }
+
+    #[test]
+    fn test_server_over_connection() {
+        let (server, client) = Connection::memory();
+        let thread = std::thread::spawn(move || serve(&server).unwrap());
+
+        let request = |id: i32, method: &str, params: serde_json::Value| {
+            Message::Request(Request::new(
+                RequestId::from(id),
+                method.to_string(),
+                params,
+            ))
+        };
+        let notification = |method: &str, params: serde_json::Value| {
+            Message::Notification(Notification::new(method.to_string(), params))
+        };
+        client
+            .sender
+            .send(request(1, "initialize", json!({ "capabilities": {} })))
+            .unwrap();
+        let Message::Response(response) = client.receiver.recv().unwrap() else {
+            panic!("expected the initialize response");
+        };
+        assert_eq!(
+            response.result.unwrap()["capabilities"]["hoverProvider"],
+            json!(true)
+        );
+        client
+            .sender
+            .send(notification("initialized", json!({})))
+            .unwrap();
+
+        let uri = "file:///copy.asm";
+        client
+            .sender
+            .send(notification(
+                "textDocument/didOpen",
+                json!({ "textDocument": {
+                    "uri": uri, "languageId": "sicxe", "version": 1, "text": PROGRAM
+                }}),
+            ))
+            .unwrap();
+        let Message::Notification(published) = client.receiver.recv().unwrap() else {
+            panic!("expected diagnostics");
+        };
+        assert_eq!(published.method, "textDocument/publishDiagnostics");
+        assert_eq!(published.params["diagnostics"].as_array().unwrap().len(), 1);
+
+        client
+            .sender
+            .send(request(
+                2,
+                "textDocument/definition",
+                json!({ "textDocument": { "uri": uri }, "position": { "line": 2, "character": 18 } }),
+            ))
+            .unwrap();
+        let Message::Response(response) = client.receiver.recv().unwrap() else {
+            panic!("expected the definition");
+        };
+        assert_eq!(response.result.unwrap()["range"]["start"]["line"], json!(5));
+
+        client
+            .sender
+            .send(request(3, "shutdown", serde_json::Value::Null))
+            .unwrap();
+        client.receiver.recv().unwrap();
+        client
+            .sender
+            .send(notification("exit", json!(null)))
+            .unwrap();
+        thread.join().unwrap();
+    }
+}
@@ -1,7 +1,13 @@
+use hexe::assembler::pass2asm::pass2asm;
 use hexe::predefined::common::{
-    AddressFlags, Command, DisAssembledToken, Instruction, OBJECTPROGRAM, ObjectRecord, OpCode,
+    ASSEMBLER_LOCK, AddressFlags, AssemblerOptions, Command, DisAssembledToken, Instruction,
+    LINEMAP, OBJECTPROGRAM, ObjectRecord, OpCode, Origin,
 };
-use hexe::simulator::sim::Simulator;
+use hexe::simulator::command::{DebugCommand, parse_command};
+use hexe::simulator::sim::{RunTarget, Simulator, StopReason};
+use hexe::simulator::trace::{OpcodeClass, TraceFilter};
+use hexe::tui::memory::{Follow, MemoryWidget, ebcdic};
+use std::collections::HashSet;
 
 #[cfg(test)]
 mod simulator_tests {
@@ -134,7 +140,7 @@ mod simulator_tests {
                 ObjectRecord::Text {
                     start: 0x1000,
                     length: 3,
-                    objcodes: vec!["4B1000".to_string()],
+                    bytes: vec![0x4B, 0x10, 0x00],
                 },
                 ObjectRecord::End { start: 0x1000 },
             ];
@@ -227,4 +233,446 @@ mod simulator_tests {
         assert_eq!(sim.machine.reg_pc, 0, "PC should be reset to 0");
         assert!(!sim.running, "Running flag should be false");
     }
+
+    #[test]
+    fn test_step_records_changed_memory() {
+        let mut sim = Simulator::new();
+        sim.machine.reg_pc = 0x1000;
+        sim.machine.reg_a = 0x123456;
+        sim.instructions = vec![DisAssembledToken {
+            locctr: 0x1000,
+            command: Command::Instruction(Instruction {
+                instr: "STA".to_string(),
+                opcode: OpCode {
+                    code: 0x0C,
+                    format: 3,
+                },
+            }),
+            flags: Some(AddressFlags {
+                n: true,
+                i: true,
+                x: false,
+                b: false,
+                p: false,
+                e: false,
+            }),
+            address: Some(0x2000),
+            reg: None,
+        }];
+
+        assert!(sim.step());
+        assert_eq!(&sim.machine.memory[0x2000..0x2003], &[0x12, 0x34, 0x56]);
+        assert_eq!(sim.changed, vec![0x2000, 0x2001, 0x2002]);
+        assert_eq!(sim.last_address, Some(0x2000));
+        assert!(sim.changed_since_stop.contains(&0x2001));
+
+        // a run starts a new set of changes since the stop
+        sim.run();
+        assert!(sim.changed_since_stop.is_empty());
+    }
+
+    #[test]
+    fn test_register_editing_and_changes() {
+        let mut sim = Simulator::new();
+        sim.machine.set_register("A", "0x1F").unwrap();
+        sim.machine.set_register("X", "-1").unwrap();
+        sim.machine.set_register("T", "#10").unwrap();
+        sim.machine.set_register("F", "2.5").unwrap();
+        assert_eq!(sim.machine.reg_a, 0x1F);
+        assert_eq!(sim.machine.reg_x, 0xFFFFFF);
+        assert_eq!(sim.machine.reg_t, 10);
+        assert_eq!(sim.machine.reg_f, 2.5);
+        assert!(sim.machine.set_register("Q", "1").is_err());
+        assert!(sim.machine.set_register("A", "xyz").is_err());
+
+        // SW carries the condition code, setting it sets CC
+        sim.machine.set_register("SW", "000000").unwrap();
+        assert_eq!(sim.machine.cc, -1);
+        assert_eq!(sim.machine.registers().sw & 0x030000, 0);
+
+        sim.machine.reg_pc = 0x1000;
+        sim.instructions = vec![DisAssembledToken {
+            locctr: 0x1000,
+            command: Command::Instruction(Instruction {
+                instr: "LDA".to_string(),
+                opcode: OpCode {
+                    code: 0x00,
+                    format: 3,
+                },
+            }),
+            flags: Some(AddressFlags {
+                n: false,
+                i: true,
+                x: false,
+                b: false,
+                p: false,
+                e: false,
+            }),
+            address: Some(5),
+            reg: None,
+        }];
+        sim.step();
+        assert_eq!(sim.changed_registers, vec!["A", "PC"]);
+    }
+
+    #[test]
+    fn test_debugger_commands() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let source = "PROG    START   1000
+FIRST   JSUB    SUB
+        STA     RESULT
+        J       FIRST
+SUB     LDA     #5
+        ADD     #1
+        RSUB
+RESULT  RESW    1
+        END     FIRST
+";
+        pass2asm(source, &AssemblerOptions::default());
+        let mut sim = Simulator::new();
+        sim.load_program();
+        let mut run = |line: &str| {
+            let command = parse_command(line)?;
+            sim.execute_command(&command)
+        };
+
+        // labels work wherever an address does
+        assert_eq!(run("break SUB").unwrap(), vec!["Breakpoint 1 at 001009"]);
+        assert_eq!(run("b").unwrap(), vec!["1  001009"]);
+        assert_eq!(run("until").unwrap(), vec!["Breakpoint hit at 001009"]);
+        assert_eq!(
+            run("delete 1").unwrap(),
+            vec!["Deleted breakpoint 1 at 001009"]
+        );
+        assert!(run("delete 1").is_err());
+
+        // finish runs to the return of the subroutine
+        assert_eq!(run("finish").unwrap(), vec!["Stopped at 001003"]);
+        assert_eq!(run("x/1dw 0").unwrap(), vec!["000000: 0"]);
+        run("until 1006").unwrap();
+        assert_eq!(run("x/1dw RESULT").unwrap(), vec!["001012: 6"]);
+
+        run("set mem RESULT+1 = 4142").unwrap();
+        assert_eq!(run("x/3xb RESULT").unwrap(), vec!["001012: 00 41 42"]);
+        assert_eq!(run("x/3cb RESULT").unwrap(), vec!["001012: . A B"]);
+        assert_eq!(run("find C'AB'").unwrap(), vec!["001013"]);
+        run("set A = 0x41").unwrap();
+        run("goto FIRST").unwrap();
+        run("delete").unwrap();
+
+        // a count too large for memory is an error, not an overflow
+        assert!(run("x/9000000000000000000w 0").is_err());
+
+        // a file that does not parse leaves the program loaded
+        let bad = std::env::temp_dir().join("sicxe_debugger_bad.hex");
+        std::fs::write(&bad, ":zz\n").unwrap();
+        assert!(run(&format!("load {}", bad.display())).is_err());
+        std::fs::remove_file(&bad).unwrap();
+        assert_eq!(run("x/1dw RESULT").unwrap(), vec!["001012: 16706"]);
+        assert_eq!(run("b SUB").unwrap(), vec!["Breakpoint 1 at 001009"]);
+
+        assert_eq!(sim.machine.reg_a, 0x41);
+        assert_eq!(sim.machine.reg_pc, 0x1000);
+
+        assert_eq!(
+            parse_command("x 1000"),
+            Ok(DebugCommand::Examine {
+                count: 1,
+                format: 'x',
+                unit: 'b',
+                address: 0x1000,
+            })
+        );
+        assert!(parse_command("x/4q 1000").is_err());
+        assert!(parse_command("break NOWHERE").is_err());
+        assert!(parse_command("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_source_line_map() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let source = "PROG    START   1000
+. comments and RESW have no code
+FIRST   LDA     #5
+        ADD     =X'000001'
+        STA     RESULT
+RESULT  RESW    1
+        END     FIRST
+";
+        pass2asm(source, &AssemblerOptions::default());
+        let lines: Vec<(u32, usize)> = LINEMAP
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|line| (line.address, line.origin.line))
+            .collect();
+        assert_eq!(lines, vec![(0x1000, 3), (0x1003, 4), (0x1006, 5)]);
+
+        let mut sim = Simulator::new();
+        sim.load_program();
+        let line = |line| Origin { file: None, line };
+        assert_eq!(sim.source_line(0x1003).unwrap().origin, line(4));
+        assert_eq!(sim.line_address(&line(5)), Some(0x1006));
+        assert_eq!(sim.line_address(&line(2)), None);
+
+        assert_eq!(sim.run_until(RunTarget::Line), "Stopped at 001003");
+        assert_eq!(sim.machine.reg_pc, 0x1003);
+        assert!(sim.toggle_breakpoint(0x1006));
+        assert!(!sim.toggle_breakpoint(0x1006));
+        assert!(sim.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn test_run_in_slices() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let source = "PROG    START   1000
+FIRST   LDA     #0
+LOOP    ADD     #1
+        J       LOOP
+HALT    J       HALT
+        END     FIRST
+";
+        pass2asm(source, &AssemblerOptions::default());
+        let mut sim = Simulator::new();
+        sim.load_program();
+
+        // a runaway loop stops at the limit
+        sim.instruction_limit = 100;
+        sim.run();
+        assert_eq!(sim.stop_reason, Some(StopReason::Limit(100)));
+        assert!(!sim.running);
+
+        // slices keep the run going until it is paused
+        sim.start_run();
+        assert_eq!(sim.run_slice(5), 5);
+        assert!(sim.running);
+        sim.pause();
+        assert_eq!(sim.stop_reason, Some(StopReason::Paused));
+        assert_eq!(sim.run_slice(5), 0);
+
+        // a run started on a breakpoint leaves it and stops there the next time round
+        sim.machine.reg_pc = 0x1003;
+        sim.add_breakpoint(0x1003);
+        sim.start_run();
+        assert_eq!(sim.run_slice(usize::MAX), 2);
+        assert_eq!(sim.stop_reason, Some(StopReason::Breakpoint(0x1003)));
+
+        sim.machine.reg_pc = 0x1009;
+        sim.run();
+        assert_eq!(sim.stop_reason, Some(StopReason::Halted(0x1009)));
+        assert_eq!(sim.executed, 1);
+
+        // until runs in slices as well, and a pause drops its target
+        sim.breakpoints.clear();
+        sim.machine.reg_pc = 0x1000;
+        sim.run_to(RunTarget::Address(0x1009));
+        assert_eq!(sim.run_slice(5), 5);
+        assert!(sim.running);
+        sim.pause();
+        assert_eq!(sim.target, None);
+        sim.instruction_limit = 1_000;
+        assert_eq!(
+            sim.run_until(RunTarget::Address(0x1003)),
+            "Stopped at 001003"
+        );
+        assert_eq!(
+            sim.run_until(RunTarget::Address(0x1009)),
+            "Stopped after 1000 instructions"
+        );
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let source = "PROG    START   1000
+FIRST   JSUB    OUTER
+HALT    J       HALT
+SECOND  JSUB    LEAKY
+OUTER   STL     RETADR
+        JSUB    INNER
+        LDL     RETADR
+        RSUB
+LEAKY   JSUB    INNER
+        RSUB
+INNER   LDA     #1
+        RSUB
+RETADR  RESW    1
+        END     FIRST
+";
+        pass2asm(source, &AssemblerOptions::default());
+        let mut sim = Simulator::new();
+        sim.load_program();
+
+        sim.step();
+        assert_eq!(sim.call_stack.len(), 1);
+        assert_eq!(sim.call_stack[0].call_site, 0x1000);
+        assert_eq!(sim.call_stack[0].entry, 0x1009);
+        assert_eq!(sim.call_stack[0].return_to, 0x1003);
+        sim.step();
+        assert_eq!(sim.call_stack[0].saved_at, Some(0x1021));
+
+        // OUTER saved L before calling on, so it still knows its way back
+        sim.step();
+        assert_eq!(sim.call_stack.len(), 2);
+        assert!(!sim.call_stack[0].clobbered);
+        assert_eq!(sim.run_until(sim.step_out_target()), "Stopped at 00100F");
+        assert_eq!(sim.call_stack.len(), 1);
+        assert_eq!(sim.run_until(sim.step_out_target()), "Stopped at 001003");
+        assert!(sim.call_stack.is_empty());
+
+        // step over runs the whole call
+        sim.machine.reg_pc = 0x1000;
+        assert_eq!(sim.run_until(sim.step_over_target()), "Stopped at 001003");
+        assert!(sim.call_stack.is_empty());
+        assert_eq!(sim.machine.reg_a, 1);
+
+        // LEAKY calls on without saving L and cannot return
+        sim.machine.reg_pc = 0x1006;
+        sim.step();
+        sim.step();
+        assert!(sim.call_stack[0].clobbered);
+        assert_eq!(sim.run_until(sim.step_out_target()), "Stopped at 001018");
+        sim.step();
+        assert_eq!(sim.machine.reg_pc, 0x1018);
+        assert!(sim.call_stack.is_empty());
+    }
+
+    #[test]
+    fn test_memory_navigation() {
+        assert_eq!(
+            parse_command("mem 0x1000").unwrap(),
+            DebugCommand::Memory(0x1000)
+        );
+        assert_eq!(
+            parse_command("search \"EOF\"").unwrap(),
+            DebugCommand::Search(b"EOF".to_vec())
+        );
+        assert_eq!(
+            parse_command("search C'EOF'").unwrap(),
+            DebugCommand::Search(b"EOF".to_vec())
+        );
+        assert!(parse_command("mem").is_err());
+
+        // a search goes on past its start and wraps round to the beginning
+        let mut sim = Simulator::new();
+        sim.machine.memory[0x100..0x103].copy_from_slice(b"EOF");
+        sim.machine.memory[0x2000..0x2003].copy_from_slice(b"EOF");
+        assert_eq!(sim.search_memory(b"EOF", 0), Some(0x100));
+        assert_eq!(sim.search_memory(b"EOF", 0x101), Some(0x2000));
+        assert_eq!(sim.search_memory(b"EOF", 0x2001), Some(0x100));
+        assert_eq!(sim.search_memory(b"NOPE", 0), None);
+
+        // windows are pinned, followed and closed independently
+        let mut memory = MemoryWidget::new(0x10000);
+        memory.scroll_to_address(0x100);
+        memory.pin();
+        assert_eq!(memory.views.len(), 2);
+        assert_eq!(memory.active, 1);
+        memory.cycle_follow();
+        assert_eq!(memory.views[1].follow, Follow::Pc);
+        memory.pc = 0x3000;
+        memory.follow();
+        let top = memory.views[1].top;
+        assert!(top <= 0x3000 && top.is_multiple_of(16));
+        assert_eq!(memory.views[0].top, 0xD0);
+        // words are shown six to a line, the top stays on a line boundary
+        memory.toggle_unit();
+        assert!(memory.views[1].top.is_multiple_of(18));
+        memory.close();
+        assert_eq!(memory.views.len(), 1);
+        assert_eq!(memory.active, 0);
+        memory.close();
+        assert_eq!(memory.views.len(), 1);
+
+        // only the marks on what a window shows are copied in
+        let mut machine_memory = vec![0u8; 0x100000];
+        machine_memory[0x3000] = 0xAB;
+        let since_stop: HashSet<u32> = HashSet::from([0x3000, 0xF0000]);
+        memory.cycle_follow();
+        memory.update(&machine_memory, &since_stop);
+        assert_eq!(memory.changed_since_stop, HashSet::from([0x3000]));
+
+        assert_eq!(ebcdic(0xC1), Some('A'));
+        assert_eq!(ebcdic(0xF9), Some('9'));
+        assert_eq!(ebcdic(0x00), None);
+    }
+
+    #[test]
+    fn test_trace() {
+        let _guard = ASSEMBLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let source = "PROG    START   1000
+FIRST   LDA     #5
+        STA     VAL
+        ADDR    A,X
+        JSUB    SUB
+HALT    J       HALT
+SUB     RSUB
+VAL     RESW    1
+        END     FIRST
+";
+        pass2asm(source, &AssemblerOptions::default());
+        let dir = std::env::temp_dir();
+        let csv = dir.join(format!("hexe-trace-{}.csv", std::process::id()));
+        let jsonl = dir.join(format!("hexe-trace-{}.jsonl", std::process::id()));
+
+        let mut sim = Simulator::new();
+        sim.load_program();
+        let command = parse_command(&format!("trace {}", csv.display())).unwrap();
+        sim.execute_command(&command).unwrap();
+        sim.run();
+        assert_eq!(
+            sim.execute_command(&DebugCommand::Trace(None)).unwrap(),
+            vec!["Trace stopped, 6 of 6 instructions written".to_string()]
+        );
+        assert!(sim.execute_command(&DebugCommand::Trace(None)).is_err());
+
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("step,pc,bytes,instruction,class,ea,cc,writes,a_before"));
+        assert!(lines[1].starts_with("1,001000,010005,LDA #5,load,,"));
+        // STA writes the word at VAL
+        assert!(lines[2].starts_with("2,001003,0F200B,STA 00000B,store,001011,=,001011:000005,"));
+        // an operand with a comma is quoted
+        assert!(lines[3].starts_with("3,001006,9001,\"ADDR A,X\",register,"));
+
+        // filtered by class and range, the steps still count every instruction
+        sim.reset();
+        sim.load_program();
+        let command = parse_command(&format!("trace {} FIRST..SUB jump", jsonl.display())).unwrap();
+        let DebugCommand::Trace(Some((_, filter))) = &command else {
+            panic!("expected a trace command, got {:?}", command);
+        };
+        assert_eq!(
+            filter,
+            &TraceFilter {
+                range: Some((0x1000, 0x100E)),
+                classes: vec![OpcodeClass::Jump],
+            }
+        );
+        sim.execute_command(&command).unwrap();
+        sim.run();
+        let trace = sim.stop_trace().unwrap();
+        assert_eq!((trace.written, trace.steps), (3, 6));
+        let text = std::fs::read_to_string(&jsonl).unwrap();
+        let entries: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let steps: Vec<u64> = entries
+            .iter()
+            .map(|e| e["step"].as_u64().unwrap())
+            .collect();
+        assert_eq!(steps, vec![4, 5, 6]);
+        assert_eq!(entries[0]["instruction"], "JSUB 000003");
+        assert_eq!(entries[0]["before"]["L"], "000000");
+        assert_eq!(entries[0]["after"]["L"], "00100B");
+        assert_eq!(entries[0]["cc"], "=");
+
+        assert!(parse_command("trace run.csv LOOP..").is_err());
+        assert!(parse_command("trace run.csv float").is_err());
+        assert!(parse_command("trace").is_err());
+        std::fs::remove_file(csv).unwrap();
+        std::fs::remove_file(jsonl).unwrap();
+    }
 }
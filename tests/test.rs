@@ -1,6 +1,12 @@
 #[cfg(test)]
+mod assembler_test;
+#[cfg(test)]
 mod disassembler_test;
 #[cfg(test)]
 mod loader_test;
 #[cfg(test)]
+mod lsp_test;
+#[cfg(test)]
 mod simulator_test;
+#[cfg(test)]
+mod tui_test;
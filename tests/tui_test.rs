@@ -0,0 +1,163 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use hexe::tui::Tui;
+use hexe::tui::config::Config;
+use hexe::tui::keys::{Action, Key, KeyMap};
+use hexe::tui::layout::{LayoutConfig, Pane};
+use hexe::tui::theme::Theme;
+use hexe::tui::tui::MouseAction;
+use ratatui::style::Color;
+use ratatui::{Terminal, backend::TestBackend};
+
+#[cfg(test)]
+mod tui_tests {
+    use super::*;
+
+    fn click(kind: MouseEventKind, column: u16, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn test_layout_config() {
+        let layout =
+            LayoutConfig::from_toml("left_width = 40\nhidden = [\"callstack\"]\n").unwrap();
+        assert_eq!(layout.left_width, 40);
+        assert_eq!(layout.top_height, LayoutConfig::default().top_height);
+        assert!(!layout.is_visible(Pane::CallStack));
+        assert_eq!(LayoutConfig::from_toml(&layout.to_toml()).unwrap(), layout);
+
+        // a pane cannot be squeezed away by a hand-edited file
+        assert_eq!(
+            LayoutConfig::from_toml("left_width = 100")
+                .unwrap()
+                .left_width,
+            90
+        );
+        assert!(LayoutConfig::from_toml("hidden = [\"nothing\"]").is_err());
+
+        let mut layout = LayoutConfig::default();
+        assert!(layout.set_visible(Pane::Controls, false).is_err());
+        layout.set_visible(Pane::Memory, false).unwrap();
+        layout.set_visible(Pane::Memory, false).unwrap();
+        assert_eq!(layout.hidden, vec![Pane::Memory]);
+        layout.set_visible(Pane::Memory, true).unwrap();
+        assert!(layout.hidden.is_empty());
+        assert_eq!(Pane::from_name("Tables"), Some(Pane::Tables));
+    }
+
+    #[test]
+    fn test_mouse() {
+        let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+        let mut tui = Tui::new();
+        tui.update_disassembly(vec![
+            (0x1000, "LDA ALPHA".to_string(), ">".to_string()),
+            (0x1003, "STA BETA".to_string(), " ".to_string()),
+        ]);
+        terminal.draw(|f| tui.draw(f)).unwrap();
+
+        // the controls sit below the 15 rows of registers, in the left 30 columns
+        assert_eq!(
+            tui.mouse(click(MouseEventKind::Down(MouseButton::Left), 12, 16)),
+            MouseAction::Button(1)
+        );
+        // the disassembly starts below them, inside its border
+        assert_eq!(
+            tui.mouse(click(MouseEventKind::Down(MouseButton::Left), 5, 20)),
+            MouseAction::Breakpoint(0x1003)
+        );
+
+        // dragging the divider between the columns widens the left one
+        tui.mouse(click(MouseEventKind::Down(MouseButton::Left), 30, 5));
+        tui.mouse(click(MouseEventKind::Drag(MouseButton::Left), 50, 5));
+        tui.mouse(click(MouseEventKind::Up(MouseButton::Left), 50, 5));
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        assert_eq!(
+            tui.mouse(click(MouseEventKind::Down(MouseButton::Left), 45, 16)),
+            MouseAction::Button(2)
+        );
+
+        assert!(tui.set_pane_visible("disassembly", false).is_ok());
+        assert!(tui.set_pane_visible("nothing", false).is_err());
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        assert_eq!(
+            tui.mouse(click(MouseEventKind::Down(MouseButton::Left), 5, 20)),
+            MouseAction::None
+        );
+
+        // a terminal wider than 655 columns does not overflow the split
+        let mut terminal = Terminal::new(TestBackend::new(2500, 30)).unwrap();
+        let mut tui = Tui::new();
+        terminal.draw(|f| tui.draw(f)).unwrap();
+        assert_eq!(
+            tui.mouse(click(MouseEventKind::Down(MouseButton::Left), 12, 16)),
+            MouseAction::Button(0)
+        );
+    }
+
+    #[test]
+    fn test_themes() {
+        assert_eq!(Config::from_toml("").unwrap().theme, Theme::dark());
+        assert_eq!(
+            Config::from_toml("theme = \"light\"").unwrap().theme,
+            Theme::light()
+        );
+        let config = Config::from_toml(
+            "theme = \"mine\"\n[themes.mine]\nbase = \"high-contrast\"\naccent = \"#ff8800\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.theme.accent, Color::Rgb(0xff, 0x88, 0x00));
+        assert_eq!(config.theme.pc, Theme::high_contrast().pc);
+
+        assert!(Config::from_toml("theme = \"sepia\"").is_err());
+        assert!(Config::from_toml("theme = \"x\"\n[themes.x]\naccent = \"#nope\"").is_err());
+        assert!(Config::from_toml("theme = \"x\"\n[themes.x]\naccnet = \"red\"").is_err());
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let press = |code, modifiers| KeyEvent::new(code, modifiers);
+        let keys = KeyMap::default();
+        assert_eq!(
+            keys.action(&press(KeyCode::Char('s'), KeyModifiers::NONE)),
+            Some(Action::Step)
+        );
+        assert_eq!(
+            keys.action(&press(KeyCode::Char('F'), KeyModifiers::SHIFT)),
+            Some(Action::Follow)
+        );
+
+        let config =
+            Config::from_toml("[keys]\nstep = \"F10\"\nquit = [\"Ctrl+q\", \"s\"]\n").unwrap();
+        let keys = &config.keys;
+        assert_eq!(
+            keys.action(&press(KeyCode::F(10), KeyModifiers::NONE)),
+            Some(Action::Step)
+        );
+        // a key bound to another action is taken away from the one it had
+        assert_eq!(
+            keys.action(&press(KeyCode::Char('s'), KeyModifiers::NONE)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            keys.action(&press(KeyCode::Char('q'), KeyModifiers::CONTROL)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            keys.action(&press(KeyCode::Char('q'), KeyModifiers::NONE)),
+            None
+        );
+
+        // the status bar shows the keys as they are bound
+        let bar = keys.status_bar();
+        assert_eq!(bar[0], ("Ctrl+q".to_string(), "quit"));
+        assert_eq!(bar[1], ("F10".to_string(), "step"));
+        assert_eq!(bar[4], ("+ -".to_string(), "speed"));
+
+        assert!(Config::from_toml("[keys]\nfly = \"x\"").is_err());
+        assert!(Key::parse("Hyper+x").is_err());
+    }
+}